@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rust_calculator::{compile, Environment, Program, Registry, Value, Vm};
+use std::hint::black_box;
+
+/// Evaluate `a*x^2 + b*x + c` for 1000 values of `x`, walking the postfix tokens with `eval`
+/// against running the compiled bytecode.
+fn quadratic(c: &mut Criterion) {
+    let postfix = compile("a*x^2 + b*x + c", &Registry::default()).unwrap();
+    let mut env = Environment::default();
+    env.set("a", Value::Float(1.5));
+    env.set("b", Value::Float(-2.0));
+    env.set("c", Value::Float(0.25));
+    let xs: Vec<_> = (0..1000)
+        .map(|i| Value::Float(f64::from(i) / 100.0))
+        .collect();
+    let program = Program::compile(&postfix, &env);
+    let x = program.slot("x").unwrap();
+    let mut slots = program.load(&env);
+    let mut vm = Vm::default();
+
+    let mut group = c.benchmark_group("quadratic");
+    group.bench_function("eval", |bench| {
+        bench.iter(|| {
+            for value in &xs {
+                env.set("x", value.clone());
+                black_box(postfix.eval(&env).unwrap());
+            }
+        });
+    });
+    group.bench_function("bytecode", |bench| {
+        bench.iter(|| {
+            for value in &xs {
+                slots[x] = Some(value.clone());
+                black_box(vm.run(&program, &env, &slots).unwrap());
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, quadratic);
+criterion_main!(benches);
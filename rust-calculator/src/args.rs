@@ -1,13 +1,244 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_calculator::plot::{Style, SIZES};
+use rust_calculator::solve::Method;
+use rust_calculator::value::{Base, Value, Width};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cli {
     #[arg(long, short, action = clap::ArgAction::Count)]
     pub debug: u8,
 
+    /// Start an interactive session, the default when no expression is given.
+    #[arg(long)]
+    pub repl: bool,
+
+    /// Define a variable before evaluating, e.g. `--var x=3`. Can be repeated.
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, Value)>,
+
+    /// Calculate with exact fractions and integers of any size, e.g. `1/3 + 1/6` is `1/2`.
+    #[arg(long)]
+    pub exact: bool,
+
+    /// Calculate with integers of a fixed size, results that don't fit are an error.
+    #[arg(long, value_enum, value_name = "TYPE", conflicts_with = "exact")]
+    pub width: Option<Width>,
+
+    /// With `--width`, wrap around on overflow instead of reporting an error.
+    #[arg(long, requires = "width")]
+    pub wrapping: bool,
+
+    /// With `--exact`, also show fractions as decimals with N digits after the point.
+    #[arg(long, value_name = "N", requires = "exact")]
+    pub digits: Option<usize>,
+
+    /// Print complex results in polar form, `r * e^(θi)`, instead of `a + bi`.
+    #[arg(long)]
+    pub polar: bool,
+
+    /// Print whole number results in another base.
+    #[arg(long, value_enum)]
+    pub base: Option<Base>,
+
+    /// With `--base`, print negative numbers as two's complement in the bits of `--width`, or 64.
+    #[arg(long, requires = "base")]
+    pub twos_complement: bool,
+
+    /// Exit with status 1 when the result is zero and 0 otherwise, errors exit with 2.
+    #[arg(long, conflicts_with = "repl")]
+    pub exit_status: bool,
+
+    /// Evaluate each line of a file in order, `-` reads stdin. Can be repeated.
+    #[arg(
+        long = "file",
+        short,
+        value_name = "PATH",
+        conflicts_with_all = ["math_expression", "repl", "exit_status"]
+    )]
+    pub files: Vec<PathBuf>,
+
+    /// When reading lines from files or stdin, report failing lines and carry on instead of stopping.
+    #[arg(long, conflicts_with_all = ["math_expression", "repl", "exit_status"])]
+    pub keep_going: bool,
+
+    /// Read expressions in reverse Polish notation, e.g. `3 4 + 2 *`.
+    #[arg(long)]
+    pub rpn: bool,
+
+    /// Print how the expression was understood before its result.
+    #[arg(long, value_enum, value_name = "NOTATION")]
+    pub show: Option<Notation>,
+
+    /// Print the expression in the given notation instead of evaluating it.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "NOTATION",
+        requires = "math_expression",
+        conflicts_with_all = ["show", "exit_status", "repl"]
+    )]
+    pub emit: Option<Notation>,
+
+    /// Print each step of converting the expression to postfix and of evaluating it.
+    #[arg(
+        long,
+        requires = "math_expression",
+        conflicts_with_all = ["rpn", "emit", "show", "repl"]
+    )]
+    pub explain: bool,
+
     /// Infix calculation to compute.
-    pub math_expression: String,
+    #[arg(allow_hyphen_values = true)]
+    pub math_expression: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// work on an expression symbolically instead of evaluating it, clap reads a doc comment here as the about text of `oxc`
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Differentiate an expression, e.g. `oxc diff "x^3 + 2*x" x` prints `3*x^2 + 2`.
+    Diff {
+        /// Expression to differentiate.
+        #[arg(allow_hyphen_values = true)]
+        expression: String,
+        /// Variable to differentiate with respect to, others are constants.
+        #[arg(value_parser = parse_name)]
+        variable: String,
+    },
+    /// Fold constants and apply identities such as `x*1 = x`, `x+0 = x` and `x-x = 0`.
+    Simplify {
+        /// Expression to simplify.
+        #[arg(allow_hyphen_values = true)]
+        expression: String,
+    },
+    /// Find real roots of an equation, e.g. `oxc solve "x^2 - 2 = 0"`.
+    Solve {
+        /// Equation to solve, like `x^2 = 2` or `cos(x) = x`. Without `=` it is solved for zero.
+        #[arg(allow_hyphen_values = true)]
+        equation: String,
+        /// Unknown to solve for.
+        #[arg(default_value = "x", value_parser = parse_name)]
+        variable: String,
+        /// How to find the roots.
+        #[arg(long, value_enum, default_value_t)]
+        method: Method,
+        /// Find every root between A and B where the sign changes, -10 to 10 without this or `--guess`.
+        #[arg(
+            long,
+            value_name = "A,B",
+            value_parser = parse_interval,
+            allow_hyphen_values = true,
+            conflicts_with = "guess"
+        )]
+        interval: Option<(f64, f64)>,
+        /// Find one root starting near X.
+        #[arg(long, value_name = "X", allow_negative_numbers = true)]
+        guess: Option<f64>,
+    },
+    /// Draw a chart of expressions in the terminal, e.g. `oxc plot "sin(x) * x" --from -10 --to 10`.
+    Plot {
+        /// Expression to draw, it may start with `-` like `-x^2`.
+        #[arg(allow_hyphen_values = true)]
+        expression: String,
+        /// More expressions to draw on the same axes.
+        expressions: Vec<String>,
+        /// Variable along the x axis.
+        #[arg(long, default_value = "x", value_parser = parse_name)]
+        variable: String,
+        /// Left end of the x axis.
+        #[arg(long, default_value_t = -10.0, allow_negative_numbers = true)]
+        from: f64,
+        /// Right end of the x axis.
+        #[arg(long, default_value_t = 10.0, allow_negative_numbers = true)]
+        to: f64,
+        /// Width of the chart in characters, without the axis labels.
+        #[arg(long, default_value_t = 72, value_parser = parse_size)]
+        columns: usize,
+        /// Height of the chart in characters, without the axis labels.
+        #[arg(long, default_value_t = 20, value_parser = parse_size)]
+        rows: usize,
+        /// Characters to draw with.
+        #[arg(long, value_enum, default_value_t)]
+        style: Style,
+    },
+}
+
+/// Ways to print a parsed expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Notation {
+    /// Expression tree, one node per line
+    Ast,
+    /// Infix with only the parentheses that are needed
+    Infix,
+    /// Infix with every operation parenthesized
+    Parens,
+    /// Polish notation, operators before their operands
+    Prefix,
+    /// Reverse Polish notation, as evaluated
+    Postfix,
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse_name(s: &str) -> Result<String, String> {
+    if is_identifier(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("`{s}` is not a valid variable name"))
+    }
+}
+
+#[allow(clippy::float_cmp)]
+fn parse_interval(s: &str) -> Result<(f64, f64), String> {
+    let (a, b) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected A,B, got `{s}`"))?;
+    let bound = |n: &str| {
+        n.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite())
+            .ok_or_else(|| format!("`{}` is not a number", n.trim()))
+    };
+    let (a, b) = (bound(a)?, bound(b)?);
+    if a == b {
+        return Err("the interval is empty".to_string());
+    }
+    Ok((a, b))
+}
+
+fn parse_size(s: &str) -> Result<usize, String> {
+    s.parse::<usize>()
+        .ok()
+        .filter(|n| SIZES.contains(n))
+        .ok_or_else(|| {
+            format!(
+                "expected a size from {} to {}, got `{s}`",
+                SIZES.start(),
+                SIZES.end()
+            )
+        })
+}
+
+fn parse_var(s: &str) -> Result<(String, Value), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{s}`"))?;
+    let name = name.trim();
+    if !is_identifier(name) {
+        return Err(format!("`{name}` is not a valid variable name"));
+    }
+    let value = value.trim().parse::<Value>().map_err(|e| e.to_string())?;
+    Ok((name.to_string(), value))
 }
 
 #[cfg(test)]
@@ -20,4 +251,171 @@ mod tests {
     fn test_cli() {
         Cli::command().debug_assert();
     }
+
+    #[test]
+    fn test_optional_expression() {
+        let args = Cli::parse_from(["oxc"]);
+        assert!(args.math_expression.is_none());
+        assert!(!args.repl);
+        let args = Cli::parse_from(["oxc", "3 + 4"]);
+        assert_eq!(args.math_expression.as_deref(), Some("3 + 4"));
+        let args = Cli::parse_from(["oxc", "-2^2"]);
+        assert_eq!(args.math_expression.as_deref(), Some("-2^2"));
+        let args = Cli::parse_from(["oxc", "--exact", "-3 + 4"]);
+        assert_eq!(args.math_expression.as_deref(), Some("-3 + 4"));
+    }
+
+    #[test]
+    fn test_vars() {
+        let args = Cli::parse_from(["oxc", "--var", "x=3", "--var", "rate = 0.5", "x"]);
+        assert_eq!(
+            args.vars,
+            vec![
+                ("x".to_string(), Value::Int(3)),
+                ("rate".to_string(), Value::Float(0.5))
+            ]
+        );
+        assert!(Cli::try_parse_from(["oxc", "--var", "x", "x"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--var", "1x=2", "x"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--var", "x=y", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_exact() {
+        let args = Cli::parse_from(["oxc", "--exact", "--digits", "5", "1/3"]);
+        assert!(args.exact);
+        assert_eq!(args.digits, Some(5));
+        assert!(Cli::try_parse_from(["oxc", "--digits", "5", "1/3"]).is_err());
+    }
+
+    #[test]
+    fn test_polar() {
+        assert!(Cli::parse_from(["oxc", "--polar", "1 + i"]).polar);
+        assert!(!Cli::parse_from(["oxc", "1 + i"]).polar);
+    }
+
+    #[test]
+    fn test_programmer() {
+        let args = Cli::parse_from(["oxc", "--width", "u16", "--wrapping", "--base", "hex", "~0"]);
+        assert_eq!(args.width, Some(Width::U16));
+        assert!(args.wrapping);
+        assert_eq!(args.base, Some(Base::Hex));
+        assert!(Cli::try_parse_from(["oxc", "--wrapping", "1"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--twos-complement", "1"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--width", "i8", "--exact", "1"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--width", "i128", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert!(Cli::parse_from(["oxc", "--exit-status", "x > 3"]).exit_status);
+        assert!(Cli::try_parse_from(["oxc", "--exit-status", "--repl"]).is_err());
+    }
+
+    #[test]
+    fn test_files() {
+        let args = Cli::parse_from(["oxc", "-f", "a.txt", "--file", "-", "--keep-going"]);
+        assert_eq!(args.files, vec![PathBuf::from("a.txt"), PathBuf::from("-")]);
+        assert!(args.keep_going);
+        assert!(Cli::try_parse_from(["oxc", "-f", "a.txt", "1 + 2"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--keep-going", "--repl"]).is_err());
+    }
+
+    #[test]
+    fn test_rpn() {
+        let args = Cli::parse_from(["oxc", "--rpn", "--emit", "infix", "3 4 + 2 *"]);
+        assert!(args.rpn);
+        assert_eq!(args.emit, Some(Notation::Infix));
+        assert!(Cli::try_parse_from(["oxc", "--emit", "postfix"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--emit", "ast", "--show", "ast", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        assert!(Cli::parse_from(["oxc", "--explain", "1 + 2"]).explain);
+        assert!(Cli::try_parse_from(["oxc", "--explain"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "--explain", "--rpn", "1 2 +"]).is_err());
+    }
+
+    #[test]
+    fn test_show() {
+        let args = Cli::parse_from(["oxc", "--show", "prefix", "1 + 2"]);
+        assert_eq!(args.show, Some(Notation::Prefix));
+        assert!(Cli::parse_from(["oxc", "1"]).show.is_none());
+        assert!(Cli::try_parse_from(["oxc", "--show", "lisp", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_symbolic() {
+        let args = Cli::parse_from(["oxc", "diff", "x^3 + 2*x", "x"]);
+        assert!(args.math_expression.is_none());
+        assert!(matches!(
+            args.command,
+            Some(Command::Diff { expression, variable }) if expression == "x^3 + 2*x" && variable == "x"
+        ));
+        let args = Cli::parse_from(["oxc", "simplify", "x*1 + 0"]);
+        assert!(matches!(args.command, Some(Command::Simplify { .. })));
+        let args = Cli::parse_from(["oxc", "diff", "-x^2", "x"]);
+        assert!(
+            matches!(args.command, Some(Command::Diff { expression, .. }) if expression == "-x^2")
+        );
+        assert!(Cli::parse_from(["oxc", "1 + 2"]).command.is_none());
+        assert!(Cli::try_parse_from(["oxc", "diff", "x^2", "2x"]).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_solve() {
+        let args = Cli::parse_from(["oxc", "solve", "x^2 = 2", "--interval", "-2,0"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Solve { variable, method: Method::Brent, interval: Some((a, b)), guess: None, .. })
+                if variable == "x" && a == -2.0 && b == 0.0
+        ));
+        let args = Cli::parse_from([
+            "oxc", "solve", "t^2 = 2", "t", "--method", "newton", "--guess", "-1",
+        ]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Solve { method: Method::Newton, guess: Some(g), .. }) if g == -1.0
+        ));
+        assert!(Cli::try_parse_from(["oxc", "solve", "x", "--interval", "1"]).is_err());
+        let args = Cli::parse_from(["oxc", "solve", "-x + 2", "--guess", "1"]);
+        assert!(
+            matches!(args.command, Some(Command::Solve { equation, .. }) if equation == "-x + 2")
+        );
+        assert!(Cli::try_parse_from(["oxc", "solve", "x", "--interval", "1,1"]).is_err());
+        assert!(
+            Cli::try_parse_from(["oxc", "solve", "x", "--interval", "0,1", "--guess", "1"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_plot() {
+        let args = Cli::parse_from(["oxc", "plot", "sin(x) * x", "cos(x)", "--from", "-5"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Plot { expressions, from, to, style: Style::Braille, .. })
+                if expressions.len() == 1 && from == -5.0 && to == 10.0
+        ));
+        let args = Cli::parse_from(["oxc", "plot", "-x", "--from", "-5", "x^2", "--to", "3"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Plot { expression, expressions, from, to, .. })
+                if expression == "-x" && expressions == ["x^2"] && from == -5.0 && to == 3.0
+        ));
+        let args = Cli::parse_from(["oxc", "plot", "--from", "-5", "-x"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Plot { expression, from, .. }) if expression == "-x" && from == -5.0
+        ));
+        assert!(Cli::try_parse_from(["oxc", "plot"]).is_err());
+        assert!(Cli::try_parse_from(["oxc", "plot", "x", "--style", "dots"]).is_err());
+        for size in ["0", "1", "70000"] {
+            assert!(Cli::try_parse_from(["oxc", "plot", "x", "--columns", size]).is_err());
+            assert!(Cli::try_parse_from(["oxc", "plot", "x", "--rows", size]).is_err());
+        }
+    }
 }
@@ -0,0 +1,439 @@
+use crate::postfix::{PostExpression, PostExpressionError};
+use crate::registry;
+use crate::span::Spanned;
+use crate::tokens::{Associativity, MathToken, OperatorType};
+use crate::value::Value;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::fmt::Write;
+
+/// Expression tree rebuilt from the postfix form, used to show how an input was understood.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ast {
+    Number(Value),
+    Variable(String),
+    Unary {
+        op: OperatorType,
+        operand: Box<Self>,
+    },
+    Binary {
+        op: OperatorType,
+        lhs: Box<Self>,
+        rhs: Box<Self>,
+    },
+    /// A call, or a series like `sum(i, 1, n, i^2)` whose first argument is its variable.
+    Call {
+        name: String,
+        args: Vec<Self>,
+    },
+    /// `[a, b, c]`
+    List(Vec<Self>),
+    /// `cond ? then : otherwise`
+    Conditional {
+        cond: Box<Self>,
+        then: Box<Self>,
+        otherwise: Box<Self>,
+    },
+}
+
+impl TryFrom<&PostExpression> for Ast {
+    type Error = Spanned<PostExpressionError>;
+
+    fn try_from(postfix: &PostExpression) -> Result<Self, Self::Error> {
+        let invalid = |msg: &str, idx| {
+            Spanned::new(
+                PostExpressionError::InvalidExpression(msg.to_string()),
+                postfix.span(idx),
+            )
+        };
+        let mut stack: Vec<Self> = Vec::new();
+        for (idx, tok) in postfix.tokens().iter().enumerate() {
+            let missing = || invalid("Operator is missing an operand", idx);
+            let node = match tok {
+                MathToken::IntOperand(i) => Self::Number(Value::Int(*i)),
+                MathToken::FloatOperand(x) => Self::Number(Value::Float(*x)),
+                MathToken::ImaginaryOperand(x) => {
+                    Self::Number(Value::Complex(Complex64::new(0.0, *x)))
+                }
+                MathToken::BigIntOperand(n) => {
+                    Self::Number(Value::Rational(BigRational::from_integer(n.clone())))
+                }
+                MathToken::Identifier(name) | MathToken::Unit(name) => Self::Variable(name.clone()),
+                // short-circuiting only changes the order of evaluation, not the tree
+                MathToken::Skip(..) => continue,
+                MathToken::Operator(OperatorType::Cond) => {
+                    let otherwise = stack.pop().ok_or_else(missing)?;
+                    let then = stack.pop().ok_or_else(missing)?;
+                    let cond = stack.pop().ok_or_else(missing)?;
+                    Self::Conditional {
+                        cond: Box::new(cond),
+                        then: Box::new(then),
+                        otherwise: Box::new(otherwise),
+                    }
+                }
+                MathToken::Operator(op) if op.is_unary() => Self::Unary {
+                    op: op.clone(),
+                    operand: Box::new(stack.pop().ok_or_else(missing)?),
+                },
+                MathToken::Operator(op) => {
+                    let rhs = stack.pop().ok_or_else(missing)?;
+                    let lhs = stack.pop().ok_or_else(missing)?;
+                    Self::Binary {
+                        op: op.clone(),
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    }
+                }
+                MathToken::Call(name, argc) => {
+                    let first_arg = stack.len().checked_sub(*argc).ok_or_else(missing)?;
+                    Self::Call {
+                        name: name.clone(),
+                        args: stack.split_off(first_arg),
+                    }
+                }
+                MathToken::List(len) => {
+                    let first = stack.len().checked_sub(*len).ok_or_else(missing)?;
+                    Self::List(stack.split_off(first))
+                }
+                MathToken::Series(series) => {
+                    let to = stack.pop().ok_or_else(missing)?;
+                    let from = stack.pop().ok_or_else(missing)?;
+                    let var = Self::Variable(series.var.clone());
+                    Self::Call {
+                        name: series.name().to_string(),
+                        args: vec![var, from, to, Self::try_from(&series.body)?],
+                    }
+                }
+                MathToken::Parens(_)
+                | MathToken::Brackets(_)
+                | MathToken::Assign
+                | MathToken::Function(_)
+                | MathToken::Comma
+                | MathToken::Question
+                | MathToken::Colon => unreachable!("Token {tok:?} never appears in postfix"),
+            };
+            stack.push(node);
+        }
+        let last = postfix.tokens().len().saturating_sub(1);
+        match (stack.pop(), stack.is_empty()) {
+            (Some(root), true) => Ok(root),
+            (None, _) => Err(invalid("Operator is missing an operand", last)),
+            (Some(_), false) => Err(invalid(
+                "Expression has unused values, is an operator missing?",
+                last,
+            )),
+        }
+    }
+}
+
+impl Ast {
+    /// Precedence of the node when it appears as an operand, atoms bind tightest.
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Unary { op, .. } | Self::Binary { op, .. } => op.precedence(),
+            Self::Conditional { .. } => OperatorType::Cond.precedence(),
+            // a fraction is written as a quotient, e.g. `x^(1/2)`
+            Self::Number(Value::Rational(r)) if !r.is_integer() => OperatorType::Div.precedence(),
+            // a negative number needs the same parentheses as a unary minus
+            Self::Number(v) if v.as_float().is_sign_negative() => OperatorType::Neg.precedence(),
+            Self::Number(_) | Self::Variable(_) | Self::Call { .. } | Self::List(_) => u8::MAX,
+        }
+    }
+
+    /// Prefix operators named by a word, e.g. a registered `not`, need a space before their operand.
+    fn word_gap(op: &OperatorType) -> &'static str {
+        if registry::is_word(&op.to_string()) {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn join_args(args: &[Self], render: fn(&Self) -> String) -> String {
+        args.iter().map(render).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Canonical infix with only the parentheses needed to keep the meaning.
+    #[must_use]
+    pub fn to_infix(&self) -> String {
+        self.infix(false)
+    }
+
+    /// Infix like [`Ast::to_infix`], but products, quotients and powers are written
+    /// without spaces, e.g. `3*x^2 + 2`.
+    #[must_use]
+    pub fn to_compact(&self) -> String {
+        self.infix(true)
+    }
+
+    fn infix(&self, compact: bool) -> String {
+        let wrap = |child: &Self, needs_parens: bool| {
+            if needs_parens {
+                format!("({})", child.infix(compact))
+            } else {
+                child.infix(compact)
+            }
+        };
+        match self {
+            Self::Number(v) => v.to_string(),
+            Self::Variable(name) => name.clone(),
+            Self::Unary { op, operand } => {
+                format!(
+                    "{op}{}{}",
+                    Self::word_gap(op),
+                    wrap(operand, operand.precedence() < op.precedence())
+                )
+            }
+            Self::Binary { op, lhs, rhs } => {
+                let prec = op.precedence();
+                let left_assoc = op.associativity() == Associativity::Left;
+                let lhs_parens =
+                    lhs.precedence() < prec || (lhs.precedence() == prec && !left_assoc);
+                let rhs_parens =
+                    rhs.precedence() < prec || (rhs.precedence() == prec && left_assoc);
+                let tight = compact
+                    && prec >= OperatorType::Mul.precedence()
+                    && !registry::is_word(&op.to_string());
+                let gap = if tight { "" } else { " " };
+                format!(
+                    "{}{gap}{op}{gap}{}",
+                    wrap(lhs, lhs_parens),
+                    wrap(rhs, rhs_parens)
+                )
+            }
+            Self::Call { name, args } => {
+                let args: Vec<_> = args.iter().map(|arg| arg.infix(compact)).collect();
+                format!("{name}({})", args.join(", "))
+            }
+            Self::List(items) => {
+                let items: Vec<_> = items.iter().map(|item| item.infix(compact)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            // `?` and `:` delimit the middle operand, and `?:` groups from the right
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => format!(
+                "{} ? {} : {}",
+                wrap(cond, cond.precedence() <= self.precedence()),
+                then.infix(compact),
+                otherwise.infix(compact)
+            ),
+        }
+    }
+
+    /// Infix with every operation wrapped in parentheses.
+    pub fn to_parenthesized(&self) -> String {
+        match self {
+            Self::Number(v) => v.to_string(),
+            Self::Variable(name) => name.clone(),
+            Self::Unary { op, operand } => {
+                format!("({op}{}{})", Self::word_gap(op), operand.to_parenthesized())
+            }
+            Self::Binary { op, lhs, rhs } => {
+                format!(
+                    "({} {op} {})",
+                    lhs.to_parenthesized(),
+                    rhs.to_parenthesized()
+                )
+            }
+            Self::Call { name, args } => {
+                format!("{name}({})", Self::join_args(args, Self::to_parenthesized))
+            }
+            Self::List(items) => format!("[{}]", Self::join_args(items, Self::to_parenthesized)),
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => format!(
+                "({} ? {} : {})",
+                cond.to_parenthesized(),
+                then.to_parenthesized(),
+                otherwise.to_parenthesized()
+            ),
+        }
+    }
+
+    /// Polish notation, operators before their operands.
+    /// Unary signs are spelled `neg`/`pos` and calls reuse the postfix spelling, e.g. `max(3)`.
+    #[must_use]
+    pub fn to_prefix(&self) -> String {
+        match self {
+            Self::Number(v) => v.to_string(),
+            Self::Variable(name) => name.clone(),
+            Self::Unary { op, operand } => {
+                format!(
+                    "{} {}",
+                    MathToken::Operator(op.clone()),
+                    operand.to_prefix()
+                )
+            }
+            Self::Binary { op, lhs, rhs } => {
+                format!("{op} {} {}", lhs.to_prefix(), rhs.to_prefix())
+            }
+            Self::Call { args, .. } | Self::List(args) => {
+                let mut out = match self {
+                    Self::Call { name, .. } => MathToken::Call(name.clone(), args.len()),
+                    _ => MathToken::List(args.len()),
+                }
+                .to_string();
+                for arg in args {
+                    write!(out, " {}", arg.to_prefix()).expect("Writing to a String can't fail");
+                }
+                out
+            }
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => format!(
+                "{} {} {} {}",
+                OperatorType::Cond,
+                cond.to_prefix(),
+                then.to_prefix(),
+                otherwise.to_prefix()
+            ),
+        }
+    }
+
+    /// Label of this node when drawn as a tree.
+    fn label(&self) -> String {
+        match self {
+            Self::Number(v) => v.to_string(),
+            Self::Variable(name) => name.clone(),
+            Self::Unary { op, .. } => MathToken::Operator(op.clone()).to_string(),
+            Self::Binary { op, .. } => op.to_string(),
+            Self::Call { name, .. } => format!("{name}()"),
+            Self::List(_) => "[]".to_string(),
+            Self::Conditional { .. } => OperatorType::Cond.to_string(),
+        }
+    }
+
+    fn children(&self) -> Vec<&Self> {
+        match self {
+            Self::Number(_) | Self::Variable(_) => vec![],
+            Self::Unary { operand, .. } => vec![operand],
+            Self::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::Call { args, .. } | Self::List(args) => args.iter().collect(),
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => vec![cond, then, otherwise],
+        }
+    }
+
+    /// Draw the tree with box drawing characters, one node per line.
+    #[must_use]
+    pub fn to_tree(&self) -> String {
+        fn draw(node: &Ast, prefix: &str, out: &mut String) {
+            let children = node.children();
+            for (idx, child) in children.iter().enumerate() {
+                let last = idx + 1 == children.len();
+                let (branch, indent) = if last {
+                    ("└── ", "    ")
+                } else {
+                    ("├── ", "│   ")
+                };
+                writeln!(out, "{prefix}{branch}{}", child.label())
+                    .expect("Writing to a String can't fail");
+                draw(child, &format!("{prefix}{indent}"), out);
+            }
+        }
+        let mut out = format!("{}\n", self.label());
+        draw(self, "", &mut out);
+        out.truncate(out.trim_end().len());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::Expression;
+
+    fn ast(input: &str) -> Ast {
+        let postfix = PostExpression::try_from(input.parse::<Expression>().unwrap()).unwrap();
+        Ast::try_from(&postfix).unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        assert_eq!(
+            ast("-x + 2"),
+            Ast::Binary {
+                op: OperatorType::Add,
+                lhs: Box::new(Ast::Unary {
+                    op: OperatorType::Neg,
+                    operand: Box::new(Ast::Variable("x".into())),
+                }),
+                rhs: Box::new(Ast::Number(Value::Int(2))),
+            }
+        );
+    }
+    #[test]
+    fn test_minimal_parens() {
+        assert_eq!(ast("((1 + 2)) * 3").to_infix(), "(1 + 2) * 3");
+        assert_eq!(ast("1 + (2 * 3)").to_infix(), "1 + 2 * 3");
+        assert_eq!(ast("(1 - 2) - 3").to_infix(), "1 - 2 - 3");
+        assert_eq!(ast("1 - (2 - 3)").to_infix(), "1 - (2 - 3)");
+        assert_eq!(ast("2 ^ (3 ^ 2)").to_infix(), "2 ^ 3 ^ 2");
+        assert_eq!(ast("(2 ^ 3) ^ 2").to_infix(), "(2 ^ 3) ^ 2");
+        assert_eq!(ast("-(2 ^ 2)").to_infix(), "-2 ^ 2");
+        assert_eq!(ast("(-2) ^ 2").to_infix(), "(-2) ^ 2");
+        assert_eq!(ast("-(1 + x)").to_infix(), "-(1 + x)");
+        assert_eq!(ast("max(1, (2 + 3))").to_infix(), "max(1, 2 + 3)");
+    }
+    #[test]
+    fn test_compact() {
+        assert_eq!(ast("3 * x ^ 2 + 2").to_compact(), "3*x^2 + 2");
+        assert_eq!(ast("(a + b) / -c << 1").to_compact(), "(a + b)/-c << 1");
+    }
+    #[test]
+    fn test_parenthesized() {
+        assert_eq!(ast("1 + 2 * -3").to_parenthesized(), "(1 + (2 * (-3)))");
+        assert_eq!(ast("sqrt(x ^ 2)").to_parenthesized(), "sqrt((x ^ 2))");
+    }
+    #[test]
+    fn test_prefix() {
+        assert_eq!(ast("3 + 4 * 2").to_prefix(), "+ 3 * 4 2");
+        assert_eq!(ast("-(3 - 4)").to_prefix(), "neg - 3 4");
+        assert_eq!(
+            ast("max(1, 2) + sqrt(4)").to_prefix(),
+            "+ max(2) 1 2 sqrt 4"
+        );
+    }
+    #[test]
+    fn test_tree() {
+        assert_eq!(
+            ast("(3 + 4) * 2").to_tree(),
+            "*\n├── +\n│   ├── 3\n│   └── 4\n└── 2"
+        );
+    }
+    #[test]
+    fn test_lists_and_series() {
+        assert_eq!(ast("[1, 2 + x] * 2").to_infix(), "[1, 2 + x] * 2");
+        assert_eq!(ast("1..n - 1").to_parenthesized(), "(1 .. (n - 1))");
+        assert_eq!(ast("[a, []]").to_prefix(), "list(2) a list(0)");
+        assert_eq!(ast("sum(i, 1, n, i^2)").to_infix(), "sum(i, 1, n, i ^ 2)");
+        assert_eq!(ast("[x]").to_tree(), "[]\n└── x");
+    }
+    #[test]
+    fn test_conditional() {
+        assert_eq!(ast("(a ? b : c) ? d : e").to_infix(), "(a ? b : c) ? d : e");
+        assert_eq!(ast("a ? b : (c ? d : e)").to_infix(), "a ? b : c ? d : e");
+        assert_eq!(ast("(a ? b : c) + 1").to_infix(), "(a ? b : c) + 1");
+        assert_eq!(ast("x > 0 && y ? 1 : 2").to_prefix(), "?: && > x 0 y 1 2");
+        assert_eq!(ast("x ? 1 : 2").to_tree(), "?:\n├── x\n├── 1\n└── 2");
+    }
+    #[test]
+    fn test_negative_number_parens() {
+        let tree = Ast::Binary {
+            op: OperatorType::Pow,
+            lhs: Box::new(Ast::Number(Value::Int(-2))),
+            rhs: Box::new(Ast::Number(Value::Int(2))),
+        };
+        assert_eq!(tree.to_infix(), "(-2) ^ 2");
+    }
+}
@@ -0,0 +1,122 @@
+use crate::repl::{Action, Session};
+use log::debug;
+use std::io::{self, BufRead, Write};
+
+/// Evaluates newline separated lines from files or stdin in order.
+/// Variables, functions and `ans` carry over from one line, and one input, to the next.
+#[derive(Debug)]
+pub struct Batch {
+    session: Session,
+    keep_going: bool,
+    failures: usize,
+}
+
+/// The part of a line before its `#` comment.
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(code, _)| code)
+}
+
+impl Batch {
+    /// Stop at the first failing line, unless `keep_going` is set.
+    pub const fn new(session: Session, keep_going: bool) -> Self {
+        Self {
+            session,
+            keep_going,
+            failures: 0,
+        }
+    }
+
+    /// Number of lines that failed so far.
+    pub const fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Evaluate every line of `input`, printing one result per line to `out`.
+    /// Errors go to `err`, prefixed with `name` and the line number.
+    /// Returns `false` when evaluation stopped early, later inputs should be skipped.
+    pub fn run(
+        &mut self,
+        name: &str,
+        input: impl BufRead,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<bool> {
+        for (idx, line) in input.lines().enumerate() {
+            let line = line?;
+            debug!("{}:{}: {}", name, idx + 1, line);
+            match self.session.handle_line(strip_comment(&line)) {
+                Action::Print(msg) => writeln!(out, "{msg}")?,
+                Action::Error(msg) => {
+                    self.failures += 1;
+                    writeln!(err, "Error: {name}:{}: {msg}", idx + 1)?;
+                    if !self.keep_going {
+                        return Ok(false);
+                    }
+                }
+                Action::Nothing => {}
+                Action::Quit => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `input` as a batch, returning whether it finished, stdout and stderr.
+    fn run(input: &str, keep_going: bool) -> (Batch, bool, String, String) {
+        let mut batch = Batch::new(Session::default(), keep_going);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let finished = batch
+            .run("calc.txt", input.as_bytes(), &mut out, &mut err)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let err = String::from_utf8(err).unwrap();
+        (batch, finished, out, err)
+    }
+
+    #[test]
+    fn test_shared_state() {
+        let input = "\
+# prices
+rate = 0.25   # tax
+net(x) = x / (1 + rate)
+
+net(5)
+ans * 2
+";
+        let (batch, finished, out, err) = run(input, false);
+        assert!(finished);
+        assert_eq!(out, "0.25\n4\n8\n");
+        assert_eq!(err, "");
+        assert_eq!(batch.failures(), 0);
+    }
+    #[test]
+    fn test_stop_at_first_error() {
+        let (batch, finished, out, err) = run("1 + 1\n1 / 0\n3\n", false);
+        assert!(!finished);
+        assert_eq!(out, "2\n");
+        assert_eq!(
+            err,
+            "Error: calc.txt:2: Failed to evaluate - Division by zero\n  1 / 0\n    ^\n"
+        );
+        assert_eq!(batch.failures(), 1);
+    }
+    #[test]
+    fn test_keep_going() {
+        let (batch, finished, out, err) = run("x\n2 * 3\n4 +\nans\n", true);
+        assert!(finished);
+        assert_eq!(out, "6\n6\n");
+        assert!(err.starts_with("Error: calc.txt:1: "));
+        assert!(err.contains("\nError: calc.txt:3: "));
+        assert_eq!(batch.failures(), 2);
+    }
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!(strip_comment("1 + 2 # three"), "1 + 2 ");
+        assert_eq!(strip_comment("# only a comment"), "");
+        assert_eq!(strip_comment("x"), "x");
+    }
+}
@@ -0,0 +1,755 @@
+use crate::postfix::EvalError;
+use crate::tokens::OperatorType;
+use crate::value::Value;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::Signed;
+use std::{cmp::Ordering, f64::consts, fmt};
+
+/// How many arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    /// Inclusive range of accepted argument counts.
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    #[must_use]
+    pub const fn accepts(self, count: usize) -> bool {
+        match self {
+            Self::Exact(n) => count == n,
+            Self::Range(min, max) => min <= count && count <= max,
+            Self::AtLeast(min) => count >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match self {
+            Self::Exact(n) => write!(f, "{n} argument{}", plural(*n)),
+            Self::Range(min, max) => write!(f, "{min} to {max} arguments"),
+            Self::AtLeast(n) => write!(f, "at least {n} argument{}", plural(*n)),
+        }
+    }
+}
+
+/// A function from the standard library, e.g. `sqrt` or `min`.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// Whether the function accepts complex arguments.
+    complex: bool,
+    /// Whether the function takes the elements of lists as its arguments, like `sum`,
+    /// rather than being applied to each element.
+    aggregate: bool,
+    func: fn(&[Value]) -> Result<Value, EvalError>,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Builtin {
+    /// Call the function, `args` must already satisfy its arity.
+    /// Functions that aren't aggregates are applied to lists element by element,
+    /// `sqrt([4, 9])` is `[2, 3]`.
+    ///
+    /// # Errors
+    /// When the function has no value for `args`, e.g. `ln(0)`.
+    pub fn call(&self, args: &[Value]) -> Result<Value, EvalError> {
+        debug_assert!(self.arity.accepts(args.len()));
+        let domain_error = || {
+            let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+            EvalError::Domain(format!("{}({})", self.name, args.join(", ")))
+        };
+        if !self.aggregate && args.iter().any(Value::is_list) {
+            return self.broadcast(args);
+        }
+        let complex = if self.aggregate {
+            values(args).iter().any(Value::is_complex)
+        } else {
+            args.iter().any(Value::is_complex)
+        };
+        if (!self.complex && complex) || (!self.aggregate && args.iter().any(Value::is_quantity)) {
+            return Err(domain_error());
+        }
+        let result = (self.func)(args)?;
+        if result.is_nan() && !args.iter().any(Value::is_nan) {
+            return Err(domain_error());
+        }
+        Ok(result)
+    }
+
+    /// Call the function once for every element of the list arguments, which need the same
+    /// length, other arguments going with each.
+    fn broadcast(&self, args: &[Value]) -> Result<Value, EvalError> {
+        let mut len = None;
+        for arg in args {
+            if let Value::List(items) = arg {
+                match len {
+                    Some(len) if len != items.len() => {
+                        return Err(EvalError::LengthMismatch(len, items.len()))
+                    }
+                    _ => len = Some(items.len()),
+                }
+            }
+        }
+        (0..len.unwrap_or_default())
+            .map(|idx| {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Value::List(items) => items[idx].clone(),
+                        arg => arg.clone(),
+                    })
+                    .collect();
+                self.call(&args)
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::List)
+    }
+}
+
+/// Turn a float that holds a whole number back into an exact integer where it fits.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn integral(f: f64) -> Value {
+    if f.fract() == 0.0 && f >= isize::MIN as f64 && f < isize::MAX as f64 {
+        Value::Int(f as isize)
+    } else {
+        Value::Float(f)
+    }
+}
+
+/// Apply `real` to a real argument, or `complex` if the argument is complex
+/// or has no real result, e.g. `sqrt(-4)` is `2i`.
+fn elementary(args: &[Value], real: fn(f64) -> f64, complex: fn(Complex64) -> Complex64) -> Value {
+    let x = &args[0];
+    if !x.is_complex() {
+        let result = real(x.as_float());
+        if !result.is_nan() || x.is_nan() {
+            return Value::Float(result);
+        }
+    }
+    Value::transcendental(complex(x.as_complex()))
+}
+
+/// Rounding functions leave integers untouched and produce integers where they can.
+/// `exact` does the same rounding on a rational.
+fn rounding(args: &[Value], f: fn(f64) -> f64, exact: fn(&BigRational) -> BigRational) -> Value {
+    match &args[0] {
+        Value::Int(_) => args[0].clone(),
+        Value::Float(x) => integral(f(*x)),
+        Value::Rational(r) => Value::Rational(exact(r)),
+        Value::Complex(_) | Value::Quantity(_) | Value::List(_) => {
+            unreachable!("Rounding functions only take plain real numbers")
+        }
+    }
+}
+
+/// `log(x)` is base 10, `log(x, base)` for any other base.
+/// Logarithms of negative or complex numbers are complex.
+fn log(args: &[Value]) -> Value {
+    let base = args.get(1).cloned().unwrap_or(Value::Int(10));
+    if !args.iter().any(|a| a.is_complex() || a.as_float() < 0.0) {
+        let (x, base) = (args[0].as_float(), base.as_float());
+        // the dedicated functions are exact for powers of their base, `f64::log` is not
+        #[allow(clippy::float_cmp)]
+        let result = if base == 10.0 {
+            x.log10()
+        } else if base == 2.0 {
+            x.log2()
+        } else {
+            x.log(base)
+        };
+        return Value::Float(result);
+    }
+    // `Complex64::log` only takes a real base
+    #[allow(clippy::suboptimal_flops)]
+    let result = args[0].as_complex().ln() / base.as_complex().ln();
+    Value::transcendental(result)
+}
+
+fn abs(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Int(i) => i.checked_abs().map(Value::Int).ok_or(EvalError::Overflow),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        Value::Rational(r) => Ok(Value::Rational(r.abs())),
+        Value::Complex(c) => Ok(Value::Float(c.norm())),
+        Value::Quantity(_) | Value::List(_) => {
+            unreachable!("Functions don't take quantities or lists")
+        }
+    }
+}
+
+/// Real part, imaginary part, argument or conjugate of a complex number.
+/// Apply `part` to a complex argument, real arguments go through `real` so they keep their type.
+fn complex_part(
+    args: &[Value],
+    part: fn(Complex64) -> Complex64,
+    real: fn(&Value) -> Value,
+) -> Value {
+    match &args[0] {
+        Value::Complex(c) => Value::from(part(*c)),
+        x => real(x),
+    }
+}
+
+fn round(args: &[Value]) -> Result<Value, EvalError> {
+    let Some(digits) = args.get(1) else {
+        return Ok(rounding(args, f64::round, BigRational::round));
+    };
+    let Some(digits) = digits.as_integer() else {
+        return Err(EvalError::NotInteger(format!(
+            "round({}, {digits})",
+            args[0]
+        )));
+    };
+    let digits = i32::try_from(digits).map_err(|_| EvalError::Overflow)?;
+    if digits >= 0 && matches!(args[0], Value::Int(_)) {
+        return Ok(args[0].clone());
+    }
+    if let Value::Rational(r) = &args[0] {
+        let scale = BigRational::from_integer(10.into()).pow(digits);
+        return Ok(Value::Rational((r * &scale).round() / scale));
+    }
+    let scale = 10f64.powi(digits);
+    let rounded = (args[0].as_float() * scale).round() / scale;
+    // rounding to tens, hundreds, ... always gives a whole number
+    Ok(if digits <= 0 {
+        integral(rounded)
+    } else {
+        Value::Float(rounded)
+    })
+}
+
+/// The values of an aggregate's arguments, a list giving all of its elements.
+fn values(args: &[Value]) -> Vec<Value> {
+    args.iter()
+        .flat_map(|arg| match arg {
+            Value::List(items) => items.clone(),
+            arg => vec![arg.clone()],
+        })
+        .collect()
+}
+
+/// Order two values the way `<` does, exact values exactly and quantities in their units.
+fn compare(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    let holds = |op: OperatorType| op.apply(a.clone(), b.clone()).map(|v| !v.is_zero());
+    Ok(if holds(OperatorType::Lt)? {
+        Ordering::Less
+    } else if holds(OperatorType::Gt)? {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    })
+}
+
+/// The values in ascending order.
+fn sorted(mut values: Vec<Value>) -> Result<Vec<Value>, EvalError> {
+    let mut error = None;
+    values.sort_by(|a, b| {
+        compare(a, b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+    error.map_or(Ok(values), Err)
+}
+
+/// Pick the smallest (or largest, depending on `pick_left`) value, staying an integer if all inputs are.
+/// Exact values are compared exactly.
+fn extreme(args: &[Value], pick_left: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let mut best: Option<Value> = None;
+    for v in values(args) {
+        best = match best {
+            Some(best) if pick_left(compare(&best, &v)?) => Some(best),
+            _ => Some(v),
+        };
+    }
+    // `NaN` makes the call a domain error
+    Ok(best.unwrap_or(Value::Float(f64::NAN)))
+}
+
+/// Combine the values with `op`, exactly where they are exact and keeping their units.
+/// Without any values the result is `empty`.
+fn fold(values: Vec<Value>, op: &OperatorType, empty: Value) -> Result<Value, EvalError> {
+    let mut values = values.into_iter();
+    let Some(first) = values.next() else {
+        return Ok(empty);
+    };
+    values.try_fold(first, |total, v| op.apply(total, v))
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn mean(values: Vec<Value>) -> Result<Value, EvalError> {
+    if values.is_empty() {
+        return Ok(Value::Float(f64::NAN));
+    }
+    let count = Value::Int(values.len() as isize);
+    OperatorType::Div.apply(fold(values, &OperatorType::Add, Value::Int(0))?, count)
+}
+
+/// The middle value, or the mean of the two in the middle.
+fn median(args: &[Value]) -> Result<Value, EvalError> {
+    let mut values = sorted(values(args))?;
+    let upper = values.len() / 2;
+    match values.len() {
+        0 => Ok(Value::Float(f64::NAN)),
+        len if len % 2 == 1 => Ok(values.swap_remove(upper)),
+        _ => mean(values.drain(upper - 1..=upper).collect()),
+    }
+}
+
+/// The sample variance, dividing by one less than the number of values.
+#[allow(clippy::cast_possible_wrap)]
+fn variance(args: &[Value]) -> Result<Value, EvalError> {
+    let values = values(args);
+    if values.len() < 2 {
+        return Ok(Value::Float(f64::NAN));
+    }
+    let count = values.len() as isize;
+    let mean = mean(values.clone())?;
+    let squares = values
+        .into_iter()
+        .map(|v| {
+            let deviation = OperatorType::Sub.apply(v, mean.clone())?;
+            OperatorType::Mul.apply(deviation.clone(), deviation)
+        })
+        .collect::<Result<_, _>>()?;
+    let total = fold(squares, &OperatorType::Add, Value::Int(0))?;
+    OperatorType::Div.apply(total, Value::Int(count - 1))
+}
+
+/// `percentile(data, p)`, the value below which `p` percent of the data lies, interpolating
+/// linearly between the two nearest values.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+fn percentile(args: &[Value]) -> Result<Value, EvalError> {
+    let (p, data) = args.split_last().expect("Arity guarantees a percentile");
+    let values = sorted(values(data))?;
+    if values.is_empty() || !(0.0..=100.0).contains(&p.as_float()) {
+        return Ok(Value::Float(f64::NAN));
+    }
+    let last = Value::Int(values.len() as isize - 1);
+    let rank =
+        OperatorType::Div.apply(OperatorType::Mul.apply(p.clone(), last)?, Value::Int(100))?;
+    let below = rank.as_float().floor() as usize;
+    let Some(above) = values.get(below + 1) else {
+        return Ok(values[below].clone());
+    };
+    let fraction = OperatorType::Sub.apply(rank, Value::Int(below as isize))?;
+    let step = OperatorType::Sub.apply(above.clone(), values[below].clone())?;
+    OperatorType::Add.apply(
+        values[below].clone(),
+        OperatorType::Mul.apply(step, fraction)?,
+    )
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sqrt",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::sqrt, Complex64::sqrt)),
+    },
+    Builtin {
+        name: "cbrt",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::cbrt, Complex64::cbrt)),
+    },
+    Builtin {
+        name: "exp",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::exp, Complex64::exp)),
+    },
+    Builtin {
+        name: "ln",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::ln, Complex64::ln)),
+    },
+    Builtin {
+        name: "log",
+        arity: Arity::Range(1, 2),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(log(args)),
+    },
+    Builtin {
+        name: "log2",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::log2, |c| c.ln() / consts::LN_2)),
+    },
+    Builtin {
+        name: "sin",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::sin, Complex64::sin)),
+    },
+    Builtin {
+        name: "cos",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::cos, Complex64::cos)),
+    },
+    Builtin {
+        name: "tan",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::tan, Complex64::tan)),
+    },
+    Builtin {
+        name: "asin",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::asin, Complex64::asin)),
+    },
+    Builtin {
+        name: "acos",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::acos, Complex64::acos)),
+    },
+    Builtin {
+        name: "atan",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::atan, Complex64::atan)),
+    },
+    Builtin {
+        name: "atan2",
+        arity: Arity::Exact(2),
+        complex: false,
+        aggregate: false,
+        func: |args| Ok(Value::Float(args[0].as_float().atan2(args[1].as_float()))),
+    },
+    Builtin {
+        name: "sinh",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::sinh, Complex64::sinh)),
+    },
+    Builtin {
+        name: "cosh",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::cosh, Complex64::cosh)),
+    },
+    Builtin {
+        name: "tanh",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(elementary(args, f64::tanh, Complex64::tanh)),
+    },
+    Builtin {
+        name: "abs",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: abs,
+    },
+    Builtin {
+        name: "floor",
+        arity: Arity::Exact(1),
+        complex: false,
+        aggregate: false,
+        func: |args| Ok(rounding(args, f64::floor, BigRational::floor)),
+    },
+    Builtin {
+        name: "ceil",
+        arity: Arity::Exact(1),
+        complex: false,
+        aggregate: false,
+        func: |args| Ok(rounding(args, f64::ceil, BigRational::ceil)),
+    },
+    Builtin {
+        name: "trunc",
+        arity: Arity::Exact(1),
+        complex: false,
+        aggregate: false,
+        func: |args| Ok(rounding(args, f64::trunc, BigRational::trunc)),
+    },
+    Builtin {
+        name: "round",
+        arity: Arity::Range(1, 2),
+        complex: false,
+        aggregate: false,
+        func: round,
+    },
+    Builtin {
+        name: "min",
+        arity: Arity::AtLeast(1),
+        complex: false,
+        aggregate: true,
+        func: |args| extreme(args, Ordering::is_le),
+    },
+    Builtin {
+        name: "max",
+        arity: Arity::AtLeast(1),
+        complex: false,
+        aggregate: true,
+        func: |args| extreme(args, Ordering::is_ge),
+    },
+    Builtin {
+        name: "sum",
+        arity: Arity::AtLeast(1),
+        complex: true,
+        aggregate: true,
+        func: |args| fold(values(args), &OperatorType::Add, Value::Int(0)),
+    },
+    Builtin {
+        name: "product",
+        arity: Arity::AtLeast(1),
+        complex: true,
+        aggregate: true,
+        func: |args| fold(values(args), &OperatorType::Mul, Value::Int(1)),
+    },
+    Builtin {
+        name: "mean",
+        arity: Arity::AtLeast(1),
+        complex: true,
+        aggregate: true,
+        func: |args| mean(values(args)),
+    },
+    Builtin {
+        name: "median",
+        arity: Arity::AtLeast(1),
+        complex: false,
+        aggregate: true,
+        func: median,
+    },
+    Builtin {
+        name: "variance",
+        arity: Arity::AtLeast(1),
+        complex: false,
+        aggregate: true,
+        func: variance,
+    },
+    Builtin {
+        name: "stddev",
+        arity: Arity::AtLeast(1),
+        complex: false,
+        aggregate: true,
+        func: |args| OperatorType::Pow.apply(variance(args)?, Value::Float(0.5)),
+    },
+    Builtin {
+        name: "percentile",
+        arity: Arity::AtLeast(2),
+        complex: false,
+        aggregate: true,
+        func: percentile,
+    },
+    Builtin {
+        name: "re",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(complex_part(args, |c| c.re.into(), Value::clone)),
+    },
+    Builtin {
+        name: "im",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(complex_part(args, |c| c.im.into(), |_| Value::Int(0))),
+    },
+    Builtin {
+        name: "arg",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| {
+            Ok(complex_part(
+                args,
+                |c| c.arg().into(),
+                |x| Value::Float(x.as_complex().arg()),
+            ))
+        },
+    },
+    Builtin {
+        name: "conj",
+        arity: Arity::Exact(1),
+        complex: true,
+        aggregate: false,
+        func: |args| Ok(complex_part(args, |c| c.conj(), Value::clone)),
+    },
+];
+
+#[must_use]
+pub fn function(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Named mathematical constants, looked up after user variables.
+#[must_use]
+pub fn constant(name: &str) -> Option<Value> {
+    match name {
+        "pi" => Some(Value::Float(consts::PI)),
+        "e" => Some(Value::Float(consts::E)),
+        "i" => Some(Value::Complex(Complex64::i())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        function(name).unwrap().call(args)
+    }
+
+    #[test]
+    fn test_arity() {
+        assert!(Arity::Exact(1).accepts(1));
+        assert!(!Arity::Exact(1).accepts(2));
+        assert!(Arity::Range(1, 2).accepts(2));
+        assert!(!Arity::Range(1, 2).accepts(0));
+        assert!(Arity::AtLeast(1).accepts(5));
+        assert_eq!(Arity::Exact(1).to_string(), "1 argument");
+        assert_eq!(Arity::AtLeast(2).to_string(), "at least 2 arguments");
+    }
+    #[test]
+    fn test_lookup() {
+        assert!(function("sqrt").is_some());
+        assert!(function("nope").is_none());
+        assert_eq!(constant("pi"), Some(Value::Float(consts::PI)));
+        assert_eq!(constant("tau"), None);
+    }
+    #[test]
+    fn test_integer_results() {
+        assert_eq!(call("abs", &[Value::Int(-3)]), Ok(Value::Int(3)));
+        assert_eq!(call("floor", &[Value::Float(2.7)]), Ok(Value::Int(2)));
+        assert_eq!(
+            call("min", &[Value::Int(3), Value::Int(1), Value::Int(2)]),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            call("max", &[Value::Int(3), Value::Float(3.5)]),
+            Ok(Value::Float(3.5))
+        );
+    }
+    #[test]
+    fn test_float_results() {
+        assert_eq!(call("sqrt", &[Value::Int(4)]), Ok(Value::Float(2.0)));
+        assert_eq!(
+            call("log", &[Value::Int(100), Value::Int(10)]),
+            Ok(Value::Float(2.0))
+        );
+        assert_eq!(call("log", &[Value::Int(1000)]), Ok(Value::Float(3.0)));
+        assert_eq!(
+            call("round", &[Value::Float(1.23456), Value::Int(2)]),
+            Ok(Value::Float(1.23))
+        );
+        assert_eq!(
+            call("round", &[Value::Int(1234), Value::Int(-2)]),
+            Ok(Value::Int(1200))
+        );
+    }
+    #[test]
+    fn test_complex_results() {
+        let complex = |re, im| Value::Complex(Complex64::new(re, im));
+        assert_eq!(call("sqrt", &[Value::Int(-4)]), Ok(complex(0.0, 2.0)));
+        assert_eq!(call("abs", &[complex(3.0, 4.0)]), Ok(Value::Float(5.0)));
+        assert_eq!(call("conj", &[complex(1.0, 2.0)]), Ok(complex(1.0, -2.0)));
+        assert_eq!(call("im", &[complex(1.0, 2.0)]), Ok(Value::Float(2.0)));
+        assert_eq!(call("re", &[Value::Int(3)]), Ok(Value::Int(3)));
+    }
+    #[test]
+    fn test_lists() {
+        let list = |items: &[isize]| Value::List(items.iter().copied().map(Value::Int).collect());
+        assert_eq!(call("abs", &[list(&[-1, 2])]), Ok(list(&[1, 2])));
+        assert_eq!(
+            call(
+                "round",
+                &[Value::List(vec![Value::Float(1.26)]), Value::Int(1)]
+            ),
+            Ok(Value::List(vec![Value::Float(1.3)]))
+        );
+        assert_eq!(
+            call("atan2", &[list(&[1, 2]), list(&[1])]),
+            Err(EvalError::LengthMismatch(2, 1))
+        );
+        assert_eq!(
+            call("sum", &[list(&[1, 2]), Value::Int(3)]),
+            Ok(Value::Int(6))
+        );
+        assert_eq!(call("product", &[list(&[])]), Ok(Value::Int(1)));
+        assert_eq!(call("max", &[list(&[3, 9, 4])]), Ok(Value::Int(9)));
+        assert_eq!(call("mean", &[list(&[1, 2, 3, 4])]), Ok(Value::Float(2.5)));
+        assert_eq!(call("median", &[list(&[5, 1, 3])]), Ok(Value::Int(3)));
+        assert_eq!(
+            call("median", &[list(&[4, 1, 3, 2])]),
+            Ok(Value::Float(2.5))
+        );
+        assert_eq!(
+            call("variance", &[list(&[1, 2, 3, 4])]),
+            Ok(Value::Float(5.0 / 3.0))
+        );
+        assert_eq!(
+            call("stddev", &[list(&[2, 4])]),
+            Ok(Value::Float(2f64.sqrt()))
+        );
+        let data = list(&[1, 2, 3, 4, 5]);
+        assert_eq!(
+            call("percentile", &[data.clone(), Value::Int(25)]),
+            Ok(Value::Int(2))
+        );
+        assert_eq!(
+            call("percentile", &[data.clone(), Value::Int(90)]),
+            Ok(Value::Float(4.6))
+        );
+        assert!(matches!(
+            call("percentile", &[data, Value::Int(101)]),
+            Err(EvalError::Domain(_))
+        ));
+        assert!(matches!(
+            call("mean", &[list(&[])]),
+            Err(EvalError::Domain(_))
+        ));
+        assert!(matches!(
+            call("variance", &[Value::Int(1)]),
+            Err(EvalError::Domain(_))
+        ));
+    }
+    #[test]
+    fn test_domain_error() {
+        assert!(matches!(
+            call("floor", &[Value::Complex(Complex64::new(1.0, 1.0))]),
+            Err(EvalError::Domain(_))
+        ));
+        assert_eq!(
+            call("round", &[Value::Float(1.5), Value::Float(0.5)]),
+            Err(EvalError::NotInteger("round(1.5, 0.5)".into()))
+        );
+    }
+}
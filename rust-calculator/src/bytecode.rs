@@ -0,0 +1,646 @@
+use crate::builtins;
+use crate::environment::Environment;
+use crate::postfix::{EvalError, PostExpression};
+use crate::span::{Span, Spanned};
+use crate::tokens::{MathToken, OperatorType, Series, SkipIf};
+use crate::units;
+use crate::value::{Arithmetic, Value};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::fmt;
+
+/// One step of a compiled `Program`, working on the value stack of a `Vm`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// Push the constant at the index.
+    Push(usize),
+    /// Fail with the error at the index, for operands that don't fit the arithmetic.
+    /// They only fail when reached, e.g. not in the skipped side of `&&`.
+    Fail(usize),
+    /// Push the value of the variable in the slot.
+    Load(usize),
+    Unary(OperatorType),
+    Binary(OperatorType),
+    /// `?:`, keep the second or third value from the top depending on the first.
+    Cond,
+    /// Call the function whose name is at the index with the given number of arguments.
+    Call(usize, usize),
+    /// Like `MathToken::Skip`, the target is the index of an instruction.
+    Jump(SkipIf, usize),
+    /// Make a list of the given number of values from the top of the stack.
+    List(usize),
+    /// Sum or multiply the series at the index between the bounds on the stack.
+    Series(usize),
+}
+
+/// A sum or product whose body is compiled on its own, to be run for every value of its
+/// variable.
+#[derive(Debug, Clone)]
+struct CompiledSeries {
+    series: Series,
+    body: Program,
+    /// The slot of the outer program each slot of the body reads, `None` for the variable.
+    slots: Vec<Option<usize>>,
+}
+
+/// Postfix compiled to bytecode for evaluating the same expression many times.
+///
+/// Variables are numbered slots, so a `Vm` reads them by index rather than by name.
+/// Operations on constants only are done while compiling, e.g. `x * (1 + 2)` becomes `x * 3`,
+/// and so are builtin constants like `pi` that aren't variables of the environment it is
+/// compiled against.
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Instruction>,
+    /// Where each instruction came from in the input, folded ones cover all their operands.
+    spans: Vec<Span>,
+    constants: Vec<Value>,
+    errors: Vec<EvalError>,
+    slots: Vec<String>,
+    functions: Vec<String>,
+    series: Vec<CompiledSeries>,
+    arithmetic: Arithmetic,
+    /// Most values on the stack at once.
+    depth: usize,
+    /// The postfix compiled, to describe an out of range result.
+    source: String,
+}
+
+impl Program {
+    /// Compile `postfix` with the arithmetic of `env`, which is also used for constant folding.
+    #[allow(clippy::too_many_lines)]
+    #[must_use]
+    pub fn compile(postfix: &PostExpression, env: &Environment) -> Self {
+        let tokens = postfix.tokens();
+        let mut program = Self {
+            code: Vec::with_capacity(tokens.len()),
+            spans: Vec::with_capacity(tokens.len()),
+            constants: Vec::new(),
+            errors: Vec::new(),
+            slots: Vec::new(),
+            functions: Vec::new(),
+            series: Vec::new(),
+            arithmetic: env.arithmetic(),
+            depth: 0,
+            source: postfix.to_string(),
+        };
+        let mut landings = vec![false; tokens.len() + 1];
+        for tok in tokens {
+            if let MathToken::Skip(_, target) = tok {
+                landings[*target] = true;
+            }
+        }
+        // where the instructions of each token start, for resolving jumps
+        let mut starts = Vec::with_capacity(tokens.len() + 1);
+        // folding stops at the last jump target, a jump can't land inside a folded constant
+        let mut floor = 0;
+        for (idx, tok) in tokens.iter().enumerate() {
+            let start = program.code.len();
+            starts.push(start);
+            if landings[idx] {
+                floor = start;
+            }
+            let span = postfix.span(idx);
+            match tok {
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::BigIntOperand(_) => {
+                    let literal = match tok {
+                        MathToken::IntOperand(i) => Value::Int(*i),
+                        MathToken::FloatOperand(x) => Value::Float(*x),
+                        MathToken::BigIntOperand(n) => {
+                            Value::Rational(BigRational::from_integer(n.clone()))
+                        }
+                        _ => unreachable!("Matched a number above"),
+                    };
+                    program.constant(program.arithmetic.literal(literal), span);
+                }
+                MathToken::ImaginaryOperand(x) => {
+                    let imaginary = Value::Complex(Complex64::new(0.0, *x));
+                    let checked = program.arithmetic.check(imaginary, || tok.to_string());
+                    program.constant(checked, span);
+                }
+                MathToken::Identifier(name) => match builtins::constant(name) {
+                    Some(constant) if env.get(name).is_none() => {
+                        let checked = program.arithmetic.check(constant, || name.clone());
+                        program.constant(checked, span);
+                    }
+                    _ => {
+                        let slot = program.slot_or_add(name);
+                        program.emit(Instruction::Load(slot), span);
+                    }
+                },
+                MathToken::Unit(name) => {
+                    let unit = units::quantity(name)
+                        .ok_or_else(|| EvalError::UndefinedVariable(name.clone()))
+                        .and_then(|unit| program.arithmetic.check(unit, || name.clone()));
+                    program.constant(unit, span);
+                }
+                MathToken::Call(name, argc) => {
+                    // builtins always give the same result for the same arguments
+                    let folded = builtins::function(name).is_some()
+                        && program.fold(*argc, floor, span, |args| {
+                            PostExpression::call_function(env, name, args)
+                        });
+                    if !folded {
+                        let function = program.functions.len();
+                        program.functions.push(name.clone());
+                        program.emit(Instruction::Call(function, *argc), span);
+                    }
+                }
+                MathToken::Skip(when, target) => {
+                    program.emit(Instruction::Jump(*when, *target), span);
+                }
+                MathToken::Operator(OperatorType::Cond) => program.emit(Instruction::Cond, span),
+                MathToken::Operator(op) if op.is_unary() => {
+                    let arithmetic = program.arithmetic;
+                    let folded = program.fold(1, floor, span, |args| {
+                        arithmetic.fit(op.apply_unary(args[0].clone())?, op)
+                    });
+                    if !folded {
+                        program.emit(Instruction::Unary(op.clone()), span);
+                    }
+                }
+                MathToken::Operator(op) => {
+                    let arithmetic = program.arithmetic;
+                    let folded = program.fold(2, floor, span, |args| {
+                        arithmetic.fit(op.apply(args[0].clone(), args[1].clone())?, op)
+                    });
+                    if !folded {
+                        program.emit(Instruction::Binary(op.clone()), span);
+                    }
+                }
+                MathToken::List(len) => {
+                    let folded =
+                        program.fold(*len, floor, span, |items| Ok(Value::List(items.to_vec())));
+                    if !folded {
+                        program.emit(Instruction::List(*len), span);
+                    }
+                }
+                MathToken::Series(series) => {
+                    // the variable mustn't be folded, even if it is named like a constant
+                    let mut inner = env.clone();
+                    inner.set(series.var.clone(), Value::Int(0));
+                    let body = Self::compile(&series.body, &inner);
+                    let slots = body
+                        .slots
+                        .iter()
+                        .map(|name| (*name != series.var).then(|| program.slot_or_add(name)))
+                        .collect();
+                    program.series.push(CompiledSeries {
+                        series: series.as_ref().clone(),
+                        body,
+                        slots,
+                    });
+                    program.emit(Instruction::Series(program.series.len() - 1), span);
+                }
+                MathToken::Parens(_)
+                | MathToken::Brackets(_)
+                | MathToken::Assign
+                | MathToken::Function(_)
+                | MathToken::Comma
+                | MathToken::Question
+                | MathToken::Colon => unreachable!("Token {tok:?} never appears in postfix"),
+            }
+        }
+        starts.push(program.code.len());
+        for instruction in &mut program.code {
+            if let Instruction::Jump(_, target) = instruction {
+                *target = starts[*target];
+            }
+        }
+        program.depth = program.max_depth();
+        program
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) {
+        self.code.push(instruction);
+        self.spans.push(span);
+    }
+
+    /// Push a constant, or fail when reached if it doesn't fit the arithmetic.
+    fn constant(&mut self, value: Result<Value, EvalError>, span: Span) {
+        match value {
+            Ok(value) => {
+                self.constants.push(value);
+                self.emit(Instruction::Push(self.constants.len() - 1), span);
+            }
+            Err(error) => {
+                self.errors.push(error);
+                self.emit(Instruction::Fail(self.errors.len() - 1), span);
+            }
+        }
+    }
+
+    /// Replace the last `argc` instructions with the result of `apply`, if they all push
+    /// constants after `floor` and `apply` succeeds. Failures are left for when they are reached.
+    fn fold(
+        &mut self,
+        argc: usize,
+        floor: usize,
+        span: Span,
+        apply: impl FnOnce(&[Value]) -> Result<Value, EvalError>,
+    ) -> bool {
+        let Some(first) = self.code.len().checked_sub(argc).filter(|&at| at >= floor) else {
+            return false;
+        };
+        let operands: Option<Vec<_>> = self.code[first..]
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::Push(idx) => Some(self.constants[*idx].clone()),
+                _ => None,
+            })
+            .collect();
+        let Some(Ok(result)) = operands.map(|operands| apply(&operands)) else {
+            return false;
+        };
+        let span = self.spans.get(first).map_or(span, |start| start.to(span));
+        // the operands pushed the newest constants, they aren't needed anymore
+        if let Some(Instruction::Push(oldest)) = self.code.get(first) {
+            self.constants.truncate(*oldest);
+        }
+        self.code.truncate(first);
+        self.spans.truncate(first);
+        self.constant(Ok(result), span);
+        true
+    }
+
+    /// The most values on the stack at once, a taken jump pushes one in place of what it skips.
+    fn max_depth(&self) -> usize {
+        let mut depth: usize = 0;
+        let mut deepest = 0;
+        for instruction in &self.code {
+            depth = match instruction {
+                Instruction::Push(_) | Instruction::Fail(_) | Instruction::Load(_) => depth + 1,
+                Instruction::Unary(_) | Instruction::Jump(..) => depth,
+                Instruction::Binary(_) | Instruction::Series(_) => depth.saturating_sub(1),
+                Instruction::List(len) => depth.saturating_sub(*len) + 1,
+                Instruction::Cond => depth.saturating_sub(2),
+                Instruction::Call(_, argc) => depth.saturating_sub(*argc) + 1,
+            };
+            deepest = deepest.max(depth);
+        }
+        deepest
+    }
+
+    #[must_use]
+    pub fn code(&self) -> &[Instruction] {
+        &self.code
+    }
+
+    /// Names of the variables, in slot order.
+    #[must_use]
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    #[must_use]
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|slot| slot == name)
+    }
+
+    fn slot_or_add(&mut self, name: &str) -> usize {
+        self.slot(name).unwrap_or_else(|| {
+            self.slots.push(name.to_string());
+            self.slots.len() - 1
+        })
+    }
+
+    /// Slot values from the variables of `env`, falling back to builtin constants.
+    /// Variables `env` doesn't define are `None` and fail when loaded.
+    #[must_use]
+    pub fn load(&self, env: &Environment) -> Vec<Option<Value>> {
+        self.slots
+            .iter()
+            .map(|name| {
+                env.get(name).or_else(|| {
+                    builtins::constant(name)
+                        .and_then(|constant| self.arithmetic.check(constant, || name.clone()).ok())
+                })
+            })
+            .collect()
+    }
+
+    /// Evaluate once with the variables of `env`, like `PostExpression::eval`.
+    ///
+    /// # Errors
+    /// When evaluation fails, see `Vm::run`.
+    pub fn eval(&self, env: &Environment) -> Result<Value, Spanned<EvalError>> {
+        Vm::default().run(self, env, &self.load(env))
+    }
+}
+
+/// A listing with one instruction per line, spelled like postfix tokens.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.code.len().saturating_sub(1).to_string().len();
+        for (idx, instruction) in self.code.iter().enumerate() {
+            write!(f, "{idx:>width$}  ")?;
+            match instruction {
+                Instruction::Push(constant) => writeln!(f, "push {}", self.constants[*constant])?,
+                Instruction::Fail(error) => writeln!(f, "fail {}", self.errors[*error])?,
+                Instruction::Load(slot) => writeln!(f, "load {}", self.slots[*slot])?,
+                Instruction::Unary(op) => writeln!(f, "{}", MathToken::Operator(op.clone()))?,
+                Instruction::Binary(op) => writeln!(f, "{op}")?,
+                Instruction::Cond => writeln!(f, "{}", OperatorType::Cond)?,
+                Instruction::Call(function, argc) => writeln!(
+                    f,
+                    "{}",
+                    MathToken::Call(self.functions[*function].clone(), *argc)
+                )?,
+                Instruction::Jump(when, target) => {
+                    writeln!(f, "{}", MathToken::Skip(*when, *target))?;
+                }
+                Instruction::List(len) => writeln!(f, "{}", MathToken::List(*len))?,
+                Instruction::Series(series) => writeln!(f, "{}", self.series[*series].series)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stack machine running `Program`s. The stack is kept between runs, so evaluating a
+/// program again, e.g. for every point of a sweep, doesn't allocate.
+#[derive(Debug, Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+}
+
+impl Vm {
+    /// Run `program` with the variable values in `slots`, indexed like `Program::slots`.
+    /// User defined and registered functions are looked up in `env`.
+    ///
+    /// # Errors
+    /// When an instruction fails, e.g. loading a variable that isn't defined or dividing by
+    /// zero, pointing at the token it was compiled from.
+    #[allow(clippy::too_many_lines)]
+    pub fn run(
+        &mut self,
+        program: &Program,
+        env: &Environment,
+        slots: &[Option<Value>],
+    ) -> Result<Value, Spanned<EvalError>> {
+        let stack = &mut self.stack;
+        stack.clear();
+        stack.reserve(program.depth);
+        let mut pc = 0;
+        while let Some(instruction) = program.code.get(pc) {
+            let span = program.spans[pc];
+            let at = |error| Spanned::new(error, span);
+            pc += 1;
+            match instruction {
+                Instruction::Push(constant) => stack.push(program.constants[*constant].clone()),
+                Instruction::Fail(error) => return Err(at(program.errors[*error].clone())),
+                Instruction::Load(slot) => {
+                    let value = slots.get(*slot).cloned().flatten().ok_or_else(|| {
+                        at(EvalError::UndefinedVariable(program.slots[*slot].clone()))
+                    })?;
+                    stack.push(value);
+                }
+                Instruction::Unary(op) => {
+                    let operand = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let result = op.apply_unary(operand).map_err(at)?;
+                    stack.push(program.arithmetic.fit(result, op).map_err(at)?);
+                }
+                Instruction::Binary(op) => {
+                    let rhs = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let lhs = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let result = op.apply(lhs, rhs).map_err(at)?;
+                    stack.push(program.arithmetic.fit(result, op).map_err(at)?);
+                }
+                Instruction::Cond => {
+                    let mut pop = || stack.pop().ok_or_else(|| at(EvalError::StackUnderflow));
+                    let otherwise = pop()?;
+                    let then = pop()?;
+                    let cond = pop()?;
+                    stack.push(OperatorType::apply_cond(&cond, then, otherwise));
+                }
+                Instruction::Call(function, argc) => {
+                    let first_arg = stack
+                        .len()
+                        .checked_sub(*argc)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let name = &program.functions[*function];
+                    let result = PostExpression::call_function(env, name, &stack[first_arg..])
+                        .map_err(at)?;
+                    stack.truncate(first_arg);
+                    stack.push(result);
+                }
+                Instruction::List(len) => {
+                    let first = stack
+                        .len()
+                        .checked_sub(*len)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let items = stack.split_off(first);
+                    stack.push(Value::List(items));
+                }
+                Instruction::Series(series) => {
+                    let to = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let from = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let CompiledSeries {
+                        series,
+                        body,
+                        slots: outer,
+                    } = &program.series[*series];
+                    let mut inner: Vec<_> = outer
+                        .iter()
+                        .map(|slot| slot.and_then(|slot| slots.get(slot).cloned().flatten()))
+                        .collect();
+                    let mut vm = Self::default();
+                    let total = series.fold((from, to), program.arithmetic, at, |value| {
+                        for (slot, outer) in inner.iter_mut().zip(outer) {
+                            if outer.is_none() {
+                                *slot = Some(value.clone());
+                            }
+                        }
+                        vm.run(body, env, &inner)
+                    })?;
+                    stack.push(total);
+                }
+                Instruction::Jump(when, target) => {
+                    let jumps = match when {
+                        SkipIf::Always => true,
+                        SkipIf::True | SkipIf::False => {
+                            let top = stack.last().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                            top.is_zero() == (*when == SkipIf::False)
+                        }
+                    };
+                    if jumps {
+                        // stands in for the skipped operand, which the operator will ignore
+                        stack.push(Value::Int(0));
+                        pc = *target;
+                    }
+                }
+            }
+        }
+        let full_span = || {
+            program
+                .spans
+                .iter()
+                .copied()
+                .reduce(Span::to)
+                .unwrap_or_default()
+        };
+        match (stack.pop(), stack.len()) {
+            (None, _) => Err(Spanned::new(EvalError::StackUnderflow, full_span())),
+            // a lone fixed width literal may be out of range, see `Arithmetic::literal`
+            (Some(result), 0) => program
+                .arithmetic
+                .check(result, || program.source.clone())
+                .map_err(|e| Spanned::new(e, full_span())),
+            (Some(_), n) => Err(Spanned::new(EvalError::LeftoverOperands(n), full_span())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::Expression;
+    use crate::value::Width;
+
+    fn postfix(input: &str) -> PostExpression {
+        PostExpression::try_from(input.parse::<Expression>().unwrap()).unwrap()
+    }
+    fn listing(input: &str) -> Vec<String> {
+        let program = Program::compile(&postfix(input), &Environment::default());
+        program
+            .to_string()
+            .lines()
+            .map(|line| line.split_once("  ").unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(listing("2 * 3 + x"), ["push 6", "load x", "+"]);
+        assert_eq!(listing("x * (1 + 2) ^ 2"), ["load x", "push 9", "*"]);
+        assert_eq!(listing("-max(1, 4, 2) + sqrt(16)"), ["push 0"]);
+        assert_eq!(listing("2 * pi"), ["push 6.283185307179586"]);
+        // errors wait until they are reached
+        assert_eq!(listing("1 / 0"), ["push 1", "push 0", "/"]);
+        let mut env = Environment::default();
+        env.set("pi", Value::Int(3));
+        let program = Program::compile(&postfix("2 * pi"), &env);
+        assert_eq!(program.eval(&env), Ok(Value::Int(6)));
+    }
+    #[test]
+    fn test_jumps() {
+        assert_eq!(
+            listing("x && 1 + 1"),
+            ["load x", "jump-if-false(3)", "push 2", "&&"]
+        );
+        assert_eq!(
+            listing("x ? 2 * 2 : 1 - 1"),
+            [
+                "load x",
+                "jump-if-false(4)",
+                "push 4",
+                "jump(5)",
+                "push 0",
+                "?:"
+            ]
+        );
+        // the constants on either side of a jump stay apart
+        assert_eq!(listing("1 || 2").len(), 4);
+        let program = Program::compile(&postfix("0 && 1 / 0"), &Environment::default());
+        assert_eq!(program.eval(&Environment::default()), Ok(Value::Int(0)));
+    }
+    #[test]
+    fn test_matches_eval() {
+        let mut env = Environment::default();
+        env.set("x", Value::Float(1.5));
+        env.set("n", Value::Int(3));
+        env.define(
+            "sq",
+            crate::environment::UserFunction {
+                params: vec!["v".into()],
+                body: postfix("v * v"),
+            },
+        );
+        for input in [
+            "x ^ 2 + 2 * x - 1",
+            "n > 2 ? sq(n) : 1 / 0",
+            "max(x, n, 2) - min(1, n)",
+            "(n xor 1) << 2 | 1",
+            "sqrt(-n) * i",
+            "sq(x) + sq(2)",
+            "n / 0",
+            "nope + 1",
+            "0 || nope",
+            "~x",
+        ] {
+            let postfix = postfix(input);
+            let program = Program::compile(&postfix, &env);
+            assert_eq!(program.eval(&env), postfix.eval(&env), "{input}");
+        }
+    }
+    #[test]
+    fn test_lists_and_series() {
+        assert_eq!(listing("[1, 2 * 3] + x"), ["push [1, 6]", "load x", "+"]);
+        assert_eq!(
+            listing("sum(k, 1, n, k * x)"),
+            ["push 1", "load n", "sum(k, k x *)"]
+        );
+        let mut env = Environment::default();
+        env.set("x", Value::Float(0.5));
+        env.set("n", Value::Int(4));
+        // the variable shadows the constant it is named after
+        env.set("e", Value::Int(1));
+        for input in [
+            "[x, n] * 2",
+            "sum(k, 1, n, x ^ k)",
+            "product(i, 1, n, i + x) - sum(n..6)",
+            "sum(e, 1, 3, e * pi)",
+            "sum(i, 1, n, sum(j, i, n, i * j))",
+            "sum(i, 1, n, i > 2 && i)",
+            "mean(1..n, [x])",
+        ] {
+            let postfix = postfix(input);
+            let program = Program::compile(&postfix, &env);
+            assert_eq!(program.eval(&env), postfix.eval(&env), "{input}");
+        }
+    }
+    #[test]
+    fn test_arithmetic() {
+        let exact = Environment::new(Arithmetic::Exact);
+        let program = Program::compile(&postfix("1/3 + 1/6 + pi"), &exact);
+        assert_eq!(program.code()[0], Instruction::Push(0));
+        assert!(matches!(program.code()[1], Instruction::Fail(_)));
+        assert_eq!(
+            program.eval(&exact).unwrap_err().error,
+            EvalError::Inexact("pi".into())
+        );
+        let fixed = Environment::new(Arithmetic::Fixed {
+            width: Width::I8,
+            wrapping: false,
+        });
+        for input in ["127 + 1", "-128", "128", "0 && 300"] {
+            let postfix = postfix(input);
+            let program = Program::compile(&postfix, &fixed);
+            assert_eq!(program.eval(&fixed), postfix.eval(&fixed), "{input}");
+        }
+    }
+    #[test]
+    fn test_vm_reuses_stack() {
+        let mut env = Environment::default();
+        env.set("a", Value::Int(2));
+        let program = Program::compile(&postfix("a * x ^ 2 + (1 + 2) * x - 5"), &env);
+        assert_eq!(program.slots(), ["a", "x"]);
+        let x = program.slot("x").unwrap();
+        let mut slots = program.load(&env);
+        assert_eq!(slots[x], None);
+        let mut vm = Vm::default();
+        let mut capacity = None;
+        for n in -3..=3 {
+            slots[x] = Some(Value::Int(n));
+            let result = vm.run(&program, &env, &slots).unwrap();
+            assert_eq!(result, Value::Int(2 * n * n + 3 * n - 5));
+            assert_eq!(
+                *capacity.get_or_insert_with(|| vm.stack.capacity()),
+                vm.stack.capacity()
+            );
+        }
+        assert_eq!(program.depth, 3);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::postfix::PostExpression;
+use crate::registry::Registry;
+use crate::value::{Arithmetic, Value};
+use std::collections::HashMap;
+
+/// A function defined by the user, e.g. `f(x) = x^2 + 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: PostExpression,
+}
+
+/// Named values and functions available while evaluating an expression.
+#[derive(Debug, Default, Clone)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+    arithmetic: Arithmetic,
+    registry: Registry,
+}
+
+impl Environment {
+    #[must_use]
+    pub fn new(arithmetic: Arithmetic) -> Self {
+        Self {
+            arithmetic,
+            ..Self::default()
+        }
+    }
+
+    /// Read and evaluate expressions with the operators and functions of `registry`.
+    #[must_use]
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    #[must_use]
+    pub const fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// How numbers are represented while evaluating in this environment.
+    #[must_use]
+    pub const fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    #[must_use]
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    pub fn define(&mut self, name: impl Into<String>, function: UserFunction) {
+        self.functions.insert(name.into(), function);
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).cloned()
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, value: Value) {
+        self.variables.insert(name.into(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let mut env = Environment::default();
+        assert_eq!(env.get("x"), None);
+        env.set("x", Value::Int(3));
+        assert_eq!(env.get("x"), Some(Value::Int(3)));
+        env.set("x", Value::Float(0.5));
+        assert_eq!(env.get("x"), Some(Value::Float(0.5)));
+    }
+}
@@ -0,0 +1,349 @@
+use crate::postfix::{ConversionStep, EvalStep, PostExpression};
+use crate::tokens::{MathToken, OperatorType};
+
+/// Lay out `rows` in columns under `header`, padded to the widest cell.
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(|title| title.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: [String; N]| {
+        let padded: Vec<_> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        format!("{}\n", padded.join("  ").trim_end())
+    };
+    let mut out = line(header.map(String::from));
+    out += &line(widths.map(|width| "-".repeat(width)));
+    for row in rows {
+        out += &line(row.clone());
+    }
+    out
+}
+
+fn join<T: ToString>(items: &[T], separator: &str) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Backtick quoted tokens, e.g. `` `+` `(` ``.
+fn quoted<'a>(tokens: impl IntoIterator<Item = &'a MathToken>) -> String {
+    let quoted: Vec<_> = tokens.into_iter().map(|tok| format!("`{tok}`")).collect();
+    quoted.join(" ")
+}
+
+/// What the conversion did to get from the output and stack of `before` to those of `step`.
+fn conversion_action(before: &ConversionStep, step: &ConversionStep) -> String {
+    // a series takes its variable and body back out of the output
+    let unchanged = before
+        .output
+        .iter()
+        .zip(&step.output)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let moved = &step.output[unchanged..];
+    // operators and calls in the output came off the stack, even if an equal one was pushed back
+    let from_stack = moved
+        .iter()
+        .filter(|tok| {
+            matches!(
+                tok,
+                MathToken::Operator(_) | MathToken::Call(..) | MathToken::Series(_)
+            )
+        })
+        .count();
+    let kept = before
+        .stack
+        .iter()
+        .zip(&step.stack)
+        .take_while(|(old, new)| old == new)
+        .count()
+        .min(before.stack.len() - from_stack);
+    let dropped: Vec<_> = before.stack[kept..]
+        .iter()
+        .filter(|tok| {
+            matches!(
+                tok,
+                MathToken::Parens(true) | MathToken::Brackets(true) | MathToken::Question
+            )
+        })
+        .collect();
+    let pushed = &step.stack[kept..];
+
+    let mut parts = Vec::new();
+    if !moved.is_empty() {
+        parts.push(format!("output {}", quoted(moved)));
+    }
+    if !dropped.is_empty() {
+        parts.push(format!("discard {}", quoted(dropped)));
+    }
+    if !pushed.is_empty() {
+        parts.push(format!("push {}", quoted(pushed)));
+    }
+    if parts.is_empty() {
+        // a `,` with nothing to pop only starts the next argument
+        return if step.token.is_some() {
+            "next argument"
+        } else {
+            "stack is empty"
+        }
+        .to_string();
+    }
+    parts.join(", ")
+}
+
+/// The steps of the shunting-yard conversion as a table of the token read, what was done with
+/// it and the output queue and operator stack afterwards.
+#[must_use]
+pub fn conversion_table(steps: &[ConversionStep]) -> String {
+    let start = ConversionStep {
+        token: None,
+        output: Vec::new(),
+        stack: Vec::new(),
+    };
+    let rows: Vec<_> = steps
+        .iter()
+        .scan(&start, |before, step| {
+            let action = conversion_action(before, step);
+            *before = step;
+            Some([
+                step.token
+                    .as_ref()
+                    .map_or_else(|| "(end)".to_string(), ToString::to_string),
+                action,
+                join(&step.output, " "),
+                join(&step.stack, " "),
+            ])
+        })
+        .collect();
+    table(["Token", "Action", "Output", "Stack"], &rows)
+}
+
+/// The stack after each step as text, the placeholder a jump leaves for the operand it skips
+/// shown as `(skipped)`.
+fn shown_stacks(steps: &[EvalStep]) -> Vec<Vec<String>> {
+    // positions of the placeholders on the stack
+    let mut skipped: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    steps
+        .iter()
+        .map(|step| {
+            let top = step.stack.len().saturating_sub(1);
+            match step.token {
+                MathToken::Skip(..) if step.stack.len() > depth => skipped.push(top),
+                MathToken::Skip(..) => {}
+                // every other token replaces the values it takes with one on top
+                _ => skipped.retain(|&pos| pos < top),
+            }
+            depth = step.stack.len();
+            step.stack
+                .iter()
+                .enumerate()
+                .map(|(pos, value)| {
+                    if skipped.contains(&pos) {
+                        "(skipped)".to_string()
+                    } else {
+                        value.to_string()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// What evaluating `step` did to the stack it was given in `before`, as shown by `shown_stacks`.
+fn eval_action(before: &[String], step: &EvalStep) -> String {
+    if let MathToken::Skip(_, target) = step.token {
+        // a jump leaves a placeholder for the operand it skips
+        return if step.stack.len() > before.len() {
+            format!("jump to {target}")
+        } else {
+            "no jump".to_string()
+        };
+    }
+    let result = step
+        .stack
+        .last()
+        .expect("Every token but a skip pushes a value");
+    let args = &before[step.stack.len() - 1..];
+    let applied = match (&step.token, args) {
+        (MathToken::Operator(OperatorType::Cond), [cond, then, otherwise]) => {
+            format!("{cond} ? {then} : {otherwise}")
+        }
+        (MathToken::Operator(_), [operand]) => format!("{} {operand}", step.token),
+        (MathToken::Operator(op), [lhs, rhs]) => format!("{lhs} {op} {rhs}"),
+        (MathToken::Call(name, _), args) => format!("{name}({})", join(args, ", ")),
+        (MathToken::Identifier(name) | MathToken::Unit(name), _) => name.clone(),
+        (MathToken::Series(series), [from, to]) => {
+            format!("{} over {} = {from}..{to}", series.name(), series.var)
+        }
+        (MathToken::List(len), _) => return format!("collect {len} values"),
+        _ => return "push".to_string(),
+    };
+    format!("{applied} = {result}")
+}
+
+/// The postfix tokens with the jumps of `&&`, `||` and `?:`, which `PostExpression` leaves out
+/// when displayed, so the positions in `evaluation_table` can be counted along.
+#[must_use]
+pub fn postfix_with_jumps(postfix: &PostExpression) -> String {
+    join(postfix.tokens(), " ")
+}
+
+/// The steps of evaluating postfix as a table of the token, what it did and the stack afterwards.
+#[must_use]
+pub fn evaluation_table(steps: &[EvalStep]) -> String {
+    let start = Vec::new();
+    let rows: Vec<_> = steps
+        .iter()
+        .zip(shown_stacks(steps))
+        .scan(start, |before, (step, stack)| {
+            let action = eval_action(before, step);
+            let row = [
+                step.index.to_string(),
+                step.token.to_string(),
+                action,
+                stack.join(", "),
+            ];
+            *before = stack;
+            Some(row)
+        })
+        .collect();
+    table(["#", "Token", "Action", "Stack"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::tokens::Expression;
+    use crate::value::Value;
+
+    fn convert(input: &str) -> Vec<ConversionStep> {
+        let mut steps = Vec::new();
+        PostExpression::try_from_traced(input.parse::<Expression>().unwrap(), &mut steps).unwrap();
+        steps
+    }
+    fn actions(input: &str) -> Vec<String> {
+        let steps = convert(input);
+        let mut before = &ConversionStep {
+            token: None,
+            output: Vec::new(),
+            stack: Vec::new(),
+        };
+        steps
+            .iter()
+            .map(|step| {
+                let action = conversion_action(before, step);
+                before = step;
+                action
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_conversion_actions() {
+        assert_eq!(
+            actions("1 - 2 + 3"),
+            [
+                "output `1`",
+                "push `-`",
+                "output `2`",
+                "output `-`, push `+`",
+                "output `3`",
+                "output `+`",
+            ]
+        );
+        assert_eq!(
+            actions("max(a, -b)")[3..],
+            [
+                "next argument",
+                "push `neg`",
+                "output `b`",
+                "output `neg` `max(2)`, discard `(`",
+                "stack is empty",
+            ]
+        );
+        assert_eq!(actions("a ? b : c")[3], "discard `?`, push `?:`");
+    }
+    #[test]
+    fn test_conversion_table() {
+        assert_eq!(
+            conversion_table(&convert("2 * (3 + 4)")),
+            "\
+Token  Action                   Output     Stack
+-----  -----------------------  ---------  -----
+2      output `2`               2
+*      push `*`                 2          *
+(      push `(`                 2          * (
+3      output `3`               2 3        * (
++      push `+`                 2 3        * ( +
+4      output `4`               2 3 4      * ( +
+)      output `+`, discard `(`  2 3 4 +    *
+(end)  output `*`               2 3 4 + *
+"
+        );
+    }
+    #[test]
+    fn test_evaluation_table() {
+        let postfix =
+            PostExpression::try_from("1 || 1 / 0".parse::<Expression>().unwrap()).unwrap();
+        assert_eq!(postfix.to_string(), "1 1 0 / ||");
+        assert_eq!(postfix_with_jumps(&postfix), "1 jump-if-true(5) 1 0 / ||");
+        let mut steps = Vec::new();
+        postfix
+            .eval_traced(&Environment::default(), &mut steps)
+            .unwrap();
+        assert_eq!(
+            evaluation_table(&steps),
+            "\
+#  Token            Action              Stack
+-  ---------------  ------------------  ------------
+0  1                push                1
+1  jump-if-true(5)  jump to 5           1, (skipped)
+5  ||               1 || (skipped) = 1  1
+"
+        );
+    }
+    #[test]
+    fn test_eval_actions() {
+        let postfix =
+            PostExpression::try_from("-sqrt(x) + (0 ? 1 : 2)".parse::<Expression>().unwrap())
+                .unwrap();
+        let mut env = Environment::default();
+        env.set("x", Value::Int(4));
+        let mut steps = Vec::new();
+        postfix.eval_traced(&env, &mut steps).unwrap();
+        let shown = shown_stacks(&steps);
+        let mut before: &[String] = &[];
+        let actions: Vec<_> = steps
+            .iter()
+            .zip(&shown)
+            .map(|(step, stack)| {
+                let action = eval_action(before, step);
+                before = stack;
+                action
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "x = 4",
+                "sqrt(4) = 2",
+                "neg 2 = -2",
+                "push",
+                "jump to 7",
+                "push",
+                "0 ? (skipped) : 2 = 2",
+                "-2 + 2 = 0",
+            ]
+        );
+    }
+}
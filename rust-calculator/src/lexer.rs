@@ -0,0 +1,414 @@
+use crate::registry::{self, Registry};
+use crate::span::{Span, Spanned};
+use crate::tokens::{MathEquationErr, MathToken};
+use crate::value::Value;
+use log::debug;
+use num_traits::ToPrimitive;
+use std::{iter::Peekable, str::CharIndices};
+
+/// Character level tokenizer for infix math expressions.
+///
+/// Whitespace between tokens is optional, so `2*(3-1)` and `2 * ( 3 - 1 )` produce the same tokens.
+/// Every token comes with the byte span it was read from.
+/// Operators are those of `registry`, the longest symbol wins, so `<<` is never read as `<` `<`.
+pub struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    registry: &'a Registry,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str, registry: &'a Registry) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+            registry,
+        }
+    }
+
+    /// Consume characters while `pred` holds, returning the byte offset just past the last one.
+    /// `end` is the offset to return if nothing is consumed.
+    fn take_while<F>(&mut self, mut end: usize, pred: F) -> usize
+    where
+        F: Fn(char) -> bool,
+    {
+        while let Some(&(idx, c)) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            end = idx + c.len_utf8();
+            self.chars.next();
+        }
+        end
+    }
+
+    /// Read a number literal whose first (already consumed) character is at byte offset `start`.
+    /// Accepts integers, decimals (`1.5`, `.5`), an exponent suffix (`1e-3`)
+    /// and integers in other bases (`0xff`, `0b1010`, `0o17`).
+    fn number(&mut self, start: usize) -> (Result<MathToken, MathEquationErr>, usize) {
+        let radix = self.source[start..]
+            .get(..2)
+            .is_some_and(|prefix| matches!(prefix, "0x" | "0X" | "0b" | "0B" | "0o" | "0O"));
+        if radix {
+            self.chars.next();
+            let end = self.take_while(start + 2, |c| c.is_ascii_alphanumeric() || c == '_');
+            return (self.source[start..end].parse::<MathToken>(), end);
+        }
+        let mut end = start + 1;
+        // a `.` followed by another is a range, as in `1..10`
+        while let Some(&(idx, c)) = self.chars.peek() {
+            if !(c.is_ascii_digit() || c == '.' && !self.source[idx + 1..].starts_with('.')) {
+                break;
+            }
+            end = idx + 1;
+            self.chars.next();
+        }
+        if let Some(&(idx, 'e' | 'E')) = self.chars.peek() {
+            let rest = &self.source[idx + 1..];
+            let digits = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+            if digits.starts_with(|c: char| c.is_ascii_digit()) {
+                self.chars.next();
+                if rest.starts_with(['+', '-']) {
+                    self.chars.next();
+                }
+                end = self.take_while(idx + 1, |c| c.is_ascii_digit());
+            }
+        }
+        let literal = &self.source[start..end];
+        debug!("Lexed number literal '{}'", literal);
+        let number = literal.parse::<MathToken>();
+        // a trailing `i` makes the number imaginary, unless it starts a longer name
+        let mut rest = self.source[end..].chars();
+        if rest.next() == Some('i') && !rest.next().is_some_and(|c| c.is_alphanumeric() || c == '_')
+        {
+            self.chars.next();
+            let imaginary = number.map(|tok| match tok {
+                MathToken::IntOperand(i) => MathToken::ImaginaryOperand(Value::Int(i).as_float()),
+                MathToken::FloatOperand(f) => MathToken::ImaginaryOperand(f),
+                MathToken::BigIntOperand(n) => {
+                    MathToken::ImaginaryOperand(n.to_f64().unwrap_or(f64::INFINITY))
+                }
+                _ => unreachable!("Decimal literals are integers or floats"),
+            });
+            return (imaginary, end + 1);
+        }
+        (number, end)
+    }
+
+    /// Read a name whose first (already consumed) character is at byte offset `start`.
+    /// Names of operators like `xor` are operators, a name directly followed by `(` is a
+    /// function call, otherwise it is a variable.
+    fn word(&mut self, start: usize, first: char) -> (MathToken, usize) {
+        let end = self.take_while(start + first.len_utf8(), |c| {
+            c.is_alphanumeric() || c == '_'
+        });
+        let name = self.source[start..end].to_string();
+        let tok = if let Some(op) = self.registry.operator(&name) {
+            MathToken::Operator(op)
+        } else if self.source[end..].trim_start().starts_with('(') {
+            MathToken::Function(name)
+        } else {
+            MathToken::Identifier(name)
+        };
+        (tok, end)
+    }
+
+    /// The longest operator symbol made of punctuation at byte offset `start`.
+    fn symbol(&self, start: usize) -> Option<&'a str> {
+        let rest = &self.source[start..];
+        self.registry
+            .symbols()
+            .filter(|symbol| !registry::is_word(symbol) && rest.starts_with(symbol))
+            .max_by_key(|symbol| symbol.len())
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<(MathToken, Span), Spanned<MathEquationErr>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, c) = self.chars.find(|(_, c)| !c.is_whitespace())?;
+        let single = start + c.len_utf8();
+        let (tok, end) = match c {
+            '(' => (Ok(MathToken::Parens(true)), single),
+            ')' => (Ok(MathToken::Parens(false)), single),
+            '[' => (Ok(MathToken::Brackets(true)), single),
+            ']' => (Ok(MathToken::Brackets(false)), single),
+            c if c.is_ascii_digit() || c == '.' && !self.source[single..].starts_with('.') => {
+                self.number(start)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let (tok, end) = self.word(start, c);
+                (Ok(tok), end)
+            }
+            c => match self.symbol(start) {
+                Some(symbol) => {
+                    for _ in symbol.chars().skip(1) {
+                        self.chars.next();
+                    }
+                    let op = self
+                        .registry
+                        .operator(symbol)
+                        .expect("Symbols come from the registry");
+                    (Ok(MathToken::Operator(op)), start + symbol.len())
+                }
+                None => (c.to_string().parse::<MathToken>(), single),
+            },
+        };
+        let span = Span::new(start, end);
+        Some(
+            tok.map(|tok| (tok, span))
+                .map_err(|e| Spanned::new(e, span)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::{Associativity, OperatorType};
+    use crate::value::Value;
+
+    fn lex(input: &str) -> Vec<MathToken> {
+        Lexer::new(input, &Registry::default())
+            .map(|tok| tok.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_no_whitespace() {
+        assert_eq!(
+            lex("3+4"),
+            vec![
+                MathToken::IntOperand(3),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(4),
+            ]
+        );
+    }
+    #[test]
+    fn test_nested_parens() {
+        assert_eq!(
+            lex("((1 + 2))"),
+            vec![
+                MathToken::Parens(true),
+                MathToken::Parens(true),
+                MathToken::IntOperand(1),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(2),
+                MathToken::Parens(false),
+                MathToken::Parens(false),
+            ]
+        );
+    }
+    #[test]
+    fn test_multi_char_numbers() {
+        assert_eq!(
+            lex("12.5*100"),
+            vec![
+                MathToken::FloatOperand(12.5),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::IntOperand(100),
+            ]
+        );
+        assert_eq!(lex("1e3"), vec![MathToken::FloatOperand(1000.0)]);
+        assert_eq!(lex("2.5E-1"), vec![MathToken::FloatOperand(0.25)]);
+    }
+    #[test]
+    fn test_lists_and_ranges() {
+        assert_eq!(
+            lex("[1..2.5]"),
+            vec![
+                MathToken::Brackets(true),
+                MathToken::IntOperand(1),
+                MathToken::Operator(OperatorType::Range),
+                MathToken::FloatOperand(2.5),
+                MathToken::Brackets(false),
+            ]
+        );
+        assert_eq!(
+            lex("..5"),
+            vec![
+                MathToken::Operator(OperatorType::Range),
+                MathToken::IntOperand(5)
+            ]
+        );
+    }
+    #[test]
+    fn test_imaginary() {
+        assert_eq!(
+            lex("2i+1.5i*i"),
+            vec![
+                MathToken::ImaginaryOperand(2.0),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::ImaginaryOperand(1.5),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::Identifier("i".into()),
+            ]
+        );
+        assert_eq!(
+            lex("2in"),
+            vec![MathToken::IntOperand(2), MathToken::Identifier("in".into())]
+        );
+    }
+    #[test]
+    fn test_radix_literals() {
+        assert_eq!(
+            lex("0xff+0b1010_1010*0o17"),
+            vec![
+                MathToken::IntOperand(255),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(170),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::IntOperand(15),
+            ]
+        );
+        assert_eq!(
+            lex("0xFFFF_FFFF_FFFF_FFFF"),
+            vec![MathToken::BigIntOperand(u64::MAX.into())]
+        );
+        assert!(Lexer::new("0x", &Registry::default())
+            .next()
+            .unwrap()
+            .is_err());
+        assert!(Lexer::new("0b102", &Registry::default())
+            .next()
+            .unwrap()
+            .is_err());
+    }
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(
+            lex("~a<<2 xor b>>1&c|d%2"),
+            vec![
+                MathToken::Operator(OperatorType::BitNot),
+                MathToken::Identifier("a".into()),
+                MathToken::Operator(OperatorType::Shl),
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Xor),
+                MathToken::Identifier("b".into()),
+                MathToken::Operator(OperatorType::Shr),
+                MathToken::IntOperand(1),
+                MathToken::Operator(OperatorType::BitAnd),
+                MathToken::Identifier("c".into()),
+                MathToken::Operator(OperatorType::BitOr),
+                MathToken::Identifier("d".into()),
+                MathToken::Operator(OperatorType::Rem),
+                MathToken::IntOperand(2),
+            ]
+        );
+    }
+    #[test]
+    fn test_comparison_operators() {
+        let ops = |input: &str| -> Vec<MathToken> {
+            lex(input)
+                .into_iter()
+                .filter(|tok| *tok != MathToken::Identifier("a".into()))
+                .collect()
+        };
+        assert_eq!(
+            ops("a<a<=a<<a==a!=!a"),
+            vec![
+                MathToken::Operator(OperatorType::Lt),
+                MathToken::Operator(OperatorType::Le),
+                MathToken::Operator(OperatorType::Shl),
+                MathToken::Operator(OperatorType::Eq),
+                MathToken::Operator(OperatorType::Ne),
+                MathToken::Operator(OperatorType::Not),
+            ]
+        );
+        assert_eq!(
+            ops("a&&a&a||a?a:a"),
+            vec![
+                MathToken::Operator(OperatorType::And),
+                MathToken::Operator(OperatorType::BitAnd),
+                MathToken::Operator(OperatorType::Or),
+                MathToken::Question,
+                MathToken::Colon,
+            ]
+        );
+        assert_eq!(lex("x=1")[1], MathToken::Assign);
+    }
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            lex("2*ans"),
+            vec![
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::Identifier("ans".into()),
+            ]
+        );
+        assert_eq!(
+            lex("x_1=2"),
+            vec![
+                MathToken::Identifier("x_1".into()),
+                MathToken::Assign,
+                MathToken::IntOperand(2),
+            ]
+        );
+    }
+    #[test]
+    fn test_function_call() {
+        assert_eq!(
+            lex("min(a,2)"),
+            vec![
+                MathToken::Function("min".into()),
+                MathToken::Parens(true),
+                MathToken::Identifier("a".into()),
+                MathToken::Comma,
+                MathToken::IntOperand(2),
+                MathToken::Parens(false),
+            ]
+        );
+        assert_eq!(lex("sqrt (2)")[0], MathToken::Function("sqrt".into()));
+    }
+    #[test]
+    fn test_registered_operators() {
+        let mut registry = Registry::default();
+        let first = |args: &[Value]| Ok(args[0].clone());
+        registry
+            .add_operator("**", 2, 110, Associativity::Right, first)
+            .unwrap();
+        registry
+            .add_operator("mod", 2, 90, Associativity::Left, first)
+            .unwrap();
+        let tokens: Vec<_> = Lexer::new("2**3 mod m*-1", &registry)
+            .map(|tok| tok.unwrap().0.to_string())
+            .collect();
+        assert_eq!(tokens, ["2", "**", "3", "mod", "m", "*", "-", "1"]);
+        // without the registry `**` is two multiplications
+        assert_eq!(
+            lex("2**3")[1..3],
+            [
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::Operator(OperatorType::Mul)
+            ]
+        );
+        assert_eq!(lex("mod")[0], MathToken::Identifier("mod".into()));
+    }
+    #[test]
+    fn test_invalid_character() {
+        let err = Lexer::new("3 $ 4", &Registry::default()).collect::<Result<Vec<_>, _>>();
+        assert_eq!(err.unwrap_err().span, Span::new(2, 3));
+        let err = Lexer::new("1 + 1.2.3", &Registry::default()).collect::<Result<Vec<_>, _>>();
+        assert_eq!(err.unwrap_err().span, Span::new(4, 9));
+    }
+    #[test]
+    fn test_spans() {
+        let spans: Vec<Span> = Lexer::new(" sqrt(x1) *2.5e3", &Registry::default())
+            .map(|tok| tok.unwrap().1)
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(1, 5),
+                Span::new(5, 6),
+                Span::new(6, 8),
+                Span::new(8, 9),
+                Span::new(10, 11),
+                Span::new(11, 16),
+            ]
+        );
+    }
+}
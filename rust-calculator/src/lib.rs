@@ -0,0 +1,227 @@
+//! The calculator behind `oxc`, for evaluating expressions inside other programs.
+//!
+//! Input is tokenized into an [`Expression`], converted to a [`PostExpression`] with the
+//! shunting-yard algorithm and evaluated against an [`Environment`] that holds variables and
+//! user defined functions.
+//!
+//! ```
+//! use rust_calculator::{compile, evaluate, Environment, Syntax, Value};
+//!
+//! let mut env = Environment::default();
+//! env.set("cores", Value::Int(8));
+//! let workers = evaluate("workers = max(2, cores - 1)", Syntax::Infix, &mut env).unwrap();
+//! assert_eq!(workers, Some(Value::Int(7)));
+//!
+//! // compile once, evaluate with different variables
+//! let threads = compile("workers * 2", env.registry()).unwrap();
+//! assert_eq!(threads.eval(&env).unwrap(), Value::Int(14));
+//! env.set("workers", Value::Int(3));
+//! assert_eq!(threads.eval(&env).unwrap(), Value::Int(6));
+//! ```
+use log::debug;
+use thiserror::Error;
+
+pub mod ast;
+pub mod builtins;
+pub mod bytecode;
+pub mod environment;
+pub mod explain;
+mod lexer;
+pub mod plot;
+pub mod postfix;
+pub mod registry;
+pub mod solve;
+pub mod span;
+pub mod statement;
+pub mod symbolic;
+pub mod tokens;
+pub mod units;
+pub mod value;
+
+pub use bytecode::{Program, Vm};
+pub use environment::{Environment, UserFunction};
+pub use postfix::{EvalError, PostExpression, PostExpressionError};
+pub use registry::{Registry, RegistryError};
+pub use solve::{Equation, SolveError};
+pub use span::{Span, Spanned};
+pub use statement::{Statement, Syntax};
+pub use symbolic::SymbolicError;
+pub use tokens::{Associativity, Expression, MathEquationErr};
+pub use value::{Arithmetic, Format, Value};
+
+/// Anything that can go wrong between reading an expression and its result.
+#[derive(Error, Debug)]
+pub enum OxcError {
+    #[error("Invalid equation - {0}")]
+    Parse(#[from] Spanned<MathEquationErr>),
+    #[error("{0}")]
+    Postfix(#[from] Spanned<PostExpressionError>),
+    #[error("Failed to evaluate - {0}")]
+    Eval(#[from] Spanned<EvalError>),
+}
+
+impl OxcError {
+    /// Part of the input the error is about.
+    #[must_use]
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::Parse(e) => e.span,
+            Self::Postfix(e) => e.span,
+            Self::Eval(e) => e.span,
+        }
+    }
+
+    /// The error message with the part of `source` it is about underlined.
+    #[must_use]
+    pub fn report(&self, source: &str) -> String {
+        format!("{self}\n{}", self.span().underline(source))
+    }
+}
+
+/// Parse one expression, assignment or function definition written in `syntax`,
+/// reading the operators of `registry`.
+///
+/// # Errors
+/// When the expression can't be tokenized or converted to postfix.
+pub fn parse(
+    math_expression: &str,
+    syntax: Syntax,
+    registry: &Registry,
+) -> Result<Statement, OxcError> {
+    debug!("staring evaluation of input '{}'", math_expression);
+    let eq = Expression::parse_with(math_expression, registry)?;
+    debug!("Valid equation given - {:?}", eq);
+    Ok(Statement::compile(eq, syntax)?)
+}
+
+/// Compile an infix expression to postfix, which can be evaluated any number of times.
+///
+/// # Errors
+/// When the expression can't be tokenized or converted to postfix.
+pub fn compile(math_expression: &str, registry: &Registry) -> Result<PostExpression, OxcError> {
+    let eq = Expression::parse_with(math_expression, registry)?;
+    Ok(PostExpression::try_from(eq)?)
+}
+
+/// Evaluate one expression, assignment or function definition against `env`,
+/// with the operators and functions of its registry. Function definitions have no result.
+///
+/// # Errors
+/// When the expression can't be parsed or evaluated, or a definition uses a name that
+/// isn't defined.
+pub fn evaluate(
+    math_expression: &str,
+    syntax: Syntax,
+    env: &mut Environment,
+) -> Result<Option<Value>, OxcError> {
+    Ok(parse(math_expression, syntax, env.registry())?.execute(env)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_rational::BigRational;
+
+    #[test]
+    fn test_evaluate_shares_env() {
+        let mut env = Environment::new(Arithmetic::Exact);
+        let third = Value::Rational(BigRational::new(1.into(), 3.into()));
+        assert_eq!(
+            evaluate("third = 1/3", Syntax::Infix, &mut env).unwrap(),
+            Some(third.clone())
+        );
+        assert_eq!(env.get("third"), Some(third));
+        assert_eq!(
+            evaluate("f(x) = x * 3", Syntax::Infix, &mut env).unwrap(),
+            None
+        );
+        assert_eq!(
+            evaluate("third f(1)", Syntax::Rpn, &mut env).unwrap(),
+            Some(Value::Rational(BigRational::from_integer(1.into())))
+        );
+    }
+    #[test]
+    fn test_compile() {
+        let postfix = compile("2 ^ n", &Registry::default()).unwrap();
+        assert_eq!(postfix.to_string(), "2 n ^");
+        let mut env = Environment::default();
+        env.set("n", Value::Int(10));
+        assert_eq!(postfix.eval(&env), Ok(Value::Int(1024)));
+        assert!(matches!(
+            compile("x = 1", &Registry::default()),
+            Err(OxcError::Postfix(_))
+        ));
+    }
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::default();
+        registry
+            .add_operator("**", 2, 110, Associativity::Right, |args| {
+                tokens::OperatorType::Pow.apply(args[0].clone(), args[1].clone())
+            })
+            .unwrap();
+        // a remainder that is never negative, binding like `%`
+        registry
+            .add_operator("mod", 2, 90, Associativity::Left, |args| {
+                Ok(Value::Float(
+                    args[0].as_float().rem_euclid(args[1].as_float()),
+                ))
+            })
+            .unwrap();
+        registry
+            .add_operator("√", 1, 100, Associativity::Right, |args| {
+                Ok(Value::Float(args[0].as_float().sqrt()))
+            })
+            .unwrap();
+        registry
+            .add_function("hypot", builtins::Arity::Exact(2), |args| {
+                Ok(Value::Float(args[0].as_float().hypot(args[1].as_float())))
+            })
+            .unwrap();
+        let mut env = Environment::default().with_registry(registry);
+        let mut eval = |input| evaluate(input, Syntax::Infix, &mut env).unwrap();
+        assert_eq!(eval("2 ** 3 ** 2"), Some(Value::Int(512)));
+        assert_eq!(eval("-2 ** 2"), Some(Value::Int(-4)));
+        assert_eq!(eval("-7 mod 3 + 1"), Some(Value::Float(3.0)));
+        assert_eq!(eval("√16 * 2"), Some(Value::Float(8.0)));
+        assert_eq!(eval("f(a, b) = hypot(a, b) mod 4"), None);
+        assert_eq!(eval("f(3, 4)"), Some(Value::Float(1.0)));
+        assert_eq!(
+            evaluate("3 4 ** 4 hypot(2)", Syntax::Rpn, &mut env).unwrap(),
+            Some(Value::Float(81.0_f64.hypot(4.0)))
+        );
+        let shown = parse("√(a mod b) ** 2", Syntax::Infix, env.registry()).unwrap();
+        let Statement::Expression(postfix) = shown else {
+            panic!("Not an assignment or definition");
+        };
+        assert_eq!(postfix.to_string(), "a b mod 2 ** √");
+        assert_eq!(
+            ast::Ast::try_from(&postfix).unwrap().to_infix(),
+            "√(a mod b) ** 2"
+        );
+        let err = evaluate("hypot(x) = x", Syntax::Infix, &mut env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to evaluate - `hypot` is a registered function"
+        );
+        let err = evaluate("hypot(1)", Syntax::Infix, &mut env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to evaluate - `hypot` takes 2 arguments, but 1 were given"
+        );
+        // other environments don't know the operators
+        assert!(compile("2 ** 3", &Registry::default()).is_err());
+    }
+    #[test]
+    fn test_errors() {
+        let mut env = Environment::default();
+        let err = evaluate("1 + $", Syntax::Infix, &mut env).unwrap_err();
+        assert!(matches!(err, OxcError::Parse(_)));
+        assert_eq!(err.span(), Span::new(4, 5));
+        let err = evaluate("1 + nope", Syntax::Infix, &mut env).unwrap_err();
+        assert_eq!(
+            err.report("1 + nope"),
+            "Failed to evaluate - Undefined variable `nope`\n  1 + nope\n      ^~~~"
+        );
+    }
+}
@@ -1,12 +1,281 @@
 use clap::Parser;
 use env_logger::Builder;
+use log::LevelFilter;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::iter;
+use std::path::PathBuf;
+use std::process;
 
-use log::{debug, error, LevelFilter};
-use postfix::PostExpression;
+use args::{Command, Notation};
+use batch::Batch;
+use repl::Session;
+use rust_calculator::ast::Ast;
+use rust_calculator::explain;
+use rust_calculator::plot::{Chart, Style};
+use rust_calculator::solve::{Method, Start};
+use rust_calculator::tokens::MathToken;
+use rust_calculator::value::Width;
+use rust_calculator::{
+    compile, parse, Arithmetic, Environment, Equation, Expression, Format, OxcError,
+    PostExpression, PostExpressionError, Registry, Spanned, Statement, Syntax, Value,
+};
 
 mod args;
-mod postfix;
-mod tokens;
+mod batch;
+mod repl;
+
+fn render(
+    postfix: &PostExpression,
+    notation: Notation,
+) -> Result<String, Spanned<PostExpressionError>> {
+    if notation == Notation::Postfix {
+        return Ok(postfix.to_string());
+    }
+    let tree = Ast::try_from(postfix)?;
+    Ok(match notation {
+        Notation::Ast => tree.to_tree(),
+        Notation::Infix => tree.to_infix(),
+        Notation::Parens => tree.to_parenthesized(),
+        Notation::Prefix => tree.to_prefix(),
+        Notation::Postfix => unreachable!("Handled above"),
+    })
+}
+
+/// Show a parsed statement in the given notation, the left hand side of assignments
+/// and definitions is kept as written.
+fn show(statement: &Statement, notation: Notation) -> Result<String, Spanned<PostExpressionError>> {
+    Ok(match statement {
+        Statement::Expression(expr) => render(expr, notation)?,
+        Statement::Assignment { name, value } => format!("{name} = {}", render(value, notation)?),
+        Statement::Definition { name, function } => format!(
+            "{name}({}) = {}",
+            function.params.join(", "),
+            render(&function.body, notation)?
+        ),
+    })
+}
+
+/// Evaluate an expression given on the command line, printing it in `notation` first if asked.
+fn run_once(
+    math_expression: &str,
+    syntax: Syntax,
+    notation: Option<Notation>,
+    env: &mut Environment,
+) -> Result<Option<Value>, OxcError> {
+    let statement = parse(math_expression, syntax, env.registry())?;
+    if let Some(notation) = notation {
+        println!("{}", show(&statement, notation)?);
+    }
+    Ok(statement.execute(env)?)
+}
+
+/// Evaluate the lines of each file in turn, `-` being stdin, exiting with status 1 if any failed.
+fn run_batch(files: &[PathBuf], keep_going: bool, session: Session) {
+    let mut batch = Batch::new(session, keep_going);
+    let (mut out, mut err) = (io::stdout().lock(), io::stderr().lock());
+    for path in files {
+        let (name, input): (_, Box<dyn BufRead>) = if path.as_os_str() == "-" {
+            ("stdin".to_string(), Box::new(io::stdin().lock()))
+        } else {
+            let name = path.display().to_string();
+            match File::open(path) {
+                Ok(file) => (name, Box::new(BufReader::new(file))),
+                Err(e) => {
+                    eprintln!("Error: Failed to open {name} - {e}");
+                    process::exit(1);
+                }
+            }
+        };
+        match batch.run(&name, input, &mut out, &mut err) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("Error: Failed to read {name} - {e}");
+                process::exit(1);
+            }
+        }
+    }
+    if batch.failures() > 0 {
+        process::exit(1);
+    }
+}
+
+/// Evaluate an infix expression, printing the steps of its conversion to postfix and its
+/// evaluation along the way.
+fn run_explained(math_expression: &str, env: &Environment) -> Result<Value, OxcError> {
+    let eq = Expression::parse_with(math_expression, env.registry())?;
+    if eq.tokens.contains(&MathToken::Assign) {
+        eprintln!("Error: Only expressions can be explained, not assignments or definitions");
+        process::exit(1);
+    }
+    let mut steps = Vec::new();
+    let converted = PostExpression::try_from_traced(eq, &mut steps);
+    println!(
+        "Shunting-yard conversion:\n{}",
+        explain::conversion_table(&steps)
+    );
+    let postfix = converted?;
+    println!("Postfix: {}\n", explain::postfix_with_jumps(&postfix));
+    let mut steps = Vec::new();
+    let result = postfix.eval_traced(env, &mut steps);
+    println!("Evaluation:\n{}", explain::evaluation_table(&steps));
+    Ok(result?)
+}
+
+/// Differentiate an expression with respect to `variable`, or simplify it without one,
+/// printing the result as compact infix.
+fn run_symbolic(expression: &str, variable: Option<&str>, syntax: Syntax, registry: &Registry) {
+    let tree = match parse(expression, syntax, registry) {
+        Ok(Statement::Expression(postfix)) => Ast::try_from(&postfix).map_err(OxcError::from),
+        Ok(_) => {
+            eprintln!("Error: Only expressions can be differentiated or simplified");
+            process::exit(1);
+        }
+        Err(e) => Err(e),
+    };
+    let tree = tree.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e.report(expression));
+        process::exit(1);
+    });
+    let result = variable.map_or_else(|| Ok(tree.simplify()), |variable| tree.derivative(variable));
+    match result {
+        Ok(result) => println!("{}", result.to_compact()),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Where `oxc solve` looks for roots without `--interval` or `--guess`.
+const DEFAULT_INTERVAL: Start = Start::Interval(-10.0, 10.0);
+
+/// Print the roots of an equation with their residual and iteration count, every root of
+/// an interval or the one nearest a guess.
+fn run_solve(source: &str, unknown: &str, method: Method, start: Option<Start>, env: &Environment) {
+    let mut equation = Equation::new(source, unknown, env).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e.report(source));
+        process::exit(1);
+    });
+    let roots = match start.unwrap_or(DEFAULT_INTERVAL) {
+        Start::Interval(a, b) => match equation.roots(method, a, b) {
+            Ok(roots) if roots.is_empty() => Err(format!("No roots between {a} and {b}")),
+            Ok(roots) => Ok(roots),
+            Err(e) => Err(e.to_string()),
+        },
+        start @ Start::Guess(_) => equation
+            .solve(method, start)
+            .map(|root| vec![root])
+            .map_err(|e| e.to_string()),
+    };
+    match roots {
+        Ok(roots) => {
+            for root in roots {
+                println!(
+                    "{unknown} = {} (residual {:.1e}, {} iterations)",
+                    root.x, root.residual, root.iterations
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Settings of `oxc plot`.
+struct PlotArgs {
+    variable: String,
+    from: f64,
+    to: f64,
+    columns: usize,
+    rows: usize,
+    style: Style,
+}
+
+/// Draw `expressions` on one chart, in color when printing to a terminal that allows it.
+fn run_plot(expressions: &[String], args: &PlotArgs, env: &Environment) {
+    if args.from >= args.to || !args.from.is_finite() || !args.to.is_finite() {
+        eprintln!("Error: `--from` has to be a number below `--to`");
+        process::exit(1);
+    }
+    if !(args.to - args.from).is_finite() {
+        eprintln!("Error: `--from` and `--to` are too far apart to plot");
+        process::exit(1);
+    }
+    let color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut chart = Chart::new(&args.variable, args.from, args.to)
+        .with_size(args.columns, args.rows)
+        .with_style(args.style)
+        .with_color(color);
+    for expression in expressions {
+        let added = compile(expression, env.registry())
+            .and_then(|postfix| Ok(chart.add(expression, &postfix, env)?));
+        match added {
+            Ok(0) => {
+                eprintln!(
+                    "Error: `{expression}` has no real value between {} and {}",
+                    args.from, args.to
+                );
+                process::exit(1);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error: {}", e.report(expression));
+                process::exit(1);
+            }
+        }
+    }
+    println!("{}", chart.render());
+}
+
+/// Run a subcommand, which works on the expression it is given instead of evaluating it.
+fn run_command(command: Command, syntax: Syntax, env: &Environment) {
+    match command {
+        Command::Diff {
+            expression,
+            variable,
+        } => run_symbolic(&expression, Some(&variable), syntax, env.registry()),
+        Command::Simplify { expression } => {
+            run_symbolic(&expression, None, syntax, env.registry());
+        }
+        Command::Solve {
+            equation,
+            variable,
+            method,
+            interval,
+            guess,
+        } => {
+            let start = interval
+                .map(|(a, b)| Start::Interval(a, b))
+                .or_else(|| guess.map(Start::Guess));
+            run_solve(&equation, &variable, method, start, env);
+        }
+        Command::Plot {
+            expression,
+            expressions,
+            variable,
+            from,
+            to,
+            columns,
+            rows,
+            style,
+        } => {
+            let args = PlotArgs {
+                variable,
+                from,
+                to,
+                columns,
+                rows,
+                style,
+            };
+            let expressions: Vec<_> = iter::once(expression).chain(expressions).collect();
+            run_plot(&expressions, &args, env);
+        }
+    }
+}
 
 fn main() {
     let cli = args::Cli::parse();
@@ -17,18 +286,99 @@ fn main() {
         3.. => Builder::new().filter_level(LevelFilter::max()).init(),
     }
 
-    debug!("staring evaluation of input '{}'", cli.math_expression);
-    let eq = cli
-        .math_expression
-        .parse::<tokens::Expression>()
-        .expect("Valid equation");
-    debug!("Valid equation given - {:?}", eq);
-    let postfix = match PostExpression::try_from(eq) {
-        Ok(x) => x,
-        Err(e) => {
-            error!("{}", e);
+    let arithmetic = match (cli.width, cli.exact) {
+        (Some(width), _) => Arithmetic::Fixed {
+            width,
+            wrapping: cli.wrapping,
+        },
+        (None, true) => Arithmetic::Exact,
+        (None, false) => Arithmetic::Standard,
+    };
+    let mut env = Environment::new(arithmetic);
+    for (name, value) in cli.vars {
+        match arithmetic
+            .literal(value)
+            .and_then(|value| arithmetic.check(value, || name.clone()))
+        {
+            Ok(value) => env.set(name, value),
+            Err(e) => {
+                eprintln!("Error: Invalid value for `{name}` - {e}");
+                process::exit(1);
+            }
+        }
+    }
+    let format = Format {
+        digits: cli.digits,
+        polar: cli.polar,
+        base: cli.base,
+        twos_complement: cli
+            .twos_complement
+            .then(|| cli.width.map_or(64, Width::bits)),
+    };
+
+    let syntax = if cli.rpn { Syntax::Rpn } else { Syntax::Infix };
+    if let Some(command) = cli.command {
+        run_command(command, syntax, &env);
+        return;
+    }
+
+    // piped input is evaluated line by line rather than starting an interactive session
+    let files = if cli.files.is_empty()
+        && cli.math_expression.is_none()
+        && !cli.repl
+        && !io::stdin().is_terminal()
+    {
+        vec![PathBuf::from("-")]
+    } else {
+        cli.files
+    };
+    if !files.is_empty() {
+        let session = Session::new(env, format).with_syntax(syntax);
+        run_batch(&files, cli.keep_going, session);
+        return;
+    }
+
+    let math_expression = match cli.math_expression {
+        Some(expr) if !cli.repl => expr,
+        _ => {
+            if let Err(e) = repl::run(Session::new(env, format).with_syntax(syntax)) {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
             return;
         }
     };
-    println!("Result: {}", postfix.eval());
+
+    if let Some(notation) = cli.emit {
+        match parse(&math_expression, syntax, env.registry())
+            .and_then(|statement| Ok(show(&statement, notation)?))
+        {
+            Ok(shown) => println!("{shown}"),
+            Err(e) => {
+                eprintln!("Error: {}", e.report(&math_expression));
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let result = if cli.explain {
+        run_explained(&math_expression, &env).map(Some)
+    } else {
+        run_once(&math_expression, syntax, cli.show, &mut env)
+    };
+    match result {
+        Ok(Some(result)) => {
+            println!("Result: {}", format.show(&result));
+            // like `test`, a true (non zero) result succeeds
+            if cli.exit_status && result.is_zero() {
+                process::exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e.report(&math_expression));
+            process::exit(if cli.exit_status { 2 } else { 1 });
+        }
+    }
 }
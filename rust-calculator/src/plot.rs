@@ -0,0 +1,409 @@
+//! Terminal charts of expressions in one variable.
+use crate::bytecode::{Program, Vm};
+use crate::environment::Environment;
+use crate::postfix::{EvalError, PostExpression};
+use crate::span::Spanned;
+use crate::value::Value;
+use clap::ValueEnum;
+use std::fmt::{self, Write};
+
+/// Characters a chart is drawn with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Style {
+    /// Braille dots, 2 across and 4 down in each character
+    #[default]
+    Braille,
+    /// Half blocks, 1 across and 2 down in each character
+    Blocks,
+}
+
+impl Style {
+    /// Dots across and down in one character.
+    const fn dots(self) -> (usize, usize) {
+        match self {
+            Self::Braille => (2, 4),
+            Self::Blocks => (1, 2),
+        }
+    }
+
+    /// The character with the dots of `bits` set, numbered down each column in turn.
+    fn glyph(self, bits: u8) -> char {
+        match self {
+            // braille numbers the bottom row last, after both columns above it
+            Self::Braille => {
+                const DOTS: [u8; 8] = [0x01, 0x02, 0x04, 0x40, 0x08, 0x10, 0x20, 0x80];
+                let code = (0..8)
+                    .filter(|dot| bits & (1 << dot) != 0)
+                    .fold(0, |code, dot| code | u32::from(DOTS[dot]));
+                char::from_u32(0x2800 + code).expect("Braille patterns are characters")
+            }
+            Self::Blocks => match bits & 0b11 {
+                0b01 => '▀',
+                0b10 => '▄',
+                _ => '█',
+            },
+        }
+    }
+}
+
+/// ANSI colors the curves are drawn in, in turn.
+const COLORS: [u8; 6] = [32, 36, 35, 33, 34, 31];
+
+/// Chart sizes in characters, beyond these there is nothing to see or the padding overflows.
+pub const SIZES: std::ops::RangeInclusive<usize> = 2..=1000;
+
+#[derive(Debug)]
+struct Curve {
+    label: String,
+    /// Value at each column of dots, `None` where it failed or isn't a real number.
+    ys: Vec<Option<f64>>,
+}
+
+/// A chart of curves over `from..=to`, sampled once for each column of dots.
+/// The y axis is scaled to fit every curve.
+#[derive(Debug)]
+pub struct Chart {
+    variable: String,
+    from: f64,
+    to: f64,
+    columns: usize,
+    rows: usize,
+    style: Style,
+    color: bool,
+    curves: Vec<Curve>,
+}
+
+impl Chart {
+    /// A chart of `variable` from `from` to `to`, 72 characters wide and 20 high.
+    #[must_use]
+    pub fn new(variable: &str, from: f64, to: f64) -> Self {
+        Self {
+            variable: variable.to_string(),
+            from,
+            to,
+            columns: 72,
+            rows: 20,
+            style: Style::default(),
+            color: false,
+            curves: Vec::new(),
+        }
+    }
+
+    /// Size of the plotting area in characters, without the axis labels, clamped to `SIZES`.
+    #[must_use]
+    pub fn with_size(mut self, columns: usize, rows: usize) -> Self {
+        self.columns = columns.clamp(*SIZES.start(), *SIZES.end());
+        self.rows = rows.clamp(*SIZES.start(), *SIZES.end());
+        self
+    }
+
+    #[must_use]
+    pub const fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Draw each curve in its own ANSI color.
+    #[must_use]
+    pub const fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    const fn dot_columns(&self) -> usize {
+        self.columns * self.style.dots().0
+    }
+
+    const fn dot_rows(&self) -> usize {
+        self.rows * self.style.dots().1
+    }
+
+    /// `variable` at each column of dots.
+    fn xs(&self) -> impl Iterator<Item = f64> + '_ {
+        let last = self.dot_columns() - 1;
+        #[allow(clippy::cast_precision_loss)]
+        (0..=last)
+            .map(move |idx| (self.to - self.from).mul_add(idx as f64 / last as f64, self.from))
+    }
+
+    /// Sample `postfix` across the chart, compiled once against `env`, and return how many
+    /// points can be drawn. Points that fail or aren't real numbers are gaps in the curve.
+    ///
+    /// # Errors
+    /// With the first error when no point could be evaluated.
+    pub fn add(
+        &mut self,
+        label: &str,
+        postfix: &PostExpression,
+        env: &Environment,
+    ) -> Result<usize, Spanned<EvalError>> {
+        let program = Program::compile(postfix, env);
+        let mut slots = program.load(env);
+        let slot = program.slot(&self.variable);
+        let mut vm = Vm::default();
+        let mut first_error = None;
+        let ys: Vec<_> = self
+            .xs()
+            .map(|x| {
+                if let Some(slot) = slot {
+                    slots[slot] = Some(Value::Float(x));
+                }
+                match vm.run(&program, env, &slots) {
+                    Ok(y) => Some(y.as_float()).filter(|y| y.is_finite()),
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let points = ys.iter().flatten().count();
+        if let (Some(e), 0) = (first_error, points) {
+            return Err(e);
+        }
+        self.curves.push(Curve {
+            label: label.to_string(),
+            ys,
+        });
+        Ok(points)
+    }
+
+    /// Lowest and highest value of every curve, widened if they are the same.
+    fn y_range(&self) -> (f64, f64) {
+        let ys = self
+            .curves
+            .iter()
+            .flat_map(|curve| curve.ys.iter().flatten());
+        let (low, high) = ys.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &y| {
+            (low.min(y), high.max(y))
+        });
+        if low > high {
+            (-1.0, 1.0)
+        } else if high - low <= f64::EPSILON * high.abs().max(1.0) {
+            let pad = high.abs().max(1.0) / 10.0;
+            (low - pad, high + pad)
+        } else {
+            (low, high)
+        }
+    }
+
+    /// Row of dots `y` falls on, counting down from the top.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn dot_row(&self, y: f64, (low, high): (f64, f64)) -> usize {
+        let last = self.dot_rows() - 1;
+        ((high - y) / (high - low) * last as f64)
+            .round()
+            .clamp(0.0, last as f64) as usize
+    }
+
+    /// Column of dots `x` falls on, if it is on the chart.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn dot_column(&self, x: f64) -> Option<usize> {
+        let last = self.dot_columns() - 1;
+        let column = ((x - self.from) / (self.to - self.from) * last as f64).round();
+        (0.0..=last as f64)
+            .contains(&column)
+            .then_some(column as usize)
+    }
+
+    /// Draw the chart with the y range on the left, the x range below and a legend naming
+    /// each curve, see the `Display` implementation.
+    #[must_use]
+    pub fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Chart {
+    /// The lines `x = 0` and `y = 0` are drawn where they are on the chart, and columns
+    /// where a curve couldn't be evaluated are marked with `×` on the x axis.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (across, down) = self.style.dots();
+        let range = self.y_range();
+        // the dots set in each character and the curve that set one last
+        let mut cells = vec![vec![(0u8, None); self.columns]; self.rows];
+        let mut gaps = vec![false; self.columns];
+        for (idx, curve) in self.curves.iter().enumerate() {
+            let mut previous: Option<usize> = None;
+            for (column, y) in curve.ys.iter().enumerate() {
+                let Some(y) = y else {
+                    gaps[column / across] = true;
+                    previous = None;
+                    continue;
+                };
+                let row = self.dot_row(*y, range);
+                // fill in steep steps, half in the column of each point, so the curve stays connected
+                let mut set = |column: usize, dot_row: usize| {
+                    let cell = &mut cells[dot_row / down][column / across];
+                    cell.0 |= 1 << ((column % across) * down + dot_row % down);
+                    cell.1 = Some(idx);
+                };
+                set(column, row);
+                if let Some(p) = previous {
+                    let mid = p.midpoint(row);
+                    let (before, after) = if p < row {
+                        (p + 1..=mid, mid + 1..=row)
+                    } else {
+                        (mid + 1..=p.saturating_sub(1), row..=mid)
+                    };
+                    before.for_each(|dot_row| set(column - 1, dot_row));
+                    after.for_each(|dot_row| set(column, dot_row));
+                }
+                previous = Some(row);
+            }
+        }
+
+        let zero_row = (range.0..=range.1)
+            .contains(&0.0)
+            .then(|| self.dot_row(0.0, range) / down);
+        let zero_column = self.dot_column(0.0).map(|column| column / across);
+        let labels = [label(range.1), label(range.0)];
+        let margin = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        for (row, cells) in cells.iter().enumerate() {
+            let (tick, side) = match row {
+                0 => (labels[0].as_str(), '┤'),
+                _ if row + 1 == self.rows => (labels[1].as_str(), '┤'),
+                _ => ("", '│'),
+            };
+            write!(f, "{tick:>margin$} {side}")?;
+            for (column, (bits, curve)) in cells.iter().enumerate() {
+                let on_axis = (Some(row) == zero_row, Some(column) == zero_column);
+                match (curve, on_axis) {
+                    (Some(curve), _) => {
+                        let glyph = self.style.glyph(*bits);
+                        if self.color {
+                            let color = COLORS[curve % COLORS.len()];
+                            write!(f, "\x1b[{color}m{glyph}\x1b[0m")?;
+                        } else {
+                            f.write_char(glyph)?;
+                        }
+                    }
+                    (None, (true, true)) => f.write_char('┼')?,
+                    (None, (true, false)) => f.write_char('─')?,
+                    (None, (false, true)) => f.write_char('│')?,
+                    (None, (false, false)) => f.write_char(' ')?,
+                }
+            }
+            f.write_char('\n')?;
+        }
+        write!(f, "{:margin$} └", "")?;
+        for &gap in &gaps {
+            f.write_char(if gap { '×' } else { '─' })?;
+        }
+        let (from, to) = (label(self.from), label(self.to));
+        let width = self
+            .columns
+            .saturating_sub(from.chars().count())
+            .max(to.chars().count() + 1);
+        write!(f, "\n{:margin$}  {from}{to:>width$}", "")?;
+        for (idx, curve) in self.curves.iter().enumerate() {
+            let marker = self.style.glyph(u8::MAX);
+            if self.color {
+                let color = COLORS[idx % COLORS.len()];
+                write!(f, "\n\x1b[{color}m{marker}\x1b[0m {}", curve.label)?;
+            } else {
+                write!(f, "\n{marker} {}", curve.label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A short label for an axis, with at most 3 decimals and no trailing zeros.
+fn label(value: f64) -> String {
+    if value != 0.0 && !(1e-3..1e6).contains(&value.abs()) {
+        return format!("{value:.2e}");
+    }
+    let fixed = format!("{value:.3}");
+    let trimmed = fixed.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" => "0".to_string(),
+        _ => trimmed.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::registry::Registry;
+
+    fn chart(style: Style, columns: usize, rows: usize, expressions: &[&str]) -> String {
+        let env = Environment::default();
+        let mut chart = Chart::new("x", -1.0, 1.0)
+            .with_size(columns, rows)
+            .with_style(style);
+        for expression in expressions {
+            let postfix = compile(expression, &Registry::default()).unwrap();
+            chart.add(expression, &postfix, &env).unwrap();
+        }
+        chart.render()
+    }
+
+    #[test]
+    fn test_glyph() {
+        assert_eq!(Style::Braille.glyph(0), '⠀');
+        assert_eq!(Style::Braille.glyph(0b0000_1000), '⡀');
+        assert_eq!(Style::Braille.glyph(0b0001_0000), '⠈');
+        assert_eq!(Style::Braille.glyph(u8::MAX), '⣿');
+        assert_eq!(Style::Blocks.glyph(0b01), '▀');
+        assert_eq!(Style::Blocks.glyph(0b10), '▄');
+    }
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            chart(Style::Blocks, 4, 2, &["x"]),
+            " 1 ┤  ▄▀\n-1 ┤▄▀┼─\n   └────\n    -1 1\n█ x"
+        );
+    }
+    #[test]
+    fn test_axes() {
+        assert_eq!(
+            chart(Style::Blocks, 5, 3, &["x^2 - 0.5"]),
+            " 0.5 ┤█ │ █\n     │▀▄┼▄▀\n-0.5 ┤ ▀▄▀ \n     └─────\n      -1  1\n█ x^2 - 0.5"
+        );
+    }
+    #[test]
+    fn test_size() {
+        let rendered = chart(Style::Blocks, 0, 100_000, &["x"]);
+        assert_eq!(rendered.lines().count(), 1000 + 3);
+        assert!(rendered.contains("└──\n"), "{rendered}");
+    }
+    #[test]
+    fn test_braille() {
+        assert_eq!(
+            chart(Style::Braille, 2, 2, &["x", "-x"]),
+            " 1 ┤⢣⡜\n-1 ┤⡸⢇\n   └──\n    -1 1\n⣿ x\n⣿ -x"
+        );
+    }
+    #[test]
+    fn test_gaps() {
+        let rendered = chart(Style::Blocks, 4, 2, &["x > 0 ? x : y"]);
+        assert!(rendered.contains("└××──"), "{rendered}");
+        let env = Environment::default();
+        let postfix = compile("y", &Registry::default()).unwrap();
+        let err = Chart::new("x", 0.0, 1.0)
+            .add("y", &postfix, &env)
+            .unwrap_err();
+        assert_eq!(err.error, EvalError::UndefinedVariable("y".into()));
+        let postfix = compile("sqrt(x)", &Registry::default()).unwrap();
+        let points = Chart::new("x", -1.0, -0.5).add("sqrt(x)", &postfix, &env);
+        assert_eq!(points, Ok(0));
+    }
+    #[test]
+    fn test_label() {
+        assert_eq!(label(10.0), "10");
+        assert_eq!(label(-0.25), "-0.25");
+        assert_eq!(label(1.0 / 3.0), "0.333");
+        assert_eq!(label(-0.0001), "-1.00e-4");
+        assert_eq!(label(2.5e7), "2.50e7");
+    }
+}
@@ -1,98 +1,1058 @@
-use crate::tokens::{Expression, MathToken};
+use crate::builtins::{self, Arity};
+use crate::environment::Environment;
+use crate::span::{Span, Spanned};
+use crate::tokens::{Associativity, Expression, MathToken, OperatorType, Series, SkipIf};
+use crate::units;
+use crate::value::Value;
 use log::debug;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::{cmp, fmt};
 use thiserror::Error;
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct PostExpression {
     /// postfix ordering of a given math equation.
     tokens: Vec<MathToken>,
+    /// Where each token came from in the infix input, a call covers its name up to `)`.
+    spans: Vec<Span>,
+}
+
+/// Spans only locate tokens in the input, expressions with the same tokens are equal.
+impl PartialEq for PostExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.tokens == other.tokens
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum PostExpressionError {
-    #[error("Failed to convert infix to postfix - `{0}`")]
+    #[error("Failed to convert infix to postfix - {0}")]
     InvalidExpression(String),
+    #[error("Invalid reverse Polish notation - {0}")]
+    InvalidRpn(String),
+    #[error("`{name}` takes {expected}, but {got} were given")]
+    WrongArity {
+        name: String,
+        expected: Arity,
+        got: usize,
+    },
+    #[error("Expression is nested deeper than {MAX_DEPTH} levels")]
+    TooDeep,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Result is too large to represent")]
+    Overflow,
+    #[error("Result is too small to represent")]
+    Underflow,
+    #[error("Invalid exponent in `{0}`")]
+    InvalidExponent(String),
+    #[error("Operator is missing an operand")]
+    StackUnderflow,
+    #[error("Expression has {0} unused value(s), is an operator missing?")]
+    LeftoverOperands(usize),
+    #[error("Undefined variable `{0}`")]
+    UndefinedVariable(String),
+    #[error("Undefined function `{0}`")]
+    UndefinedFunction(String),
+    #[error("`{name}` takes {expected}, but {got} were given")]
+    WrongArity {
+        name: String,
+        expected: Arity,
+        got: usize,
+    },
+    #[error("`{0}` is outside the function's domain")]
+    Domain(String),
+    #[error("Calls to `{0}` nested deeper than {MAX_CALL_DEPTH} levels")]
+    RecursionLimit(String),
+    #[error("`{0}` has no exact value, leave out `--exact` to approximate it")]
+    Inexact(String),
+    #[error("`{0}` is a registered function")]
+    Registered(String),
+    #[error("`{0}` needs whole numbers")]
+    NotInteger(String),
+    #[error("Invalid shift amount in `{0}`")]
+    InvalidShift(String),
+    #[error("Units don't match in `{0}`")]
+    IncompatibleUnits(String),
+    #[error("`{0}` is not a unit to convert to")]
+    NotAUnit(String),
+    #[error("`{0}` has a unit, fixed width integers can't have one")]
+    IntegerUnits(String),
+    #[error("Lists have different lengths, {0} and {1}")]
+    LengthMismatch(usize, usize),
+    #[error("`{0}` has more than {MAX_LIST_LEN} elements")]
+    TooLong(String),
+}
+
+/// The state after one step of the shunting-yard conversion, recorded for `--explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionStep {
+    /// Token read, `None` for the final step that outputs the operators left on the stack.
+    pub token: Option<MathToken>,
+    /// Output queue, without the skips that make `&&`, `||` and `?:` short-circuit.
+    pub output: Vec<MathToken>,
+    /// Operator stack, bottom first.
+    pub stack: Vec<MathToken>,
+}
+
+/// The stack after evaluating one postfix token, recorded for `--explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalStep {
+    /// Position of the token in the postfix expression, skips jump to these.
+    pub index: usize,
+    pub token: MathToken,
+    /// Value stack, bottom first.
+    pub stack: Vec<Value>,
+}
+
+/// Deepest nesting of operations, calls and lists in an expression. Deeper trees would
+/// overflow the stack of the code that walks them, e.g. to print them as infix.
+pub const MAX_DEPTH: usize = 256;
+
+/// Deepest nesting of user defined function calls before evaluation gives up.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Most elements a range, and so a list or series, may have.
+pub const MAX_LIST_LEN: usize = 1 << 20;
+
+/// Arguments bound to the parameters of the user defined function being evaluated.
+#[derive(Debug, Default, Clone, Copy)]
+struct Frame<'a> {
+    params: &'a [String],
+    args: &'a [Value],
+    depth: usize,
+}
+
+impl Frame<'_> {
+    fn get(&self, name: &str) -> Option<Value> {
+        self.params
+            .iter()
+            .position(|p| p == name)
+            .map(|idx| self.args[idx].clone())
+    }
+}
+
+/// Written as plain reverse Polish notation, without the skips that make it short-circuit.
+impl fmt::Display for PostExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens: Vec<_> = self
+            .tokens
+            .iter()
+            .filter(|tok| !matches!(tok, MathToken::Skip(..)))
+            .map(ToString::to_string)
+            .collect();
+        write!(f, "{}", tokens.join(" "))
+    }
 }
 
 impl PostExpression {
-    pub fn eval(self) -> isize {
+    #[must_use]
+    pub fn tokens(&self) -> &[MathToken] {
+        &self.tokens
+    }
+
+    /// Span of the token at `idx`, empty if the expression wasn't parsed from text.
+    #[must_use]
+    pub fn span(&self, idx: usize) -> Span {
+        self.spans.get(idx).copied().unwrap_or_default()
+    }
+
+    /// How deep operations nest, counting `outer` levels around the expression, e.g. the
+    /// series whose body it is. Series bodies count as operands of their series.
+    ///
+    /// # Errors
+    /// When the nesting goes deeper than `MAX_DEPTH`, at the first token that does.
+    fn check_depth(&self, outer: usize) -> Result<usize, Spanned<PostExpressionError>> {
+        let mut depths: Vec<usize> = Vec::new();
+        for (idx, tok) in self.tokens.iter().enumerate() {
+            if matches!(tok, MathToken::Skip(..)) {
+                continue;
+            }
+            let span = self.span(idx);
+            let operands = depths.split_off(depths.len().saturating_sub(operand_count(tok, span)?));
+            let mut depth = operands.into_iter().max().unwrap_or(0) + 1;
+            if let MathToken::Series(series) = tok {
+                depth = depth.max(series.body.check_depth(outer + 1)? + 1);
+            }
+            if outer + depth > MAX_DEPTH {
+                return Err(Spanned::new(PostExpressionError::TooDeep, span));
+            }
+            depths.push(depth);
+        }
+        Ok(depths.into_iter().max().unwrap_or(0))
+    }
+
+    /// Span covering every token of the expression.
+    fn full_span(&self) -> Span {
+        self.spans
+            .iter()
+            .copied()
+            .reduce(Span::to)
+            .unwrap_or_default()
+    }
+
+    /// Evaluate the expression, looking up variables and functions in `env`.
+    /// Errors point at the token that caused them.
+    ///
+    /// # Errors
+    /// When a token can't be evaluated, e.g. an undefined variable or a division by zero.
+    pub fn eval(&self, env: &Environment) -> Result<Value, Spanned<EvalError>> {
+        self.eval_with(env, None)
+    }
+
+    /// Like `eval`, recording the stack after every token in `steps`, up to an error.
+    /// Calls to user defined functions are a single step.
+    ///
+    /// # Errors
+    /// Like `eval`.
+    pub fn eval_traced(
+        &self,
+        env: &Environment,
+        steps: &mut Vec<EvalStep>,
+    ) -> Result<Value, Spanned<EvalError>> {
+        self.eval_with(env, Some(steps))
+    }
+
+    fn eval_with(
+        &self,
+        env: &Environment,
+        trace: Option<&mut Vec<EvalStep>>,
+    ) -> Result<Value, Spanned<EvalError>> {
+        let result = self.eval_in(env, Frame::default(), trace)?;
+        // a lone fixed width literal may be out of range, see `Arithmetic::literal`
+        env.arithmetic()
+            .check(result, || self.to_string())
+            .map_err(|e| Spanned::new(e, self.full_span()))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn eval_in(
+        &self,
+        env: &Environment,
+        frame: Frame,
+        mut trace: Option<&mut Vec<EvalStep>>,
+    ) -> Result<Value, Spanned<EvalError>> {
         let mut stack = Vec::new();
-        for tok in self.tokens {
+        let mut idx = 0;
+        while let Some(tok) = self.tokens.get(idx) {
+            let index = idx;
+            let span = self.span(idx);
+            let at = |error| Spanned::new(error, span);
+            idx += 1;
             match tok {
-                MathToken::IntOperand(x) => stack.push(x),
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::BigIntOperand(_) => {
+                    let literal = match tok {
+                        MathToken::IntOperand(i) => Value::Int(*i),
+                        MathToken::FloatOperand(x) => Value::Float(*x),
+                        MathToken::BigIntOperand(n) => {
+                            Value::Rational(BigRational::from_integer(n.clone()))
+                        }
+                        _ => unreachable!("Matched a number above"),
+                    };
+                    stack.push(env.arithmetic().literal(literal).map_err(at)?);
+                }
+                MathToken::ImaginaryOperand(x) => {
+                    let imaginary = Value::Complex(Complex64::new(0.0, *x));
+                    stack.push(
+                        env.arithmetic()
+                            .check(imaginary, || tok.to_string())
+                            .map_err(at)?,
+                    );
+                }
+                MathToken::Identifier(name) => {
+                    let value = if let Some(value) = frame.get(name).or_else(|| env.get(name)) {
+                        value
+                    } else {
+                        let constant = builtins::constant(name)
+                            .ok_or_else(|| at(EvalError::UndefinedVariable(name.clone())))?;
+                        env.arithmetic()
+                            .check(constant, || name.clone())
+                            .map_err(at)?
+                    };
+                    stack.push(value);
+                }
+                MathToken::Unit(name) => {
+                    let unit = units::quantity(name)
+                        .ok_or_else(|| EvalError::UndefinedVariable(name.clone()))
+                        .and_then(|unit| env.arithmetic().check(unit, || name.clone()));
+                    stack.push(unit.map_err(at)?);
+                }
+                MathToken::Call(name, argc) => {
+                    let first_arg = stack
+                        .len()
+                        .checked_sub(*argc)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let call_args = stack.split_off(first_arg);
+                    stack.push(Self::call(env, frame, name, &call_args).map_err(at)?);
+                }
+                MathToken::Skip(when, target) => {
+                    let jumps = match when {
+                        SkipIf::Always => true,
+                        SkipIf::True | SkipIf::False => {
+                            let top = stack.last().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                            top.is_zero() == (*when == SkipIf::False)
+                        }
+                    };
+                    if jumps {
+                        // stands in for the skipped operand, which the operator will ignore
+                        stack.push(Value::Int(0));
+                        idx = *target;
+                    }
+                }
+                MathToken::Operator(OperatorType::Cond) => {
+                    let mut pop = || stack.pop().ok_or_else(|| at(EvalError::StackUnderflow));
+                    let otherwise = pop()?;
+                    let then = pop()?;
+                    let cond = pop()?;
+                    stack.push(OperatorType::apply_cond(&cond, then, otherwise));
+                }
+                MathToken::Operator(op) if op.is_unary() => {
+                    let operand = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let result = op.apply_unary(operand).map_err(at)?;
+                    stack.push(env.arithmetic().fit(result, op).map_err(at)?);
+                }
                 MathToken::Operator(op) => {
-                    let rhs = stack.pop().expect("A number for an operator");
-                    let lhs = stack.pop().expect("A number for a binary operator");
-                    stack.push(op.apply(lhs, rhs));
+                    let rhs = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let lhs = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let result = op.apply(lhs, rhs).map_err(at)?;
+                    stack.push(env.arithmetic().fit(result, op).map_err(at)?);
                 }
-                MathToken::Parens(_) => {
+                MathToken::List(len) => {
+                    let first = stack
+                        .len()
+                        .checked_sub(*len)
+                        .ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let items = stack.split_off(first);
+                    stack.push(Value::List(items));
+                }
+                MathToken::Series(series) => {
+                    let to = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    let from = stack.pop().ok_or_else(|| at(EvalError::StackUnderflow))?;
+                    // the variable is bound like a parameter, shadowing those of the function
+                    let params: Vec<_> = std::iter::once(series.var.clone())
+                        .chain(frame.params.iter().cloned())
+                        .collect();
+                    let mut args: Vec<_> = std::iter::once(Value::Int(0))
+                        .chain(frame.args.iter().cloned())
+                        .collect();
+                    let total = series.fold((from, to), env.arithmetic(), at, |value| {
+                        args[0] = value;
+                        let inner = Frame {
+                            params: &params,
+                            args: &args,
+                            depth: frame.depth,
+                        };
+                        series.body.eval_in(env, inner, None)
+                    })?;
+                    stack.push(total);
+                }
+                MathToken::Parens(_)
+                | MathToken::Brackets(_)
+                | MathToken::Assign
+                | MathToken::Function(_)
+                | MathToken::Comma
+                | MathToken::Question
+                | MathToken::Colon => {
                     unreachable!("Unexpected MathToken type in evaluating function")
                 }
-            };
+            }
+            if let Some(steps) = trace.as_deref_mut() {
+                steps.push(EvalStep {
+                    index,
+                    token: tok.clone(),
+                    stack: stack.clone(),
+                });
+            }
+        }
+        match stack.len() {
+            0 => Err(Spanned::new(EvalError::StackUnderflow, self.full_span())),
+            1 => Ok(stack.pop().expect("Stack has exactly one value")),
+            n => Err(Spanned::new(
+                EvalError::LeftoverOperands(n - 1),
+                self.full_span(),
+            )),
+        }
+    }
+
+    /// Call a function outside of any user defined function, e.g. from compiled bytecode.
+    pub(crate) fn call_function(
+        env: &Environment,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, EvalError> {
+        Self::call(env, Frame::default(), name, args)
+    }
+
+    /// Call a builtin, registered or user defined function.
+    /// Errors inside a user defined function are reported at the call, not in its body.
+    fn call(
+        env: &Environment,
+        frame: Frame,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, EvalError> {
+        let wrong_arity = |expected| EvalError::WrongArity {
+            name: name.to_string(),
+            expected,
+            got: args.len(),
+        };
+        if let Some(function) = env.registry().function(name) {
+            if !function.arity().accepts(args.len()) {
+                return Err(wrong_arity(function.arity()));
+            }
+            let result = function.call(args)?;
+            return env.arithmetic().check(result, || {
+                let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+                format!("{name}({})", args.join(", "))
+            });
+        }
+        let func = env
+            .function(name)
+            .ok_or_else(|| EvalError::UndefinedFunction(name.to_string()))?;
+        if func.params.len() != args.len() {
+            return Err(wrong_arity(Arity::Exact(func.params.len())));
+        }
+        if frame.depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit(name.to_string()));
+        }
+        let inner = Frame {
+            params: &func.params,
+            args,
+            depth: frame.depth + 1,
+        };
+        func.body.eval_in(env, inner, None).map_err(|e| e.error)
+    }
+}
+/// Postfix tokens produced by the shunting-yard conversion.
+#[derive(Default)]
+struct Output {
+    tokens: Vec<(MathToken, Span)>,
+    /// Indices of the skips whose operator hasn't been output yet, innermost last.
+    pending: Vec<usize>,
+}
+
+impl Output {
+    /// Output a token, a short-circuiting operator is where its skip lands.
+    /// Implicit products are output as ordinary multiplication.
+    fn push(&mut self, mut tok: (MathToken, Span)) {
+        if tok.0 == MathToken::Operator(OperatorType::Implicit) {
+            tok.0 = MathToken::Operator(OperatorType::Mul);
+        }
+        if matches!(&tok.0, MathToken::Operator(op) if op.short_circuits()) {
+            let skip = self
+                .pending
+                .pop()
+                .expect("Short-circuiting operators output a skip");
+            self.aim(skip);
+        }
+        self.tokens.push(tok);
+    }
+
+    /// Output a skip over the operand that follows, it is aimed once its operator is output.
+    fn skip(&mut self, when: SkipIf, span: Span) {
+        self.pending.push(self.tokens.len());
+        self.tokens.push((MathToken::Skip(when, 0), span));
+    }
+
+    /// At the `:` of `cond ? then : otherwise`, skip over `otherwise` once `then` is done.
+    /// A false `cond` skips to just after this.
+    fn otherwise(&mut self, span: Span) {
+        let question = self.pending.pop().expect("`?` outputs a skip");
+        self.skip(SkipIf::Always, span);
+        self.aim(question);
+    }
+
+    /// Record the state after reading `token`, or after the input ran out.
+    fn snapshot(&self, token: Option<MathToken>, op_stack: &[(MathToken, Span)]) -> ConversionStep {
+        ConversionStep {
+            token,
+            output: self
+                .tokens
+                .iter()
+                .map(|(tok, _)| tok)
+                .filter(|tok| !matches!(tok, MathToken::Skip(..)))
+                .cloned()
+                .collect(),
+            stack: op_stack.iter().map(|(tok, _)| tok.clone()).collect(),
+        }
+    }
+
+    /// Make the skip at `idx` jump to the next token.
+    fn aim(&mut self, idx: usize) {
+        let next = self.tokens.len();
+        if let (MathToken::Skip(_, target), _) = &mut self.tokens[idx] {
+            *target = next;
         }
-        assert!(stack.len() == 1, "Invalid postfix expression");
-        stack.pop().unwrap()
     }
 }
+
+/// Turn the arguments of `sum(i, 1, n, i^2)` or `product(...)`, already in `tokens`, into a
+/// series whose bounds stay in `tokens` while its body is taken out, to be evaluated for every
+/// value of `i`. `starts` is where each argument begins. Exactly four arguments with a bare name
+/// first make a series, even if the body doesn't use it, `sum(k, 1, 3, 2)` is `6`. Anything
+/// else is an ordinary call, `sum(1, a, b, c)` adds up four values.
+fn take_series(
+    tokens: &mut Vec<(MathToken, Span)>,
+    name: &str,
+    starts: &[usize],
+) -> Option<Series> {
+    let product = match name {
+        "sum" => false,
+        "product" => true,
+        _ => return None,
+    };
+    let &[var_at, from_at, _, body_at] = starts else {
+        return None;
+    };
+    let [(MathToken::Identifier(var), _)] = &tokens[var_at..from_at] else {
+        return None;
+    };
+    let var = var.clone();
+    let (body, spans) = tokens
+        .split_off(body_at)
+        .into_iter()
+        .map(|(mut tok, span)| {
+            if let MathToken::Skip(_, target) = &mut tok {
+                *target -= body_at;
+            }
+            (tok, span)
+        })
+        .unzip();
+    tokens.remove(var_at);
+    for (tok, _) in &mut tokens[var_at..] {
+        if let MathToken::Skip(_, target) = tok {
+            *target -= 1;
+        }
+    }
+    Some(Series {
+        product,
+        var,
+        body: PostExpression {
+            tokens: body,
+            spans,
+        },
+    })
+}
+
+/// Output the operators on the stack that bind tighter than `op`, which is about to be pushed.
+fn pop_tighter(op_stack: &mut Vec<(MathToken, Span)>, output: &mut Output, op: &OperatorType) {
+    while let Some(top) = op_stack.pop_if(|(top, _)| {
+        let MathToken::Operator(top_op) = top else {
+            return false;
+        };
+        match (*top_op).cmp(op) {
+            cmp::Ordering::Greater => true,
+            cmp::Ordering::Equal => op.associativity() == Associativity::Left,
+            cmp::Ordering::Less => false,
+        }
+    }) {
+        output.push(top);
+    }
+}
+
+/// Number of values a postfix token takes off the stack, checking the arity of builtin calls.
+fn operand_count(tok: &MathToken, span: Span) -> Result<usize, Spanned<PostExpressionError>> {
+    Ok(match tok {
+        MathToken::Operator(OperatorType::Cond) => 3,
+        MathToken::Operator(op) if op.is_unary() => 1,
+        MathToken::Operator(_) | MathToken::Series(_) => 2,
+        MathToken::List(len) => *len,
+        MathToken::Call(name, argc) => {
+            if let Some(builtin) = builtins::function(name) {
+                if !builtin.arity.accepts(*argc) {
+                    return Err(Spanned::new(
+                        PostExpressionError::WrongArity {
+                            name: name.clone(),
+                            expected: builtin.arity,
+                            got: *argc,
+                        },
+                        span,
+                    ));
+                }
+            }
+            *argc
+        }
+        _ => 0,
+    })
+}
+
 impl TryFrom<Expression> for PostExpression {
-    type Error = PostExpressionError;
+    type Error = Spanned<PostExpressionError>;
 
     fn try_from(eq: Expression) -> Result<Self, Self::Error> {
-        let mut queue: Vec<MathToken> = Vec::new();
-        let mut op_stack: Vec<MathToken> = Vec::new();
+        Self::convert(eq, None)
+    }
+}
+
+impl PostExpression {
+    /// Like `try_from`, recording the output queue and operator stack after every token in
+    /// `steps`, up to an error.
+    ///
+    /// # Errors
+    /// Like `try_from`, when the expression isn't well formed.
+    pub fn try_from_traced(
+        eq: Expression,
+        steps: &mut Vec<ConversionStep>,
+    ) -> Result<Self, Spanned<PostExpressionError>> {
+        Self::convert(eq, Some(steps))
+    }
+
+    /// Convert infix to postfix with the shunting-yard algorithm.
+    #[allow(clippy::too_many_lines)]
+    fn convert(
+        eq: Expression,
+        mut trace: Option<&mut Vec<ConversionStep>>,
+    ) -> Result<Self, Spanned<PostExpressionError>> {
+        let invalid =
+            |msg: String, span| Spanned::new(PostExpressionError::InvalidExpression(msg), span);
+        // a `?` still on the stack when its operands end never got its `:`
+        let unfinished = |(tok, span): (MathToken, Span)| match tok {
+            MathToken::Question => Err(invalid("`?` is missing its `:`".to_string(), span)),
+            tok => Ok((tok, span)),
+        };
+        let mut output = Output::default();
+        let mut op_stack: Vec<(MathToken, Span)> = Vec::new();
+
+        // an operator seen while expecting an operand is a prefix (unary) operator
+        let mut expect_operand = true;
+        // a name is a unit right after a number, a unit, a closing parenthesis or bracket, or
+        // `to`, and after a `*`, `/` or `^` that follows a unit, as in `60 mi/h to km/h`,
+        // anywhere else it's a variable
+        let mut unit_follows = false;
+        let mut after_unit = false;
+        // where each argument of the function calls and lists currently open starts in the
+        // output, innermost last
+        let mut arg_starts: Vec<Vec<usize>> = Vec::new();
+        let mut after_open_paren = false;
+        let mut after_open_bracket = false;
+        // the token read last, blamed if the expression ends while an operand is expected
+        let mut last: Option<(MathToken, Span)> = None;
 
         debug!("Started tokens while-loop");
-        for tok in eq.tokens {
-            debug!("Working on token {:?}", tok);
+        for (tok, span) in eq.into_spanned() {
+            debug!("Working on token {:?} at {:?}", tok, span);
+            last = Some((tok.clone(), span));
+            let empty_parens = after_open_paren && tok == MathToken::Parens(false);
+            let empty_brackets = after_open_bracket && tok == MathToken::Brackets(false);
+            after_open_paren = tok == MathToken::Parens(true);
+            after_open_bracket = tok == MathToken::Brackets(true);
+            let is_unit =
+                matches!(&tok, MathToken::Identifier(name) if unit_follows && units::is_unit(name));
+            unit_follows = match &tok {
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::BigIntOperand(_)
+                | MathToken::Parens(false)
+                | MathToken::Brackets(false)
+                | MathToken::Operator(OperatorType::To) => true,
+                MathToken::Operator(OperatorType::Mul | OperatorType::Div | OperatorType::Pow) => {
+                    after_unit
+                }
+                _ => is_unit,
+            };
+            after_unit = is_unit;
             match tok {
-                MathToken::IntOperand(x) => queue.push(MathToken::IntOperand(x)),
+                MathToken::Identifier(name) if is_unit => {
+                    // a unit right after an operand multiplies it, `20 cm` is `20 * cm`
+                    if !expect_operand {
+                        pop_tighter(&mut op_stack, &mut output, &OperatorType::Implicit);
+                        let implicit = Span::new(span.start, span.start);
+                        op_stack.push((MathToken::Operator(OperatorType::Implicit), implicit));
+                    }
+                    output.push((MathToken::Unit(name), span));
+                    expect_operand = false;
+                }
+                // two operands in a row, like `2 (3)` or `2 pi`, only a unit multiplies without `*`
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::ImaginaryOperand(_)
+                | MathToken::BigIntOperand(_)
+                | MathToken::Identifier(_)
+                | MathToken::Function(_)
+                | MathToken::Parens(true)
+                | MathToken::Brackets(true)
+                    if !expect_operand =>
+                {
+                    return Err(invalid(
+                        format!("Expected an operator before `{tok}`"),
+                        span,
+                    ));
+                }
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::ImaginaryOperand(_)
+                | MathToken::BigIntOperand(_)
+                | MathToken::Identifier(_) => {
+                    output.push((tok, span));
+                    expect_operand = false;
+                }
+                MathToken::Operator(op) if expect_operand => {
+                    let Some(unary) = op.to_unary() else {
+                        return Err(invalid(
+                            format!("Operator `{op}` is missing its left operand"),
+                            span,
+                        ));
+                    };
+                    // prefix operators have nothing to their left to pop.
+                    op_stack.push((MathToken::Operator(unary), span));
+                }
+                MathToken::Operator(op) if op.is_unary() => {
+                    return Err(invalid(
+                        format!("Operator `{op}` only goes before its operand"),
+                        span,
+                    ));
+                }
                 MathToken::Operator(op) => {
                     debug!("State of operator stack - {:?}", op_stack);
-                    while let Some(MathToken::Operator(top_op)) = op_stack.last() {
-                        if *top_op > op {
-                            let tobe_pushed = op_stack
-                                .pop()
-                                .expect("A token should be here since we peeked it.");
-                            queue.push(tobe_pushed);
-                        } else {
-                            break;
+                    pop_tighter(&mut op_stack, &mut output, &op);
+                    if op.short_circuits() {
+                        output.skip(
+                            if op == OperatorType::And {
+                                SkipIf::False
+                            } else {
+                                SkipIf::True
+                            },
+                            span,
+                        );
+                    }
+                    op_stack.push((MathToken::Operator(op), span));
+                    expect_operand = true;
+                }
+                MathToken::Question | MathToken::Colon if expect_operand => {
+                    return Err(invalid(format!("Expected an operand before `{tok}`"), span));
+                }
+                MathToken::Question => {
+                    pop_tighter(&mut op_stack, &mut output, &OperatorType::Cond);
+                    output.skip(SkipIf::False, span);
+                    op_stack.push((tok, span));
+                    expect_operand = true;
+                }
+                MathToken::Colon => {
+                    while let Some(top) =
+                        op_stack.pop_if(|(top, _)| matches!(top, MathToken::Operator(_)))
+                    {
+                        output.push(top);
+                    }
+                    let Some((_, question)) =
+                        op_stack.pop_if(|(top, _)| *top == MathToken::Question)
+                    else {
+                        return Err(invalid("`:` without a matching `?`".to_string(), span));
+                    };
+                    output.otherwise(span);
+                    op_stack.push((MathToken::Operator(OperatorType::Cond), question));
+                    expect_operand = true;
+                }
+                MathToken::Assign => {
+                    return Err(invalid(
+                        "`=` can only follow a variable name at the start of the expression"
+                            .to_string(),
+                        span,
+                    ))
+                }
+                MathToken::Function(_) | MathToken::Brackets(true) => {
+                    op_stack.push((tok, span));
+                    arg_starts.push(vec![output.tokens.len()]);
+                }
+                MathToken::Comma => {
+                    while let Some(top) = op_stack.pop_if(|(top, _)| {
+                        !matches!(top, MathToken::Parens(true) | MathToken::Brackets(true))
+                    }) {
+                        output.push(unfinished(top)?);
+                    }
+                    let in_call = matches!(
+                        op_stack.as_slice(),
+                        [
+                            ..,
+                            (MathToken::Function(_), _),
+                            (MathToken::Parens(true), _)
+                        ] | [.., (MathToken::Brackets(true), _)]
+                    );
+                    if !in_call || expect_operand {
+                        return Err(invalid(
+                            "`,` can only separate function arguments and list elements"
+                                .to_string(),
+                            span,
+                        ));
+                    }
+                    arg_starts
+                        .last_mut()
+                        .expect("Function calls and lists push their starts")
+                        .push(output.tokens.len());
+                    expect_operand = true;
+                }
+                MathToken::Call(..)
+                | MathToken::Skip(..)
+                | MathToken::List(_)
+                | MathToken::Series(_)
+                | MathToken::Unit(_) => {
+                    unreachable!("{tok:?} is only created by the postfix conversion")
+                }
+                MathToken::Brackets(false) => {
+                    if expect_operand && !empty_brackets {
+                        return Err(invalid("Expected an operand before `]`".to_string(), span));
+                    }
+                    expect_operand = false;
+                    let mut opening = None;
+                    while let Some((tok, tok_span)) = op_stack.pop() {
+                        match tok {
+                            MathToken::Brackets(true) => {
+                                opening = Some(tok_span);
+                                break;
+                            }
+                            MathToken::Parens(true) => {
+                                return Err(invalid("Unclosed parenthesis".to_string(), tok_span));
+                            }
+                            _ => output.push(unfinished((tok, tok_span))?),
                         }
                     }
-                    op_stack.push(MathToken::Operator(op));
+                    let Some(opening) = opening else {
+                        return Err(invalid("No matching opening bracket".to_string(), span));
+                    };
+                    let starts = arg_starts.pop().expect("Lists push their starts");
+                    let len = if empty_brackets { 0 } else { starts.len() };
+                    output.push((MathToken::List(len), opening.to(span)));
                 }
-                MathToken::Parens(true) => op_stack.push(MathToken::Parens(true)),
+                MathToken::Parens(true) => op_stack.push((MathToken::Parens(true), span)),
                 MathToken::Parens(false) => {
-                    let mut matched = false;
-                    while let Some(tok) = op_stack.pop() {
+                    if expect_operand && !empty_parens {
+                        return Err(invalid("Expected an operand before `)`".to_string(), span));
+                    }
+                    expect_operand = false;
+                    let mut opening = None;
+                    while let Some((tok, tok_span)) = op_stack.pop() {
                         match tok {
                             MathToken::Parens(true) => {
-                                matched = true;
+                                opening = Some(tok_span);
                                 break;
                             }
                             MathToken::Parens(false) => {
                                 unreachable!("Stack NEVER stores closing parentheses")
                             }
-                            _ => queue.push(tok),
+                            MathToken::Brackets(true) => {
+                                return Err(invalid("Unclosed `[`".to_string(), tok_span));
+                            }
+                            _ => output.push(unfinished((tok, tok_span))?),
                         }
                     }
-                    if !matched {
-                        return Err(PostExpressionError::InvalidExpression(
-                            "No matching opening parenthesis".to_string(),
-                        ));
+                    let Some(opening) = opening else {
+                        return Err(invalid("No matching opening parenthesis".to_string(), span));
+                    };
+                    if let Some((MathToken::Function(name), name_span)) =
+                        op_stack.pop_if(|(top, _)| matches!(top, MathToken::Function(_)))
+                    {
+                        let call_span = name_span.to(span);
+                        let starts = arg_starts.pop().expect("Function calls push their starts");
+                        let argc = if empty_parens { 0 } else { starts.len() };
+                        let series = take_series(&mut output.tokens, &name, &starts);
+                        let call = if let Some(series) = series {
+                            MathToken::Series(Box::new(series))
+                        } else {
+                            if let Some(builtin) = builtins::function(&name) {
+                                if !builtin.arity.accepts(argc) {
+                                    return Err(Spanned::new(
+                                        PostExpressionError::WrongArity {
+                                            name,
+                                            expected: builtin.arity,
+                                            got: argc,
+                                        },
+                                        call_span,
+                                    ));
+                                }
+                            }
+                            MathToken::Call(name, argc)
+                        };
+                        output.push((call, call_span));
+                    } else if empty_parens {
+                        return Err(invalid("Empty parentheses".to_string(), opening.to(span)));
                     }
                 }
             }
+            if let (Some(steps), Some((tok, _))) = (trace.as_deref_mut(), &last) {
+                steps.push(output.snapshot(Some(tok.clone()), &op_stack));
+            }
+        }
+        while let Some((tok, span)) = op_stack.pop() {
+            match tok {
+                MathToken::Parens(true) => {
+                    return Err(invalid("Unclosed parenthesis".to_string(), span));
+                }
+                MathToken::Brackets(true) => {
+                    return Err(invalid("Unclosed `[`".to_string(), span));
+                }
+                _ => {}
+            }
+            output.push(unfinished((tok, span))?);
+        }
+        match (expect_operand, last) {
+            (_, None) => return Err(invalid("Empty expression".to_string(), Span::default())),
+            (true, Some((tok, span))) => {
+                return Err(invalid(format!("Expected an operand after `{tok}`"), span));
+            }
+            (false, Some(_)) => {}
         }
-        while let Some(tok) = op_stack.pop() {
-            queue.push(tok);
+        if let Some(steps) = trace {
+            steps.push(output.snapshot(None, &op_stack));
         }
 
-        Ok(Self { tokens: queue })
+        let (tokens, spans) = output.tokens.into_iter().unzip();
+        let expression = Self { tokens, spans };
+        expression.check_depth(0)?;
+        Ok(expression)
+    }
+
+    /// Read tokens written in reverse Polish notation, e.g. `3 4 + 2 *`.
+    /// Unary signs are `neg` and `pos`, the conditional is `?:`, and functions taking a
+    /// variable number of arguments are given the count, e.g. `max(3)`.
+    /// The stack depth is checked, so every operator has its operands and one value is left.
+    ///
+    /// # Errors
+    /// When a token is missing its operands, more than one value would be left, a token
+    /// has no meaning in reverse Polish notation, like a parenthesis, or the expression
+    /// nests deeper than `MAX_DEPTH`.
+    #[allow(clippy::too_many_lines)]
+    pub fn from_rpn(eq: Expression) -> Result<Self, Spanned<PostExpressionError>> {
+        let invalid = |msg: String, span| Spanned::new(PostExpressionError::InvalidRpn(msg), span);
+        let mut output: Vec<(MathToken, Span)> = Vec::new();
+        // where each value on the stack begins in the output, once the tokens read so far are
+        // evaluated, so that series can take their arguments apart
+        let mut starts: Vec<usize> = Vec::new();
+        let mut input = eq.into_spanned().peekable();
+        // a name is a unit right after a number, a unit, or `*`, `/` or `^`, as in `3 m * cm to`,
+        // anywhere else it's a variable
+        let mut unit_follows = false;
+        while let Some((tok, span)) = input.next() {
+            let is_unit =
+                matches!(&tok, MathToken::Identifier(name) if unit_follows && units::is_unit(name));
+            unit_follows = is_unit
+                || matches!(
+                    tok,
+                    MathToken::IntOperand(_)
+                        | MathToken::FloatOperand(_)
+                        | MathToken::BigIntOperand(_)
+                        | MathToken::Operator(
+                            OperatorType::Mul | OperatorType::Div | OperatorType::Pow
+                        )
+                );
+            let (tok, span) = match tok {
+                MathToken::Identifier(name) if is_unit => (MathToken::Unit(name), span),
+                MathToken::IntOperand(_)
+                | MathToken::FloatOperand(_)
+                | MathToken::ImaginaryOperand(_)
+                | MathToken::BigIntOperand(_)
+                | MathToken::Operator(_) => (tok, span),
+                MathToken::Identifier(name) => match (name.as_str(), builtins::function(&name)) {
+                    ("neg", _) => (MathToken::Operator(OperatorType::Neg), span),
+                    ("pos", _) => (MathToken::Operator(OperatorType::Pos), span),
+                    (_, Some(builtin)) => {
+                        let Arity::Exact(argc) = builtin.arity else {
+                            return Err(invalid(
+                                format!("`{name}` needs its number of arguments, e.g. `{name}(2)`"),
+                                span,
+                            ));
+                        };
+                        (MathToken::Call(name, argc), span)
+                    }
+                    (_, None) => (MathToken::Identifier(name), span),
+                },
+                MathToken::Function(name) => {
+                    let open = input.next_if(|(tok, _)| *tok == MathToken::Parens(true));
+                    let argc = input.next_if(|(tok, _)| matches!(tok, MathToken::IntOperand(_)));
+                    let close = input.next_if(|(tok, _)| *tok == MathToken::Parens(false));
+                    let (Some(_), Some((MathToken::IntOperand(argc), _)), Some((_, close))) =
+                        (open, argc, close)
+                    else {
+                        return Err(invalid(
+                            format!("Call `{name}` with its number of arguments, e.g. `{name}(2)`"),
+                            span,
+                        ));
+                    };
+                    let argc = usize::try_from(argc).map_err(|_| {
+                        invalid(
+                            format!("`{name}` can't take {argc} arguments"),
+                            span.to(close),
+                        )
+                    })?;
+                    if name == "list" {
+                        (MathToken::List(argc), span.to(close))
+                    } else {
+                        (MathToken::Call(name, argc), span.to(close))
+                    }
+                }
+                MathToken::Question => {
+                    let Some((_, colon)) = input.next_if(|(tok, _)| *tok == MathToken::Colon)
+                    else {
+                        return Err(invalid("The conditional is written `?:`".to_string(), span));
+                    };
+                    (MathToken::Operator(OperatorType::Cond), span.to(colon))
+                }
+                MathToken::Parens(_)
+                | MathToken::Brackets(_)
+                | MathToken::Assign
+                | MathToken::Comma
+                | MathToken::Colon => return Err(invalid(format!("Unexpected `{tok}`"), span)),
+                MathToken::Call(..)
+                | MathToken::Skip(..)
+                | MathToken::List(_)
+                | MathToken::Series(_)
+                | MathToken::Unit(_) => {
+                    unreachable!("{tok:?} is only created by the postfix conversion")
+                }
+            };
+            let operands = operand_count(&tok, span)?;
+            let Some(first) = starts.len().checked_sub(operands) else {
+                return Err(invalid(
+                    format!(
+                        "`{tok}` needs {operands} operand(s), but the stack only holds {}",
+                        starts.len()
+                    ),
+                    span,
+                ));
+            };
+            let start = starts.get(first).copied().unwrap_or(output.len());
+            let tok = match tok {
+                MathToken::Call(name, argc) => take_series(&mut output, &name, &starts[first..])
+                    .map_or(MathToken::Call(name, argc), |series| {
+                        MathToken::Series(Box::new(series))
+                    }),
+                tok => tok,
+            };
+            starts.truncate(first);
+            starts.push(start);
+            output.push((tok, span));
+        }
+        let (tokens, spans): (Vec<_>, Vec<_>) = output.into_iter().unzip();
+        let expression = Self { tokens, spans };
+        match starts.len() {
+            0 => Err(invalid("Nothing to evaluate".to_string(), Span::default())),
+            1 => {
+                expression.check_depth(0)?;
+                Ok(expression)
+            }
+            n => Err(invalid(
+                format!("{n} values are left on the stack, is an operator missing?"),
+                expression.full_span(),
+            )),
+        }
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tokens::OperatorType;
+    use crate::environment::UserFunction;
+    use crate::value::{Arithmetic, Width};
+
+    fn postfix(tokens: Vec<MathToken>) -> PostExpression {
+        PostExpression {
+            tokens,
+            spans: Vec::new(),
+        }
+    }
 
     fn init_logger() {
         let _ = env_logger::builder()
@@ -113,58 +1073,69 @@ mod tests {
         ]);
         assert_eq!(
             PostExpression::try_from(eq).unwrap(),
-            PostExpression {
-                tokens: vec![
-                    MathToken::IntOperand(3),
-                    MathToken::IntOperand(4),
-                    MathToken::Operator(OperatorType::Add),
-                ]
-            }
+            postfix(vec![
+                MathToken::IntOperand(3),
+                MathToken::IntOperand(4),
+                MathToken::Operator(OperatorType::Add),
+            ])
         );
     }
     #[test]
     fn test_eval_add() {
-        let eq = PostExpression {
-            tokens: vec![
-                MathToken::IntOperand(3),
-                MathToken::IntOperand(4),
-                MathToken::Operator(OperatorType::Add),
-            ],
-        };
-        assert_eq!(eq.eval(), 7);
+        let eq = postfix(vec![
+            MathToken::IntOperand(3),
+            MathToken::IntOperand(4),
+            MathToken::Operator(OperatorType::Add),
+        ]);
+        assert_eq!(eq.eval(&Environment::default()).unwrap(), Value::Int(7));
     }
     #[test]
     fn test_eval_sub() {
-        let eq = PostExpression {
-            tokens: vec![
-                MathToken::IntOperand(3),
-                MathToken::IntOperand(4),
-                MathToken::Operator(OperatorType::Sub),
-            ],
-        };
-        assert_eq!(eq.eval(), -1);
+        let eq = postfix(vec![
+            MathToken::IntOperand(3),
+            MathToken::IntOperand(4),
+            MathToken::Operator(OperatorType::Sub),
+        ]);
+        assert_eq!(eq.eval(&Environment::default()).unwrap(), Value::Int(-1));
     }
     #[test]
     fn test_eval_mul() {
-        let eq = PostExpression {
-            tokens: vec![
-                MathToken::IntOperand(3),
-                MathToken::IntOperand(4),
-                MathToken::Operator(OperatorType::Mul),
-            ],
-        };
-        assert_eq!(eq.eval(), 12);
+        let eq = postfix(vec![
+            MathToken::IntOperand(3),
+            MathToken::IntOperand(4),
+            MathToken::Operator(OperatorType::Mul),
+        ]);
+        assert_eq!(eq.eval(&Environment::default()).unwrap(), Value::Int(12));
     }
     #[test]
     fn test_eval_div() {
-        let eq = PostExpression {
-            tokens: vec![
-                MathToken::IntOperand(3),
-                MathToken::IntOperand(4),
-                MathToken::Operator(OperatorType::Div),
-            ],
-        };
-        assert_eq!(eq.eval(), 0);
+        let eq = postfix(vec![
+            MathToken::IntOperand(3),
+            MathToken::IntOperand(4),
+            MathToken::Operator(OperatorType::Div),
+        ]);
+        assert_eq!(
+            eq.eval(&Environment::default()).unwrap(),
+            Value::Float(0.75)
+        );
+    }
+    #[test]
+    fn test_eval_exact_div() {
+        let eq = postfix(vec![
+            MathToken::IntOperand(8),
+            MathToken::IntOperand(4),
+            MathToken::Operator(OperatorType::Div),
+        ]);
+        assert_eq!(eq.eval(&Environment::default()).unwrap(), Value::Int(2));
+    }
+    #[test]
+    fn test_eval_mixed() {
+        let eq = postfix(vec![
+            MathToken::FloatOperand(1.5),
+            MathToken::IntOperand(2),
+            MathToken::Operator(OperatorType::Mul),
+        ]);
+        assert_eq!(eq.eval(&Environment::default()).unwrap(), Value::Float(3.0));
     }
     #[test]
     fn test_precedence_postfix() {
@@ -178,15 +1149,13 @@ mod tests {
         ]);
         assert_eq!(
             PostExpression::try_from(eq).unwrap(),
-            PostExpression {
-                tokens: vec![
-                    MathToken::IntOperand(3),
-                    MathToken::IntOperand(4),
-                    MathToken::IntOperand(2),
-                    MathToken::Operator(OperatorType::Mul),
-                    MathToken::Operator(OperatorType::Add),
-                ]
-            }
+            postfix(vec![
+                MathToken::IntOperand(3),
+                MathToken::IntOperand(4),
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::Operator(OperatorType::Add),
+            ])
         );
     }
     #[test]
@@ -211,15 +1180,13 @@ mod tests {
         ]);
         assert_eq!(
             PostExpression::try_from(eq).unwrap(),
-            PostExpression {
-                tokens: vec![
-                    MathToken::IntOperand(3),
-                    MathToken::IntOperand(4),
-                    MathToken::Operator(OperatorType::Add),
-                    MathToken::IntOperand(2),
-                    MathToken::Operator(OperatorType::Mul),
-                ]
-            }
+            postfix(vec![
+                MathToken::IntOperand(3),
+                MathToken::IntOperand(4),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Mul),
+            ])
         );
     }
     #[test]
@@ -233,7 +1200,603 @@ mod tests {
             MathToken::Operator(OperatorType::Mul),
             MathToken::IntOperand(2),
         ]);
-        assert_eq!(PostExpression::try_from(eq).unwrap().eval(), 14);
+        assert_eq!(
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap(),
+            Value::Int(14)
+        );
+    }
+    fn eval_str(input: &str) -> Value {
+        let eq = input.parse::<Expression>().unwrap();
+        PostExpression::try_from(eq)
+            .unwrap()
+            .eval(&Environment::default())
+            .unwrap()
+    }
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(eval_str("-3 + 4"), Value::Int(1));
+        assert_eq!(eval_str("2 * -5"), Value::Int(-10));
+        assert_eq!(eval_str("--3"), Value::Int(3));
+        assert_eq!(eval_str("-(1 + 2)"), Value::Int(-3));
+        assert_eq!(eval_str("+2.5"), Value::Float(2.5));
+    }
+    #[test]
+    fn test_unary_minus_with_power() {
+        assert_eq!(eval_str("-2^2"), Value::Int(-4));
+        assert_eq!(eval_str("(-2)^2"), Value::Int(4));
+        assert_eq!(eval_str("2^-1"), Value::Float(0.5));
+    }
+    #[test]
+    fn test_power_right_associative() {
+        assert_eq!(eval_str("2 ^ 3 ^ 2"), Value::Int(512));
+        assert_eq!(eval_str("8 - 4 - 2"), Value::Int(2));
+        let eq = "2 ^ 3 ^ 2".parse::<Expression>().unwrap();
+        assert_eq!(
+            PostExpression::try_from(eq).unwrap(),
+            postfix(vec![
+                MathToken::IntOperand(2),
+                MathToken::IntOperand(3),
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Pow),
+                MathToken::Operator(OperatorType::Pow),
+            ])
+        );
+    }
+    #[test]
+    fn test_eval_errors() {
+        let eval_err = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap_err()
+                .error
+        };
+        assert_eq!(eval_err("1 / 0"), EvalError::DivisionByZero);
+        assert_eq!(eval_err("1 / (2 - 2)"), EvalError::DivisionByZero);
+        assert_eq!(eval_err("2 ^ 64"), EvalError::Overflow);
+        // literals beyond `isize` only stay exact for bitwise operators
+        assert_eq!(eval_err("9223372036854775808 + 1"), EvalError::Overflow);
+        assert_eq!(eval_err("9223372036854775808 ^ 10"), EvalError::Overflow);
+        assert_eq!(eval_err("-9223372036854775808 / -1"), EvalError::Overflow);
+        assert_eq!(eval_str("-9223372036854775808"), Value::Int(isize::MIN));
+        assert_eq!(eval_str("9223372036854775808 / 2"), Value::Int(1 << 62));
+        // the parser rejects `3 4`, a hand-built postfix expression can still leave a value
+        let leftover = postfix(vec![MathToken::IntOperand(3), MathToken::IntOperand(4)]);
+        assert_eq!(
+            leftover.eval(&Environment::default()).unwrap_err().error,
+            EvalError::LeftoverOperands(1)
+        );
+    }
+    #[test]
+    fn test_eval_units() {
+        let shown = |input| eval_str(input).to_string();
+        assert_eq!(shown("3 m + 20 cm"), "3.2 m");
+        assert_eq!(shown("60 mi/h to km/h"), "96.56064 km/h");
+        assert_eq!(shown("9.81 m/s^2 * 2 s"), "19.62 m/s");
+        assert_eq!(shown("2 km / 30 min to km/h"), "4 km/h");
+        assert_eq!(shown("-3 m^2"), "-3 m^2");
+        assert_eq!(shown("1 / (2 s)"), "0.5 s^-1");
+        assert_eq!(eval_str("3 m > 20 cm"), Value::Int(1));
+        assert_eq!(eval_str("2 s * 3 Hz"), Value::Float(6.0));
+        // the unit after a number binds tighter than `/` but looser than `^`
+        let postfix = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq).unwrap().to_string()
+        };
+        assert_eq!(postfix("2 km / 30 min"), "2 km * 30 min * /");
+        assert_eq!(postfix("2 ^ 3 m ^ 2"), "2 3 ^ m 2 ^ *");
+        // only units multiply without `*`, and only after a number, unit or `)`
+        assert_eq!(postfix("(1 + 2) m"), "1 2 + m *");
+        let convert_err = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            let err = PostExpression::try_from(eq).unwrap_err();
+            (err.error.to_string(), err.span)
+        };
+        for (input, at) in [("2 x", 2), ("x h", 2), ("2 pi", 2), ("2 (3)", 2)] {
+            let (message, span) = convert_err(input);
+            assert!(message.contains("Expected an operator before"), "{message}");
+            assert_eq!(span.start, at);
+        }
+        assert!(convert_err("").0.contains("Empty expression"));
+        let eval_err = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap_err()
+                .error
+        };
+        assert_eq!(
+            eval_err("3 m + 2 s"),
+            EvalError::IncompatibleUnits("3 m + 2 s".into())
+        );
+        assert_eq!(
+            eval_err("2 m to s"),
+            EvalError::IncompatibleUnits("2 m to 1 s".into())
+        );
+        assert_eq!(eval_err("2 m to 3"), EvalError::NotAUnit("3".into()));
+        assert_eq!(eval_err("3 m to 2 cm"), EvalError::NotAUnit("2 cm".into()));
+        // anywhere else a unit name is a variable
+        assert_eq!(eval_err("2 * h"), EvalError::UndefinedVariable("h".into()));
+        let mut env = Environment::default();
+        env.set("s", Value::Int(5));
+        let eq = "s * 2 s".parse::<Expression>().unwrap();
+        let value = PostExpression::try_from(eq).unwrap().eval(&env).unwrap();
+        assert_eq!(value.to_string(), "10 s");
+        assert_eq!(
+            eval_err("2 m ^ 0.5"),
+            EvalError::InvalidExponent("1 m ^ 0.5".into())
+        );
+        assert_eq!(
+            eval_err("sqrt(4 m^2)"),
+            EvalError::Domain("sqrt(4 m^2)".into())
+        );
+    }
+    #[test]
+    fn test_eval_lists() {
+        let shown = |input| eval_str(input).to_string();
+        assert_eq!(shown("[1, 2 + 3, [4]]"), "[1, 5, [4]]");
+        assert_eq!(shown("[]"), "[]");
+        assert_eq!(shown("2..5"), "[2, 3, 4, 5]");
+        assert_eq!(shown("3..1"), "[]");
+        assert_eq!(shown("[1, 2] * [3, 4] - 1"), "[2, 7]");
+        assert_eq!(shown("-(1..3) ^ 2"), "[-1, -4, -9]");
+        assert_eq!(shown("[1, 2] m"), "[1 m, 2 m]");
+        assert_eq!(shown("sum(1..10) / mean([1, 3])"), "27.5");
+        let postfix = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq).unwrap().to_string()
+        };
+        assert_eq!(postfix("[1, 2 * x]"), "1 2 x * list(2)");
+        assert_eq!(postfix("1..n + 1"), "1 n 1 + ..");
+        assert_eq!(postfix("max([], [a])"), "list(0) a list(1) max(2)");
+        let convert_err = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(convert_err("[1, 2").contains("Unclosed `[`"));
+        assert!(convert_err("[(1]").contains("Unclosed parenthesis"));
+        assert!(convert_err("(1, 2)").contains("`,` can only separate"));
+        assert!(convert_err("[1,]").contains("Expected an operand before `]`"));
+        let eval_err = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap_err()
+                .error
+        };
+        assert_eq!(eval_err("[1, 2] + [3]"), EvalError::LengthMismatch(2, 1));
+        assert_eq!(eval_err("1..2.5"), EvalError::NotInteger("1..2.5".into()));
+        assert_eq!(
+            eval_err("0..2^30"),
+            EvalError::TooLong("0..1073741824".into())
+        );
+    }
+    #[test]
+    fn test_series() {
+        let postfix = |input: &str| {
+            let eq = input.parse::<Expression>().unwrap();
+            PostExpression::try_from(eq).unwrap()
+        };
+        assert_eq!(
+            postfix("sum(i, 1, 100, i^2)").to_string(),
+            "1 100 sum(i, i 2 ^)"
+        );
+        // a name first makes a series even if the body doesn't use it, and hides a constant
+        assert_eq!(postfix("sum(a, 1, 2, 3)").to_string(), "1 2 sum(a, 3)");
+        assert_eq!(eval_str("sum(k, 1, 3, 2)"), Value::Int(6));
+        assert_eq!(eval_str("sum(e, 1, 3, 1)"), Value::Int(3));
+        assert_eq!(eval_str("sum(e, 1, 3, e)"), Value::Int(6));
+        assert_eq!(postfix("sum(1, a, 2, 3)").to_string(), "1 a 2 3 sum(4)");
+        assert_eq!(postfix("sum(a, 1, 2)").to_string(), "a 1 2 sum(3)");
+        assert_eq!(eval_str("sum(i, 1, 100, i^2)"), Value::Int(338_350));
+        assert_eq!(eval_str("product(k, 1, 5, k)"), Value::Int(120));
+        assert_eq!(eval_str("sum(i, 1, 0, i)"), Value::Int(0));
+        assert_eq!(
+            eval_str("sum(i, 1, 3, sum(j, 1, i, i * j))"),
+            Value::Int(25)
+        );
+        // skips inside the bounds and the body still land where they should
+        assert_eq!(
+            eval_str("sum(i, 0 || 2, 4, i > 3 ? 10 : i)"),
+            Value::Int(16)
+        );
+        let mut env = Environment::new(Arithmetic::Exact);
+        env.set("n", Value::Int(3));
+        let harmonic = postfix("sum(k, 1, n, 1 / k)").eval(&env).unwrap();
+        assert_eq!(harmonic.to_string(), "11/6");
+        env.define(
+            "geometric",
+            UserFunction {
+                params: vec!["x".into()],
+                body: postfix("sum(k, 0, n, x ^ k)"),
+            },
+        );
+        assert_eq!(
+            postfix("geometric(2)").eval(&env).unwrap().to_string(),
+            "15"
+        );
+        let err = postfix("sum(k, 1, 3, 1 / (k - 2))")
+            .eval(&Environment::default())
+            .unwrap_err();
+        assert_eq!(err.error, EvalError::DivisionByZero);
+        assert_eq!(err.span, Span::new(15, 16));
+    }
+    #[test]
+    fn test_eval_stack_underflow() {
+        let eq = postfix(vec![
+            MathToken::IntOperand(3),
+            MathToken::Operator(OperatorType::Add),
+        ]);
+        assert_eq!(
+            eq.eval(&Environment::default()).unwrap_err().error,
+            EvalError::StackUnderflow
+        );
+        let eq = postfix(vec![]);
+        assert_eq!(
+            eq.eval(&Environment::default()).unwrap_err().error,
+            EvalError::StackUnderflow
+        );
+    }
+    #[test]
+    fn test_function_postfix() {
+        let eq = "max(1, 2 + 3) * 2".parse::<Expression>().unwrap();
+        assert_eq!(
+            PostExpression::try_from(eq).unwrap(),
+            postfix(vec![
+                MathToken::IntOperand(1),
+                MathToken::IntOperand(2),
+                MathToken::IntOperand(3),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::Call("max".into(), 2),
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Mul),
+            ])
+        );
+    }
+    #[test]
+    fn test_eval_functions() {
+        assert_eq!(eval_str("sqrt(16)"), Value::Float(4.0));
+        assert_eq!(eval_str("log(100, 10)"), Value::Float(2.0));
+        assert_eq!(eval_str("min(3, 1, 2)"), Value::Int(1));
+        assert_eq!(eval_str("abs(-3)"), Value::Int(3));
+        assert_eq!(eval_str("-abs(min(4, -3)) ^ 2"), Value::Int(-9));
+        assert_eq!(eval_str("round(10 / 3, 2)"), Value::Float(3.33));
+        assert_eq!(eval_str("round(pi)"), Value::Int(3));
+        assert_eq!(eval_str("cos(0) + e - e"), Value::Float(1.0));
+    }
+    #[test]
+    fn test_eval_complex() {
+        let complex = |re, im| Value::Complex(Complex64::new(re, im));
+        assert_eq!(eval_str("sqrt(-4)"), complex(0.0, 2.0));
+        assert_eq!(eval_str("(1 + 2i) * (3 - i)"), complex(5.0, 5.0));
+        assert_eq!(eval_str("e ^ (i * pi)"), Value::Float(-1.0));
+        assert_eq!(eval_str("i ^ 2"), Value::Float(-1.0));
+        assert_eq!(eval_str("(2 + 3i) / (2 + 3i)"), Value::Float(1.0));
+        assert_eq!(eval_str("arg(-1)"), Value::Float(std::f64::consts::PI));
+    }
+    #[test]
+    fn test_eval_bitwise() {
+        assert_eq!(eval_str("1 | 2 & 3"), Value::Int(3));
+        assert_eq!(eval_str("1 << 2 + 1"), Value::Int(8));
+        assert_eq!(eval_str("6 & 3 xor 1 | 8"), Value::Int(11));
+        assert_eq!(eval_str("~0xf0 & 0xff"), Value::Int(0x0f));
+        assert_eq!(eval_str("-~1"), Value::Int(2));
+        assert_eq!(eval_str("10 % 4 * 2"), Value::Int(4));
+        // literals beyond `isize` keep all their bits
+        assert_eq!(eval_str("0xFFFFFFFFFFFFFFFF & 0xFF"), Value::Int(0xFF));
+        assert_eq!(eval_str("0x8000000000000001 & 1"), Value::Int(1));
+        assert_eq!(
+            eval_str("0xFFFFFFFFFFFFFFFF | 1"),
+            Value::Rational(BigRational::from_integer(u64::MAX.into()))
+        );
+        let eq = "3 ~ 4".parse::<Expression>().unwrap();
+        assert!(PostExpression::try_from(eq).is_err());
+    }
+    #[test]
+    fn test_eval_comparisons() {
+        assert_eq!(eval_str("1 + 2 == 3"), Value::Int(1));
+        assert_eq!(eval_str("2 < 1 || 3 >= 3"), Value::Int(1));
+        assert_eq!(eval_str("1 != 1.0"), Value::Int(0));
+        assert_eq!(eval_str("!(1 < 2) + !0"), Value::Int(1));
+        assert_eq!(eval_str("6 & 3 == 2"), Value::Int(1));
+        assert_eq!(eval_str("1 < 2 && 2 < 3 && 5"), Value::Int(1));
+        assert_eq!(eval_str("0 ? 1 : 0 ? 2 : 3"), Value::Int(3));
+        assert_eq!(eval_str("1 ? 0 ? 5 : 6 : 7"), Value::Int(6));
+        assert_eq!(eval_str("(1 ? 2 : 3) + 10"), Value::Int(12));
+    }
+    #[test]
+    fn test_short_circuit() {
+        assert_eq!(eval_str("0 && 1 / 0"), Value::Int(0));
+        assert_eq!(eval_str("2 || 1 / 0"), Value::Int(1));
+        assert_eq!(eval_str("1 < 2 ? 4 : 1 / 0"), Value::Int(4));
+        assert_eq!(eval_str("1 > 2 ? 1 / 0 : 5"), Value::Int(5));
+        assert_eq!(eval_str("0 && 1 / 0 || 0 ? 1 / 0 : 8"), Value::Int(8));
+        let eq = "1 && 1 / 0".parse::<Expression>().unwrap();
+        let err = PostExpression::try_from(eq)
+            .unwrap()
+            .eval(&Environment::default());
+        assert_eq!(err.unwrap_err().error, EvalError::DivisionByZero);
+    }
+    #[test]
+    fn test_skip_targets() {
+        let eq = "a ? b : c".parse::<Expression>().unwrap();
+        let postfix = PostExpression::try_from(eq).unwrap();
+        assert_eq!(
+            postfix.tokens(),
+            [
+                MathToken::Identifier("a".into()),
+                MathToken::Skip(SkipIf::False, 4),
+                MathToken::Identifier("b".into()),
+                MathToken::Skip(SkipIf::Always, 5),
+                MathToken::Identifier("c".into()),
+                MathToken::Operator(OperatorType::Cond),
+            ]
+        );
+        assert_eq!(postfix.to_string(), "a b c ?:");
+    }
+    #[test]
+    fn test_conditional_errors() {
+        let error = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("x ? 1").contains("`?` is missing its `:`"));
+        assert!(error("1 : 2").contains("`:` without a matching `?`"));
+        assert!(error("(1 ? 2) : 3").contains("`?` is missing its `:`"));
+        assert!(error("? 1 : 2").contains("Expected an operand before `?`"));
+        assert!(error("1 ! 2").contains("only goes before its operand"));
+    }
+    fn rpn(input: &str) -> Result<PostExpression, Spanned<PostExpressionError>> {
+        PostExpression::from_rpn(input.parse::<Expression>().unwrap())
+    }
+    #[test]
+    fn test_from_rpn() {
+        let infix =
+            |input: &str| PostExpression::try_from(input.parse::<Expression>().unwrap()).unwrap();
+        assert_eq!(rpn("3 4 + 2 *").unwrap(), infix("(3 + 4) * 2"));
+        assert_eq!(rpn("2 neg 3 ^").unwrap(), infix("(-2) ^ 3"));
+        assert_eq!(rpn("a b max(2) sqrt").unwrap(), infix("sqrt(max(a, b))"));
+        assert_eq!(rpn("1 2 3 ?:").unwrap().to_string(), "1 2 3 ?:");
+        assert_eq!(rpn("i 1 3 i sum(4)").unwrap(), infix("sum(i, 1, 3, i)"));
+        assert_eq!(
+            rpn("k 1 n k 2 ^ product(4) 1 +").unwrap(),
+            infix("product(k, 1, n, k^2) + 1")
+        );
+        assert_eq!(rpn("1 k 2 3 sum(4)").unwrap(), infix("sum(1, k, 2, 3)"));
+        let postfix = rpn("1 2 3 ? : 4 +").unwrap();
+        assert_eq!(postfix.to_string(), "1 2 3 ?: 4 +");
+        assert_eq!(
+            postfix.eval(&Environment::default()).unwrap(),
+            Value::Int(6)
+        );
+    }
+    #[test]
+    fn test_nesting_depth() {
+        let too_deep = |input: &str| {
+            matches!(
+                PostExpression::try_from(input.parse::<Expression>().unwrap()),
+                Err(Spanned {
+                    error: PostExpressionError::TooDeep,
+                    ..
+                })
+            )
+        };
+        let nested = |open: &str, close: &str, n| format!("{}1{}", open.repeat(n), close.repeat(n));
+        assert!(!too_deep(&nested("[", "]", MAX_DEPTH - 1)));
+        assert!(too_deep(&nested("[", "]", MAX_DEPTH)));
+        assert!(!too_deep(&nested("(", ")", 5000)));
+        assert!(too_deep(&nested("sum(i, 1, 2, ", ")", MAX_DEPTH)));
+        let err = PostExpression::try_from(nested("-", "", 5000).parse::<Expression>().unwrap())
+            .unwrap_err();
+        assert_eq!(err.span, Span::new(5000 - MAX_DEPTH, 5000 - MAX_DEPTH + 1));
+        assert!(matches!(
+            rpn(&format!("1{}", " neg".repeat(5000))),
+            Err(Spanned {
+                error: PostExpressionError::TooDeep,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn test_rpn_stack_depth() {
+        let error = |input: &str| {
+            let err = rpn(input).unwrap_err();
+            (err.error.to_string(), err.span)
+        };
+        assert_eq!(
+            error("3 + 4"),
+            (
+                "Invalid reverse Polish notation - `+` needs 2 operand(s), but the stack only holds 1"
+                    .to_string(),
+                Span::new(2, 3)
+            )
+        );
+        assert_eq!(
+            error("1 2 3 +"),
+            (
+                "Invalid reverse Polish notation - 2 values are left on the stack, is an operator missing?"
+                    .to_string(),
+                Span::new(0, 7)
+            )
+        );
+        assert_eq!(error("1 2 max(3)").1, Span::new(4, 10));
+        assert!(error("").0.contains("Nothing to evaluate"));
+        assert!(error("1 2 max").0.contains("e.g. `max(2)`"));
+        assert!(error("1 2 sqrt(2)").0.contains("takes 1 argument"));
+        assert!(error("(1 2 +)").0.contains("Unexpected `(`"));
+        assert!(error("1 2 3 ?").0.contains("`?:`"));
+        assert!(error("f(x) 1").0.contains("e.g. `f(2)`"));
+    }
+    #[test]
+    fn test_eval_fixed_width() {
+        let eval = |input: &str, width, wrapping| {
+            let env = Environment::new(Arithmetic::Fixed { width, wrapping });
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap()
+                .eval(&env)
+                .map_err(|e| e.error)
+        };
+        let int = |n: i64| Value::Rational(BigRational::from_integer(n.into()));
+        assert_eq!(eval("127 + 1", Width::I8, true), Ok(int(-128)));
+        assert_eq!(eval("127 + 1", Width::I8, false), Err(EvalError::Overflow));
+        assert_eq!(eval("-128", Width::I8, false), Ok(int(-128)));
+        assert_eq!(eval("128", Width::I8, false), Err(EvalError::Overflow));
+        assert_eq!(eval("-7 / 2", Width::I32, false), Ok(int(-3)));
+        assert_eq!(eval("~0", Width::U16, false), Ok(int(0xffff)));
+        assert_eq!(eval("1 << 8", Width::U8, false), Err(EvalError::Overflow));
+        assert_eq!(eval("1 << 8", Width::U8, true), Ok(int(0)));
+        assert_eq!(eval("0 - 1", Width::U64, false), Err(EvalError::Underflow));
+        assert_eq!(
+            eval("1.5", Width::I32, false),
+            Err(EvalError::NotInteger("1.5".into()))
+        );
+        assert_eq!(
+            eval("0xffff_ffff_ffff_ffff", Width::U64, false)
+                .unwrap()
+                .to_string(),
+            "18446744073709551615"
+        );
+        assert_eq!(eval("sqrt(17)", Width::I32, false), Ok(int(4)));
+        assert_eq!(
+            eval("3 m", Width::I32, false),
+            Err(EvalError::IntegerUnits("m".into()))
+        );
+    }
+    #[test]
+    fn test_eval_exact() {
+        let env = Environment::new(Arithmetic::Exact);
+        let eval = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap()
+                .eval(&env)
+                .map_err(|e| e.error)
+        };
+        let ratio = |n: i64, d: i64| Value::Rational(BigRational::new(n.into(), d.into()));
+        assert_eq!(eval("1/3 + 1/6"), Ok(ratio(1, 2)));
+        assert_eq!(eval("1 / 3 * 3"), Ok(ratio(1, 1)));
+        assert_eq!(eval("0.1 + 0.2"), Ok(ratio(3, 10)));
+        assert_eq!(eval("abs(-1/4) + floor(7/2)"), Ok(ratio(13, 4)));
+        assert_eq!(eval("sqrt(16)"), Ok(ratio(4, 1)));
+        assert_eq!(
+            eval("2 ^ 100").unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(eval("sqrt(2)"), Err(EvalError::Inexact("sqrt(2)".into())));
+        assert_eq!(eval("2 ^ 0.5"), Err(EvalError::Inexact("2 ^ 1/2".into())));
+        assert_eq!(eval("pi"), Err(EvalError::Inexact("pi".into())));
+        assert_eq!(eval("1 / (1/2 - 0.5)"), Err(EvalError::DivisionByZero));
+    }
+    #[test]
+    fn test_postfix_display() {
+        let eq = "-(3 + 4) * max(1, 2, x)".parse::<Expression>().unwrap();
+        assert_eq!(
+            PostExpression::try_from(eq).unwrap().to_string(),
+            "3 4 + neg 1 2 x max(3) *"
+        );
+    }
+    #[test]
+    fn test_function_arity() {
+        let compile = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap()).map_err(|e| e.error)
+        };
+        assert!(matches!(
+            compile("sqrt(1, 2)"),
+            Err(PostExpressionError::WrongArity { got: 2, .. })
+        ));
+        assert!(matches!(
+            compile("min()"),
+            Err(PostExpressionError::WrongArity { got: 0, .. })
+        ));
+        assert!(compile("f()").is_ok());
+        assert!(compile("sqrt(1,)").is_err());
+        assert!(compile("sqrt(,1)").is_err());
+        assert!(compile("1, 2").is_err());
+        assert!(compile("sqrt((1, 2))").is_err());
+        assert!(compile("()").is_err());
+    }
+    #[test]
+    fn test_undefined_function() {
+        let eq = "nope(1)".parse::<Expression>().unwrap();
+        assert_eq!(
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap_err()
+                .error,
+            EvalError::UndefinedFunction("nope".into())
+        );
+    }
+    #[test]
+    fn test_unknown_unit() {
+        let expr = postfix(vec![
+            MathToken::IntOperand(2),
+            MathToken::Unit("furlong".into()),
+            MathToken::Operator(OperatorType::Mul),
+        ]);
+        let env = Environment::default();
+        let error = EvalError::UndefinedVariable("furlong".into());
+        assert_eq!(expr.eval(&env).unwrap_err().error, error);
+        let program = crate::bytecode::Program::compile(&expr, &env);
+        assert_eq!(program.eval(&env).unwrap_err().error, error);
+    }
+    #[test]
+    fn test_unmatched_parens() {
+        let compile = |input: &str| PostExpression::try_from(input.parse::<Expression>().unwrap());
+        let err = compile("(1 + 2").unwrap_err();
+        assert_eq!(
+            err.error.to_string(),
+            "Failed to convert infix to postfix - Unclosed parenthesis"
+        );
+        assert_eq!(err.span, Span::new(0, 1));
+        assert_eq!(compile("max(1, (2)").unwrap_err().span, Span::new(3, 4));
+        assert_eq!(compile("1 + 2)").unwrap_err().span, Span::new(5, 6));
+    }
+    #[test]
+    fn test_compile_error_spans() {
+        let span = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap_err()
+                .span
+        };
+        assert_eq!(span("3 + * 4"), Span::new(4, 5));
+        assert_eq!(span("3 +"), Span::new(2, 3));
+        assert_eq!(span("1 + sqrt(1, 2)"), Span::new(4, 14));
+        assert_eq!(span("2 * ()"), Span::new(4, 6));
+    }
+    #[test]
+    fn test_eval_error_spans() {
+        let mut env = Environment::default();
+        env.define(
+            "inv",
+            UserFunction {
+                params: vec!["x".into()],
+                body: PostExpression::try_from("1 / x".parse::<Expression>().unwrap()).unwrap(),
+            },
+        );
+        let span = |input: &str| {
+            PostExpression::try_from(input.parse::<Expression>().unwrap())
+                .unwrap()
+                .eval(&env)
+                .unwrap_err()
+                .span
+        };
+        assert_eq!(span("1 + 2 / (3 - 3)"), Span::new(6, 7));
+        assert_eq!(span("2 * nope"), Span::new(4, 8));
+        // errors inside a user function point at the call
+        assert_eq!(span("1 + inv(0)"), Span::new(4, 10));
+    }
+    #[test]
+    fn test_missing_left_operand() {
+        let eq = "* 3".parse::<Expression>().unwrap();
+        assert!(PostExpression::try_from(eq).is_err());
     }
     #[test]
     fn test_eval_power() {
@@ -242,6 +1805,12 @@ mod tests {
             MathToken::Operator(OperatorType::Pow),
             MathToken::IntOperand(4),
         ]);
-        assert_eq!(PostExpression::try_from(eq).unwrap().eval(), 81);
+        assert_eq!(
+            PostExpression::try_from(eq)
+                .unwrap()
+                .eval(&Environment::default())
+                .unwrap(),
+            Value::Int(81)
+        );
     }
 }
@@ -0,0 +1,435 @@
+//! Operators and functions: how they are written, how tightly they bind and what they compute.
+//!
+//! Builtin operators are described by the `OPERATORS` table, which the lexer and parser read
+//! their symbols, arities, precedences and associativity from. The table holds no
+//! implementations: evaluating a builtin operator goes through the hardcoded
+//! `OperatorType::apply` and `OperatorType::apply_unary`, which handle every kind of value
+//! with its own rules. Only operators and functions registered at run time carry an
+//! `Implementation`.
+use crate::builtins::{self, Arity, Builtin};
+use crate::postfix::EvalError;
+use crate::tokens::{Associativity, OperatorType};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::{fmt, sync::Arc};
+use thiserror::Error;
+
+/// What a registered operator or function computes from its operands.
+pub type Implementation = Arc<dyn Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync>;
+
+/// How a builtin operator is written and how tightly it binds, see the module docs for
+/// how it is evaluated.
+#[derive(Debug)]
+pub struct OperatorDef {
+    pub op: OperatorType,
+    pub symbol: &'static str,
+    /// Number of operands, 1 for prefix operators and 2 for infix ones.
+    pub arity: usize,
+    /// Greater value means higher precedence.
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+const fn def(
+    op: OperatorType,
+    symbol: &'static str,
+    arity: usize,
+    precedence: u8,
+    associativity: Associativity,
+) -> OperatorDef {
+    OperatorDef {
+        op,
+        symbol,
+        arity,
+        precedence,
+        associativity,
+    }
+}
+
+/// The builtin operators, `-` and `+` are both prefix and infix operators.
+///
+/// Unary signs bind tighter than `*` but looser than `^`, so `-2 ^ 2` is `-(2 ^ 2)`.
+/// A unit after a number multiplies without `*`, binding between `^` and the signs.
+/// Ranges like `1..n + 1` bind looser than arithmetic.
+/// Bitwise operators bind looser than arithmetic, in the order C uses: shifts, `&`, `xor`, `|`.
+/// Comparisons come next so `a & 1 == 0` tests the bit, then `&&`, `||`, `to` and finally `?:`.
+/// Unit conversion binds loosest of the infix operators, `60 mi/h to km/h` converts the speed.
+/// Precedences are steps of 10, leaving room for registered operators in between.
+static OPERATORS: [OperatorDef; 27] = {
+    use Associativity::{Left, Right};
+    use OperatorType as Op;
+    [
+        def(Op::Pow, "^", 2, 110, Right),
+        def(Op::Neg, "-", 1, 100, Right),
+        def(Op::Pos, "+", 1, 100, Right),
+        def(Op::BitNot, "~", 1, 100, Right),
+        def(Op::Not, "!", 1, 100, Right),
+        def(Op::Mul, "*", 2, 90, Left),
+        // after `Mul`, so `*` is never read as the implicit product
+        def(Op::Implicit, "*", 2, 105, Left),
+        def(Op::Div, "/", 2, 90, Left),
+        def(Op::Rem, "%", 2, 90, Left),
+        def(Op::Add, "+", 2, 80, Left),
+        def(Op::Sub, "-", 2, 80, Left),
+        def(Op::Range, "..", 2, 75, Left),
+        def(Op::Shl, "<<", 2, 70, Left),
+        def(Op::Shr, ">>", 2, 70, Left),
+        def(Op::BitAnd, "&", 2, 60, Left),
+        def(Op::Xor, "xor", 2, 50, Left),
+        def(Op::BitOr, "|", 2, 40, Left),
+        def(Op::Eq, "==", 2, 30, Left),
+        def(Op::Ne, "!=", 2, 30, Left),
+        def(Op::Lt, "<", 2, 30, Left),
+        def(Op::Le, "<=", 2, 30, Left),
+        def(Op::Gt, ">", 2, 30, Left),
+        def(Op::Ge, ">=", 2, 30, Left),
+        def(Op::And, "&&", 2, 20, Left),
+        def(Op::Or, "||", 2, 10, Left),
+        def(Op::To, "to", 2, 5, Left),
+        def(Op::Cond, "?:", 3, 0, Right),
+    ]
+};
+
+/// The definition of a builtin operator.
+///
+/// # Panics
+/// For a registered operator, those carry their own definition.
+#[must_use]
+pub fn builtin(op: &OperatorType) -> &'static OperatorDef {
+    OPERATORS
+        .iter()
+        .find(|def| def.op == *op)
+        .expect("Every builtin operator has a definition")
+}
+
+/// The builtin operator written `symbol` with `arity` operands.
+#[must_use]
+pub fn builtin_operator(symbol: &str, arity: usize) -> Option<OperatorType> {
+    OPERATORS
+        .iter()
+        .find(|def| def.symbol == symbol && def.arity == arity)
+        .map(|def| def.op.clone())
+}
+
+/// Symbols made of letters are read like names, e.g. `xor`, and need spaces around them.
+#[must_use]
+pub fn is_word(symbol: &str) -> bool {
+    symbol.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && symbol.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Names, or punctuation that can't be mistaken for numbers, grouping or the other syntax.
+fn is_valid_symbol(symbol: &str) -> bool {
+    is_word(symbol)
+        || (!symbol.is_empty()
+            && !matches!(symbol, "=" | "?" | ":")
+            && !symbol.chars().any(|c| {
+                c.is_alphanumeric() || c.is_whitespace() || matches!(c, '_' | '.' | ',' | '(' | ')')
+            }))
+}
+
+/// An operator added by a library user, e.g. `**` for powers or `mod` for a floored remainder.
+pub struct CustomOperator {
+    symbol: String,
+    arity: usize,
+    precedence: u8,
+    associativity: Associativity,
+    implementation: Implementation,
+}
+
+impl fmt::Debug for CustomOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomOperator")
+            .field("symbol", &self.symbol)
+            .field("arity", &self.arity)
+            .field("precedence", &self.precedence)
+            .field("associativity", &self.associativity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A symbol has one definition per registry, so it identifies the operator.
+impl PartialEq for CustomOperator {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol && self.arity == other.arity
+    }
+}
+
+impl Eq for CustomOperator {}
+
+impl CustomOperator {
+    #[must_use]
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    #[must_use]
+    pub const fn arity(&self) -> usize {
+        self.arity
+    }
+
+    #[must_use]
+    pub const fn precedence(&self) -> u8 {
+        self.precedence
+    }
+
+    #[must_use]
+    pub const fn associativity(&self) -> Associativity {
+        self.associativity
+    }
+
+    /// Apply the operator, `operands` holds as many values as its arity.
+    ///
+    /// # Errors
+    /// When the operator's function fails for `operands`.
+    pub fn apply(&self, operands: &[Value]) -> Result<Value, EvalError> {
+        debug_assert_eq!(operands.len(), self.arity);
+        (self.implementation)(operands)
+    }
+}
+
+/// A function added by a library user.
+#[derive(Clone)]
+pub struct CustomFunction {
+    name: String,
+    arity: Arity,
+    implementation: Implementation,
+}
+
+impl fmt::Debug for CustomFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A function known to a registry, builtin or registered.
+#[derive(Debug, Clone, Copy)]
+pub enum Function<'a> {
+    Builtin(&'static Builtin),
+    Custom(&'a CustomFunction),
+}
+
+impl Function<'_> {
+    #[must_use]
+    pub const fn arity(&self) -> Arity {
+        match self {
+            Self::Builtin(builtin) => builtin.arity,
+            Self::Custom(custom) => custom.arity,
+        }
+    }
+
+    /// Call the function, `args` must already satisfy its arity.
+    ///
+    /// # Errors
+    /// When the function fails for `args`.
+    pub fn call(&self, args: &[Value]) -> Result<Value, EvalError> {
+        match self {
+            Self::Builtin(builtin) => builtin.call(args),
+            Self::Custom(custom) => {
+                debug_assert!(custom.arity.accepts(args.len()));
+                (custom.implementation)(args)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RegistryError {
+    #[error("`{0}` is already defined")]
+    Taken(String),
+    #[error(
+        "`{0}` can't be used as an operator, use a name or punctuation other than `( ) , . = ? :`"
+    )]
+    InvalidSymbol(String),
+    #[error("`{0}` can't be used as a function name")]
+    InvalidName(String),
+    #[error("Operators take 1 or 2 operands, not {0}")]
+    InvalidArity(usize),
+}
+
+/// Operators and functions available to the parser and evaluator on top of the builtin ones.
+///
+/// Registered operators are written like the builtin ones: prefix operators take one operand
+/// and infix operators two. Their precedence is compared with the builtin precedences, which
+/// go from `0` for `?:` to `110` for `^` in steps of 10.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    operators: Vec<Arc<CustomOperator>>,
+    functions: HashMap<String, CustomFunction>,
+}
+
+impl Registry {
+    /// Whether `name` is taken by an operator, function or constant.
+    fn is_taken(&self, name: &str) -> bool {
+        OPERATORS.iter().any(|def| def.symbol == name)
+            || self.operators.iter().any(|op| op.symbol == name)
+            || self.function(name).is_some()
+            || builtins::constant(name).is_some()
+            || matches!(name, "neg" | "pos")
+    }
+
+    /// Add an operator taking `arity` operands, 1 for a prefix and 2 for an infix operator.
+    ///
+    /// # Errors
+    /// When the symbol is already an operator, function or constant, can't be told apart from
+    /// the rest of the syntax, or the arity is neither 1 nor 2.
+    pub fn add_operator(
+        &mut self,
+        symbol: &str,
+        arity: usize,
+        precedence: u8,
+        associativity: Associativity,
+        implementation: impl Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync + 'static,
+    ) -> Result<(), RegistryError> {
+        if !matches!(arity, 1 | 2) {
+            return Err(RegistryError::InvalidArity(arity));
+        }
+        if !is_valid_symbol(symbol) {
+            return Err(RegistryError::InvalidSymbol(symbol.to_string()));
+        }
+        if self.is_taken(symbol) {
+            return Err(RegistryError::Taken(symbol.to_string()));
+        }
+        self.operators.push(Arc::new(CustomOperator {
+            symbol: symbol.to_string(),
+            arity,
+            precedence,
+            associativity,
+            implementation: Arc::new(implementation),
+        }));
+        Ok(())
+    }
+
+    /// Add a function called `name`, its arguments are checked against `arity` before it is called.
+    ///
+    /// # Errors
+    /// When the name is already an operator, function or constant, or isn't a valid name.
+    pub fn add_function(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        implementation: impl Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync + 'static,
+    ) -> Result<(), RegistryError> {
+        if !is_word(name) {
+            return Err(RegistryError::InvalidName(name.to_string()));
+        }
+        if self.is_taken(name) {
+            return Err(RegistryError::Taken(name.to_string()));
+        }
+        self.functions.insert(
+            name.to_string(),
+            CustomFunction {
+                name: name.to_string(),
+                arity,
+                implementation: Arc::new(implementation),
+            },
+        );
+        Ok(())
+    }
+
+    /// The operator written `symbol`, the infix one for symbols that are also prefix operators.
+    #[must_use]
+    pub fn operator(&self, symbol: &str) -> Option<OperatorType> {
+        self.operators
+            .iter()
+            .find(|op| op.symbol == symbol)
+            .map(|op| OperatorType::Custom(Arc::clone(op)))
+            .or_else(|| builtin_operator(symbol, 2))
+            .or_else(|| builtin_operator(symbol, 1))
+    }
+
+    /// Symbols of every operator the lexer reads, builtin and registered.
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        OPERATORS
+            .iter()
+            .filter(|def| def.arity < 3)
+            .map(|def| def.symbol)
+            .chain(self.operators.iter().map(|op| op.symbol.as_str()))
+    }
+
+    pub fn function(&self, name: &str) -> Option<Function<'_>> {
+        builtins::function(name)
+            .map(Function::Builtin)
+            .or_else(|| self.functions.get(name).map(Function::Custom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_definitions() {
+        assert_eq!(builtin(&OperatorType::Sub).symbol, "-");
+        assert_eq!(builtin_operator("-", 1), Some(OperatorType::Neg));
+        assert_eq!(builtin_operator("-", 2), Some(OperatorType::Sub));
+        assert_eq!(builtin_operator("?:", 2), None);
+        let registry = Registry::default();
+        assert_eq!(registry.operator("!"), Some(OperatorType::Not));
+        assert_eq!(registry.operator("xor"), Some(OperatorType::Xor));
+        assert!(registry.symbols().all(|symbol| symbol != "?:"));
+    }
+    #[test]
+    fn test_add_operator() {
+        let mut registry = Registry::default();
+        registry
+            .add_operator("**", 2, 110, Associativity::Right, |args| {
+                OperatorType::Pow.apply(args[0].clone(), args[1].clone())
+            })
+            .unwrap();
+        let Some(OperatorType::Custom(op)) = registry.operator("**") else {
+            panic!("`**` is registered");
+        };
+        assert_eq!(op.symbol(), "**");
+        assert_eq!(
+            op.apply(&[Value::Int(2), Value::Int(5)]),
+            Ok(Value::Int(32))
+        );
+        assert!(registry.symbols().any(|symbol| symbol == "**"));
+    }
+    #[test]
+    fn test_add_errors() {
+        let mut registry = Registry::default();
+        let noop = |args: &[Value]| Ok(args[0].clone());
+        let mut add = |symbol: &str, arity| {
+            registry.add_operator(symbol, arity, 50, Associativity::Left, noop)
+        };
+        assert_eq!(add("<<", 2), Err(RegistryError::Taken("<<".into())));
+        assert_eq!(add("max", 2), Err(RegistryError::Taken("max".into())));
+        assert_eq!(add("pi", 1), Err(RegistryError::Taken("pi".into())));
+        assert_eq!(add("=", 2), Err(RegistryError::InvalidSymbol("=".into())));
+        assert_eq!(add("+1", 2), Err(RegistryError::InvalidSymbol("+1".into())));
+        assert_eq!(add("..", 2), Err(RegistryError::InvalidSymbol("..".into())));
+        assert_eq!(add("@", 3), Err(RegistryError::InvalidArity(3)));
+        assert_eq!(add("@", 2), Ok(()));
+        assert_eq!(add("@", 1), Err(RegistryError::Taken("@".into())));
+        assert_eq!(
+            registry.add_function("@", Arity::Exact(1), noop),
+            Err(RegistryError::InvalidName("@".into()))
+        );
+        assert_eq!(
+            registry.add_function("sqrt", Arity::Exact(1), noop),
+            Err(RegistryError::Taken("sqrt".into()))
+        );
+    }
+    #[test]
+    fn test_add_function() {
+        let mut registry = Registry::default();
+        registry
+            .add_function("twice", Arity::Exact(1), |args| {
+                OperatorType::Mul.apply(args[0].clone(), Value::Int(2))
+            })
+            .unwrap();
+        let twice = registry.function("twice").unwrap();
+        assert_eq!(twice.arity(), Arity::Exact(1));
+        assert_eq!(twice.call(&[Value::Int(4)]), Ok(Value::Int(8)));
+        assert!(matches!(
+            registry.function("sqrt"),
+            Some(Function::Builtin(_))
+        ));
+        assert!(registry.function("thrice").is_none());
+    }
+}
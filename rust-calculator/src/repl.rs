@@ -0,0 +1,243 @@
+use log::{debug, warn};
+use rust_calculator::{Environment, Format, Syntax};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{env, path::PathBuf};
+
+const PROMPT: &str = "oxc> ";
+const HISTORY_FILE: &str = ".oxc_history";
+const HELP: &str = "\
+Enter an infix expression to evaluate it, e.g. `(3 + 4) * 2`.
+Assign variables with `name = expression` and define functions with `f(x, y) = expression`,
+they are kept for the rest of the session.
+The previous result is available as `ans`.
+
+Commands:
+  :help  Show this message
+  :quit  Leave the calculator (Ctrl-D also works)";
+
+/// What the REPL loop should do after a line has been handled.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Print the message and keep reading lines.
+    Print(String),
+    /// Report an error and keep reading lines.
+    Error(String),
+    /// Nothing to show, e.g. for an empty line.
+    Nothing,
+    Quit,
+}
+
+/// State carried between lines of an interactive session.
+#[derive(Debug, Default)]
+pub struct Session {
+    env: Environment,
+    format: Format,
+    syntax: Syntax,
+}
+
+impl Session {
+    pub const fn new(env: Environment, format: Format) -> Self {
+        Self {
+            env,
+            format,
+            syntax: Syntax::Infix,
+        }
+    }
+
+    /// Read lines written in `syntax` instead of infix.
+    pub const fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn handle_line(&mut self, line: &str) -> Action {
+        match line.trim() {
+            "" => Action::Nothing,
+            ":quit" | ":q" => Action::Quit,
+            ":help" | ":h" => Action::Print(HELP.to_string()),
+            cmd if cmd.starts_with(':') => {
+                Action::Error(format!("Unknown command `{cmd}`, try `:help`"))
+            }
+            expr => match rust_calculator::evaluate(expr, self.syntax, &mut self.env) {
+                Ok(Some(result)) => {
+                    let shown = self.format.show(&result);
+                    self.env.set("ans", result);
+                    Action::Print(shown)
+                }
+                Ok(None) => Action::Nothing,
+                Err(e) => Action::Error(e.report(expr)),
+            },
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Run the interactive read-eval-print loop until the user quits.
+/// Lines are handled by `session`, which may already hold variables.
+pub fn run(mut session: Session) -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        if let Err(e) = editor.load_history(path) {
+            debug!("No history loaded from {}: {}", path.display(), e);
+        }
+    }
+
+    loop {
+        match editor.readline(PROMPT) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    editor.add_history_entry(line.as_str())?;
+                }
+                match session.handle_line(&line) {
+                    Action::Print(msg) => println!("{msg}"),
+                    Action::Error(msg) => eprintln!("Error: {msg}"),
+                    Action::Nothing => {}
+                    Action::Quit => break,
+                }
+            }
+            // Ctrl-C abandons the current line, like a shell
+            Err(ReadlineError::Interrupted) => {}
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            warn!("Failed to save history to {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_calculator::value::{Arithmetic, Base, Value, Width};
+
+    #[test]
+    fn test_session_ans() {
+        let mut session = Session::default();
+        assert_eq!(session.handle_line("3 + 4"), Action::Print("7".into()));
+        assert_eq!(session.handle_line("ans * 2"), Action::Print("14".into()));
+        assert_eq!(session.handle_line("ans / 4"), Action::Print("3.5".into()));
+    }
+    #[test]
+    fn test_session_errors_keep_ans() {
+        let mut session = Session::default();
+        assert!(matches!(session.handle_line("ans"), Action::Error(_)));
+        assert_eq!(session.handle_line("2"), Action::Print("2".into()));
+        assert!(matches!(session.handle_line("1 / 0"), Action::Error(_)));
+        assert_eq!(session.handle_line("ans"), Action::Print("2".into()));
+    }
+    #[test]
+    fn test_session_variables() {
+        let mut env = Environment::default();
+        env.set("years", Value::Int(2));
+        let mut session = Session::new(env, Format::default());
+        assert_eq!(
+            session.handle_line("rate = 0.5"),
+            Action::Print("0.5".into())
+        );
+        assert_eq!(
+            session.handle_line("4 * (1 + rate) ^ years"),
+            Action::Print("9".into())
+        );
+        assert_eq!(session.handle_line("ans + 1"), Action::Print("10".into()));
+    }
+    #[test]
+    fn test_session_functions() {
+        let mut session = Session::default();
+        assert_eq!(session.handle_line("sq(x) = x * x"), Action::Nothing);
+        assert_eq!(session.handle_line("sq(4)"), Action::Print("16".into()));
+        assert!(matches!(
+            session.handle_line("bad(x) = x + nope"),
+            Action::Error(_)
+        ));
+    }
+    #[test]
+    fn test_session_error_underline() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.handle_line("3 + * 4"),
+            Action::Error(
+                "Failed to convert infix to postfix - Operator `*` is missing its left operand\n  3 + * 4\n      ^"
+                    .into()
+            )
+        );
+    }
+    #[test]
+    fn test_session_exact() {
+        let env = Environment::new(Arithmetic::Exact);
+        let mut session = Session::new(
+            env,
+            Format {
+                digits: Some(3),
+                ..Format::default()
+            },
+        );
+        assert_eq!(
+            session.handle_line("1/3 + 1/6"),
+            Action::Print("1/2 = 0.5".into())
+        );
+        assert_eq!(
+            session.handle_line("ans * 2 / 3"),
+            Action::Print("1/3 ≈ 0.333".into())
+        );
+    }
+    #[test]
+    fn test_session_fixed_width() {
+        let env = Environment::new(Arithmetic::Fixed {
+            width: Width::I8,
+            wrapping: true,
+        });
+        let format = Format {
+            base: Some(Base::Hex),
+            twos_complement: Some(8),
+            ..Format::default()
+        };
+        let mut session = Session::new(env, format);
+        assert_eq!(
+            session.handle_line("x = 0x7f"),
+            Action::Print("0x7f".into())
+        );
+        assert_eq!(session.handle_line("x + 1"), Action::Print("0x80".into()));
+        assert_eq!(session.handle_line("~ans"), Action::Print("0x7f".into()));
+    }
+    #[test]
+    fn test_session_exact_comparison() {
+        let mut session = Session::new(Environment::new(Arithmetic::Exact), Format::default());
+        assert_eq!(session.handle_line("1/3 < 1/2"), Action::Print("1".into()));
+        assert_eq!(
+            session.handle_line("ans + 1/2"),
+            Action::Print("3/2".into())
+        );
+    }
+    #[test]
+    fn test_session_rpn() {
+        let mut session = Session::default().with_syntax(Syntax::Rpn);
+        assert_eq!(session.handle_line("3 4 + 2 *"), Action::Print("14".into()));
+        assert_eq!(
+            session.handle_line("x = ans neg"),
+            Action::Print("-14".into())
+        );
+        assert_eq!(session.handle_line("sq(x) = x x *"), Action::Nothing);
+        assert_eq!(
+            session.handle_line("x sq(1) 2 max(2)"),
+            Action::Print("196".into())
+        );
+        assert!(matches!(session.handle_line("1 +"), Action::Error(_)));
+    }
+    #[test]
+    fn test_session_commands() {
+        let mut session = Session::default();
+        assert_eq!(session.handle_line("  "), Action::Nothing);
+        assert!(matches!(session.handle_line(":help"), Action::Print(_)));
+        assert!(matches!(session.handle_line(":nope"), Action::Error(_)));
+        assert_eq!(session.handle_line(":quit"), Action::Quit);
+    }
+}
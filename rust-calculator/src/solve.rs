@@ -0,0 +1,548 @@
+//! Numeric root finding for equations like `x^2 - 2 = 0`.
+use crate::bytecode::{Program, Vm};
+use crate::environment::Environment;
+use crate::postfix::{EvalError, PostExpression, PostExpressionError};
+use crate::span::{Span, Spanned};
+use crate::tokens::{Expression, MathToken, OperatorType};
+use crate::value::Value;
+use crate::OxcError;
+use clap::ValueEnum;
+use thiserror::Error;
+
+/// Roots are found to within this much, relative to their size.
+const TOLERANCE: f64 = 1e-12;
+const MAX_ITERATIONS: usize = 100;
+/// An interval is scanned for sign changes in this many steps.
+const SCAN_STEPS: usize = 200;
+/// Roots closer than this, relative to their size, found from neighbouring sign changes
+/// are the same root.
+const SAME_ROOT: f64 = 1e-9;
+/// A sign change with a larger residual is a pole, like in `tan(x)`, rather than a root.
+const MAX_RESIDUAL: f64 = 1e-6;
+
+/// Whether `error` only means the equation has no value at that point, like `ln(x)` for
+/// negative `x`, rather than being wrong everywhere, like an undefined name.
+const fn no_value(error: &SolveError) -> bool {
+    matches!(
+        error,
+        SolveError::NotReal(_)
+            | SolveError::Eval(Spanned {
+                error: EvalError::Domain(_)
+                    | EvalError::DivisionByZero
+                    | EvalError::Overflow
+                    | EvalError::Underflow
+                    | EvalError::InvalidExponent(_)
+                    | EvalError::InvalidShift(_)
+                    | EvalError::NotInteger(_),
+                ..
+            })
+    )
+}
+
+/// Add `root` unless it was already found from a neighbouring sign change.
+fn insert(roots: &mut Vec<Root>, root: Root) {
+    let known = roots
+        .iter()
+        .any(|other| (other.x - root.x).abs() <= SAME_ROOT * (1.0 + root.x.abs()));
+    if !known {
+        roots.push(root);
+    }
+}
+
+/// Ways to find a root.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Method {
+    /// Halve an interval with a sign change until it is small enough.
+    Bisection,
+    /// Follow the tangent from a guess, with the derivative taken numerically.
+    Newton,
+    /// Bisection sped up with secant steps and inverse quadratic interpolation.
+    #[default]
+    Brent,
+}
+
+/// Where to look for a root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Start {
+    /// Between two points. Newton's method starts in the middle.
+    Interval(f64, f64),
+    /// Near one point. Bisection and Brent's method widen an interval around it
+    /// until the sign changes.
+    Guess(f64),
+}
+
+/// A root with how close to zero the equation is there and how many steps it took.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Root {
+    pub x: f64,
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SolveError {
+    #[error("No sign change between {0} and {1}, try another interval")]
+    NoSignChange(f64, f64),
+    #[error("No sign change near {0}, try another guess or an interval")]
+    NoBracket(f64),
+    #[error("No root found after {0} iterations")]
+    NotConverged(usize),
+    #[error("The derivative is zero at {0}")]
+    FlatDerivative(f64),
+    #[error("Not a real number at {0}")]
+    NotReal(f64),
+    #[error("Every value between {0} and {1} is a solution")]
+    Everywhere(f64, f64),
+    #[error("Failed to evaluate - {0}")]
+    Eval(#[from] Spanned<EvalError>),
+}
+
+/// An equation `lhs = rhs` in one unknown, solved for where `lhs - rhs` is zero.
+/// Without `=` the expression itself is solved for zero.
+#[derive(Debug)]
+pub struct Equation<'a> {
+    env: &'a Environment,
+    program: Program,
+    slots: Vec<Option<Value>>,
+    /// Slot of the unknown, `None` if the equation doesn't use it.
+    unknown: Option<usize>,
+    vm: Vm,
+}
+
+impl<'a> Equation<'a> {
+    /// Parse `source` with the registry of `env`, whose variables and functions it may use.
+    ///
+    /// # Errors
+    /// When either side can't be parsed, or there is more than one `=`.
+    pub fn new(source: &str, unknown: &str, env: &'a Environment) -> Result<Self, OxcError> {
+        let eq = Expression::parse_with(source, env.registry())?;
+        let tokens: Vec<_> = eq.into_spanned().collect();
+        let mut sides = tokens.split(|(tok, _)| *tok == MathToken::Assign);
+        let lhs = sides.next().unwrap_or_default();
+        let difference = match (sides.next(), sides.next()) {
+            (None, _) => lhs.to_vec(),
+            // `(lhs) - (rhs)`, the extra tokens point at the `=`
+            (Some(rhs), None) => {
+                let at = tokens[lhs.len()].1;
+                let mut difference = vec![(MathToken::Parens(true), at)];
+                difference.extend_from_slice(lhs);
+                difference.extend([
+                    (MathToken::Parens(false), at),
+                    (MathToken::Operator(OperatorType::Sub), at),
+                    (MathToken::Parens(true), at),
+                ]);
+                difference.extend_from_slice(rhs);
+                difference.push((MathToken::Parens(false), at));
+                difference
+            }
+            (Some(rhs), Some(_)) => {
+                let at: Span = tokens[lhs.len() + rhs.len() + 1].1;
+                return Err(Spanned::new(
+                    PostExpressionError::InvalidExpression(
+                        "An equation has only one `=`".to_string(),
+                    ),
+                    at,
+                )
+                .into());
+            }
+        };
+        let (tokens, spans) = difference.into_iter().unzip();
+        let postfix = PostExpression::try_from(Expression::new(tokens).with_spans(spans))?;
+        let program = Program::compile(&postfix, env);
+        Ok(Self {
+            env,
+            slots: program.load(env),
+            unknown: program.slot(unknown),
+            program,
+            vm: Vm::default(),
+        })
+    }
+
+    /// `lhs - rhs` at `x`, `NaN` if it isn't real.
+    ///
+    /// # Errors
+    /// When the equation can't be evaluated at `x`.
+    pub fn eval(&mut self, x: f64) -> Result<f64, SolveError> {
+        if let Some(slot) = self.unknown {
+            self.slots[slot] = Some(Value::Float(x));
+        }
+        let value = self.vm.run(&self.program, self.env, &self.slots)?;
+        Ok(value.as_float())
+    }
+
+    /// Like `eval`, but `NaN` where the equation has no value, see `no_value`.
+    fn sample(&mut self, x: f64) -> Result<f64, SolveError> {
+        match self.eval(x) {
+            Err(error) if no_value(&error) => Ok(f64::NAN),
+            result => result,
+        }
+    }
+
+    /// Like `eval`, but a value that isn't real is an error.
+    fn real(&mut self, x: f64) -> Result<f64, SolveError> {
+        let fx = self.eval(x)?;
+        if fx.is_nan() {
+            return Err(SolveError::NotReal(x));
+        }
+        Ok(fx)
+    }
+
+    /// Find one root with `method`, starting from `start`.
+    ///
+    /// # Errors
+    /// When the method doesn't converge, e.g. the sign doesn't change across an interval or the
+    /// derivative is flat, or the equation can't be evaluated.
+    pub fn solve(&mut self, method: Method, start: Start) -> Result<Root, SolveError> {
+        let root = match (method, start) {
+            (Method::Newton, Start::Guess(x)) => self.newton(x),
+            (Method::Newton, Start::Interval(a, b)) => self.newton(a + (b - a) / 2.0),
+            (method, Start::Guess(x)) => {
+                let (a, b) = self.bracket(x)?;
+                self.solve(method, Start::Interval(a, b))
+            }
+            (Method::Bisection, Start::Interval(a, b)) => self.bisection(a, b),
+            (Method::Brent, Start::Interval(a, b)) => self.brent(a, b),
+        }?;
+        Ok(self.snap(root))
+    }
+
+    /// Round `root` to the digits the tolerance leaves, so `5.6e-16` becomes `0`, unless
+    /// the equation is further from zero there.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn snap(&mut self, root: Root) -> Root {
+        let digits = -(TOLERANCE * (1.0 + root.x.abs())).log10().floor();
+        let rounded = format!("{:.*}", digits.max(0.0) as usize, root.x);
+        let Ok(x) = rounded.parse::<f64>() else {
+            return root;
+        };
+        match self.eval(x) {
+            // adding zero turns `-0` into `0`
+            Ok(fx) if fx.abs() <= root.residual => Root {
+                x: x + 0.0,
+                residual: fx.abs(),
+                ..root
+            },
+            _ => root,
+        }
+    }
+
+    /// Every root between `a` and `b` where the sign changes, in order. Roots that only
+    /// touch zero, like that of `x^2 = 0`, need a guess and Newton's method instead.
+    /// Points where the equation has no value are skipped, other errors are returned.
+    ///
+    /// # Errors
+    /// When the equation can't be evaluated anywhere, e.g. it uses an undefined name, or it
+    /// holds everywhere, like `x * 0 = 0`.
+    pub fn roots(&mut self, method: Method, a: f64, b: f64) -> Result<Vec<Root>, SolveError> {
+        let (a, b) = (a.min(b), a.max(b));
+        // from the ends rather than by steps, so points like `0` are hit exactly
+        #[allow(clippy::cast_precision_loss)]
+        let samples = (0..=SCAN_STEPS)
+            .map(|idx| {
+                let x = if idx == SCAN_STEPS {
+                    b
+                } else {
+                    a + (b - a) * idx as f64 / SCAN_STEPS as f64
+                };
+                Ok((x, self.sample(x)?))
+            })
+            .collect::<Result<Vec<_>, SolveError>>()?;
+        let zeros = samples.iter().filter(|(_, fx)| *fx == 0.0).count();
+        if zeros > 1 && samples.iter().all(|(_, fx)| *fx == 0.0 || fx.is_nan()) {
+            return Err(SolveError::Everywhere(a, b));
+        }
+        let mut roots: Vec<Root> = Vec::new();
+        for pair in samples.windows(2) {
+            let (left, right) = (pair[0], pair[1]);
+            let found = if left.1 == 0.0 {
+                Some(Root {
+                    x: left.0,
+                    residual: 0.0,
+                    iterations: 0,
+                })
+            } else if left.1 * right.1 < 0.0 {
+                match self.solve(method, Start::Interval(left.0, right.0)) {
+                    Ok(root) => Some(root),
+                    Err(error @ SolveError::Eval(_)) if !no_value(&error) => return Err(error),
+                    Err(_) => None,
+                }
+            } else {
+                None
+            };
+            if let Some(root) = found.filter(|root| root.residual <= MAX_RESIDUAL) {
+                insert(&mut roots, root);
+            }
+        }
+        if samples.last().is_some_and(|(_, fx)| *fx == 0.0) {
+            let root = Root {
+                x: b,
+                residual: 0.0,
+                iterations: 0,
+            };
+            insert(&mut roots, root);
+        }
+        roots.sort_by(|l, r| l.x.total_cmp(&r.x));
+        Ok(roots)
+    }
+
+    /// Widen an interval around `x` until the sign changes across it.
+    fn bracket(&mut self, x: f64) -> Result<(f64, f64), SolveError> {
+        let mut width = 0.1 * x.abs().max(1.0);
+        for _ in 0..MAX_ITERATIONS / 2 {
+            let (a, b) = (x - width, x + width);
+            if self.sample(a)? * self.sample(b)? <= 0.0 {
+                return Ok((a, b));
+            }
+            width *= 2.0;
+        }
+        Err(SolveError::NoBracket(x))
+    }
+
+    fn bisection(&mut self, mut a: f64, mut b: f64) -> Result<Root, SolveError> {
+        let (mut fa, fb) = (self.real(a)?, self.real(b)?);
+        for (x, fx) in [(a, fa), (b, fb)] {
+            if fx == 0.0 {
+                return Ok(Root {
+                    x,
+                    residual: 0.0,
+                    iterations: 0,
+                });
+            }
+        }
+        if fa.signum() == fb.signum() {
+            return Err(SolveError::NoSignChange(a, b));
+        }
+        for iterations in 1..=MAX_ITERATIONS {
+            let half = (b - a) / 2.0;
+            let mid = a + half;
+            let fm = self.real(mid)?;
+            if fm == 0.0 || half.abs() <= TOLERANCE * (1.0 + mid.abs()) {
+                return Ok(Root {
+                    x: mid,
+                    residual: fm.abs(),
+                    iterations,
+                });
+            }
+            if fm.signum() == fa.signum() {
+                (a, fa) = (mid, fm);
+            } else {
+                b = mid;
+            }
+        }
+        Err(SolveError::NotConverged(MAX_ITERATIONS))
+    }
+
+    fn newton(&mut self, mut x: f64) -> Result<Root, SolveError> {
+        for iterations in 1..=MAX_ITERATIONS {
+            let fx = self.real(x)?;
+            if fx == 0.0 {
+                return Ok(Root {
+                    x,
+                    residual: 0.0,
+                    iterations: iterations - 1,
+                });
+            }
+            // a central difference, with the step that balances rounding and truncation error
+            let h = f64::EPSILON.cbrt() * x.abs().max(1.0);
+            let slope = (self.real(x + h)? - self.real(x - h)?) / (2.0 * h);
+            if slope == 0.0 || !slope.is_finite() {
+                return Err(SolveError::FlatDerivative(x));
+            }
+            let step = fx / slope;
+            x -= step;
+            if step.abs() <= TOLERANCE * (1.0 + x.abs()) {
+                return Ok(Root {
+                    x,
+                    residual: self.real(x)?.abs(),
+                    iterations,
+                });
+            }
+        }
+        Err(SolveError::NotConverged(MAX_ITERATIONS))
+    }
+
+    /// Brent's method as in Numerical Recipes' `zbrent`: `b` is the best estimate, `a` the
+    /// previous one and `c` the other side of the sign change.
+    #[allow(clippy::many_single_char_names, clippy::float_cmp)]
+    fn brent(&mut self, a: f64, b: f64) -> Result<Root, SolveError> {
+        let (mut a, mut b) = (a, b);
+        let (mut fa, mut fb) = (self.real(a)?, self.real(b)?);
+        if fa * fb > 0.0 {
+            return Err(SolveError::NoSignChange(a, b));
+        }
+        let (mut c, mut fc) = (b, fb);
+        let (mut d, mut e) = (b - a, b - a);
+        for iterations in 1..=MAX_ITERATIONS {
+            if fb * fc > 0.0 {
+                (c, fc) = (a, fa);
+                d = b - a;
+                e = d;
+            }
+            if fc.abs() < fb.abs() {
+                (a, b, c) = (b, c, b);
+                (fa, fb, fc) = (fb, fc, fb);
+            }
+            let tol = (2.0 * f64::EPSILON).mul_add(b.abs(), TOLERANCE / 2.0);
+            let mid = (c - b) / 2.0;
+            if mid.abs() <= tol || fb == 0.0 {
+                return Ok(Root {
+                    x: b,
+                    residual: fb.abs(),
+                    iterations,
+                });
+            }
+            if e.abs() >= tol && fa.abs() > fb.abs() {
+                // interpolate, with a secant step when only two points are known
+                let s = fb / fa;
+                let (mut p, mut q) = if a == c {
+                    (2.0 * mid * s, 1.0 - s)
+                } else {
+                    let (q, r) = (fa / fc, fb / fc);
+                    (
+                        s * (2.0 * mid * q).mul_add(q - r, -(b - a) * (r - 1.0)),
+                        (q - 1.0) * (r - 1.0) * (s - 1.0),
+                    )
+                };
+                if p > 0.0 {
+                    q = -q;
+                }
+                p = p.abs();
+                let limit = (3.0 * mid).mul_add(q, -(tol * q).abs()).min((e * q).abs());
+                if 2.0 * p < limit {
+                    e = d;
+                    d = p / q;
+                } else {
+                    d = mid;
+                    e = d;
+                }
+            } else {
+                d = mid;
+                e = d;
+            }
+            (a, fa) = (b, fb);
+            b += if d.abs() > tol { d } else { tol.copysign(mid) };
+            fb = self.real(b)?;
+        }
+        Err(SolveError::NotConverged(MAX_ITERATIONS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(source: &str, method: Method, start: Start) -> Root {
+        let env = Environment::default();
+        let mut equation = Equation::new(source, "x", &env).unwrap();
+        equation.solve(method, start).unwrap()
+    }
+
+    #[test]
+    fn test_methods() {
+        let sqrt2 = 2f64.sqrt();
+        for method in [Method::Bisection, Method::Newton, Method::Brent] {
+            let root = solve("x^2 - 2 = 0", method, Start::Interval(0.0, 3.0));
+            assert!(
+                (root.x - sqrt2).abs() < 1e-10,
+                "{method:?} found {}",
+                root.x
+            );
+            assert!(root.residual < 1e-10);
+            let root = solve("x^2 = 2", method, Start::Guess(1.0));
+            assert!(
+                (root.x - sqrt2).abs() < 1e-10,
+                "{method:?} found {}",
+                root.x
+            );
+        }
+    }
+    #[test]
+    fn test_iterations() {
+        let bisection = solve("x^3 - x - 2", Method::Bisection, Start::Interval(1.0, 2.0));
+        let brent = solve("x^3 - x - 2", Method::Brent, Start::Interval(1.0, 2.0));
+        assert!((bisection.x - brent.x).abs() < 1e-10);
+        assert!(brent.iterations < bisection.iterations);
+    }
+    #[test]
+    fn test_both_sides() {
+        let root = solve("cos(x) = x", Method::Newton, Start::Guess(1.0));
+        assert!((root.x - 0.739_085_133_215_160_6).abs() < 1e-10);
+    }
+    #[test]
+    fn test_roots() {
+        let env = Environment::default();
+        let mut equation = Equation::new("x^3 = x", "x", &env).unwrap();
+        let roots: Vec<_> = equation
+            .roots(Method::Brent, -10.0, 10.0)
+            .unwrap()
+            .iter()
+            .map(|root| root.x)
+            .collect();
+        assert_eq!(roots.len(), 3);
+        assert!(
+            (roots[0] + 1.0).abs() < 1e-10
+                && roots[1].abs() < 1e-10
+                && (roots[2] - 1.0).abs() < 1e-10
+        );
+        // the sign changes at the poles of `tan` aren't roots
+        let mut equation = Equation::new("tan(x)", "x", &env).unwrap();
+        assert_eq!(equation.roots(Method::Brent, 1.0, 5.0).unwrap().len(), 1);
+        let roots = equation.roots(Method::Brent, -10.0, 10.0).unwrap();
+        assert_eq!(roots.len(), 7);
+        assert_eq!(roots[3].x.to_string(), "0");
+        // nor are the points where `ln` has no real value
+        let mut equation = Equation::new("ln(x) = 1", "x", &env).unwrap();
+        assert_eq!(equation.roots(Method::Brent, -10.0, 10.0).unwrap().len(), 1);
+    }
+    #[test]
+    fn test_errors() {
+        let env = Environment::default();
+        let mut equation = Equation::new("x * 0 = 0", "x", &env).unwrap();
+        assert_eq!(
+            equation.roots(Method::Brent, -10.0, 10.0),
+            Err(SolveError::Everywhere(-10.0, 10.0))
+        );
+        let mut equation = Equation::new("x^2 + 1 = 0", "x", &env).unwrap();
+        assert_eq!(
+            equation.solve(Method::Bisection, Start::Interval(-1.0, 2.0)),
+            Err(SolveError::NoSignChange(-1.0, 2.0))
+        );
+        assert_eq!(
+            equation.solve(Method::Newton, Start::Guess(0.0)),
+            Err(SolveError::FlatDerivative(0.0))
+        );
+        // names that don't exist are reported rather than there being no root
+        let undefined = |source| {
+            let mut equation = Equation::new(source, "x", &env).unwrap();
+            (
+                equation.roots(Method::Brent, -10.0, 10.0),
+                equation.solve(Method::Brent, Start::Guess(1.0)),
+            )
+        };
+        let (roots, guess) = undefined("x + y = 0");
+        assert!(matches!(
+            roots,
+            Err(SolveError::Eval(Spanned {
+                error: EvalError::UndefinedVariable(_),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            guess,
+            Err(SolveError::Eval(Spanned {
+                error: EvalError::UndefinedVariable(_),
+                ..
+            }))
+        ));
+        let (roots, _) = undefined("f(x) = 2");
+        assert!(matches!(
+            roots,
+            Err(SolveError::Eval(Spanned {
+                error: EvalError::UndefinedFunction(_),
+                ..
+            }))
+        ));
+        let err = Equation::new("x = 1 = 2", "x", &env).unwrap_err();
+        assert_eq!(err.span(), Span::new(6, 7));
+    }
+}
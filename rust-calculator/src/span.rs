@@ -0,0 +1,77 @@
+use thiserror::Error;
+
+/// Byte range of a token in the input, `start` inclusive and `end` exclusive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    #[must_use]
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    #[must_use]
+    pub fn to(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Point at `source` with a `^~~~` underline below this span, like a compiler diagnostic.
+    /// A span past the end of `source` points just after the last character.
+    #[must_use]
+    pub fn underline(self, source: &str) -> String {
+        let start = self.start.min(source.len());
+        let end = self.end.clamp(start, source.len());
+        let column = source[..start].chars().count();
+        let width = source[start..end].chars().count().max(1);
+        format!(
+            "  {source}\n  {}^{}",
+            " ".repeat(column),
+            "~".repeat(width - 1)
+        )
+    }
+}
+
+/// An error together with the part of the input it is about.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{error}")]
+pub struct Spanned<E> {
+    pub error: E,
+    pub span: Span,
+}
+
+impl<E> Spanned<E> {
+    pub const fn new(error: E, span: Span) -> Self {
+        Self { error, span }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underline() {
+        assert_eq!(Span::new(4, 5).underline("3 + * 4"), "  3 + * 4\n      ^");
+        assert_eq!(
+            Span::new(4, 8).underline("1 + sqrt(x)"),
+            "  1 + sqrt(x)\n      ^~~~"
+        );
+    }
+    #[test]
+    fn test_underline_past_end() {
+        assert_eq!(Span::new(3, 4).underline("1 +"), "  1 +\n     ^");
+        assert_eq!(Span::default().underline(""), "  \n  ^");
+    }
+    #[test]
+    fn test_underline_counts_chars() {
+        assert_eq!(Span::new(5, 8).underline("é + ∞"), "  é + ∞\n      ^");
+    }
+    #[test]
+    fn test_join() {
+        assert_eq!(Span::new(4, 8).to(Span::new(9, 10)), Span::new(4, 10));
+    }
+}
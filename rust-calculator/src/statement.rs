@@ -0,0 +1,418 @@
+use crate::builtins::{self, Arity};
+use crate::environment::{Environment, UserFunction};
+use crate::postfix::{EvalError, PostExpression, PostExpressionError};
+use crate::span::{Span, Spanned};
+use crate::tokens::{Expression, MathToken};
+use crate::value::Value;
+
+/// One line of input: an expression to evaluate, an assignment like `rate = 0.07`
+/// or a function definition like `f(x) = x^2 + 1`.
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    Expression(PostExpression),
+    Assignment {
+        name: String,
+        value: PostExpression,
+    },
+    Definition {
+        name: String,
+        function: UserFunction,
+    },
+}
+
+const fn invalid(msg: String, span: Span) -> Spanned<PostExpressionError> {
+    Spanned::new(PostExpressionError::InvalidExpression(msg), span)
+}
+
+/// Turns the tokens of an expression into postfix, from infix or reverse Polish notation.
+type Compile = fn(Expression) -> Result<PostExpression, Spanned<PostExpressionError>>;
+
+/// How expressions are written in the input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// `(3 + 4) * 2`
+    #[default]
+    Infix,
+    /// Reverse Polish notation, `3 4 + 2 *`
+    Rpn,
+}
+
+/// Compile the right hand side of an assignment or definition, `assign` is the span of the `=`.
+fn compile_body(
+    name: &str,
+    assign: Span,
+    body: Vec<(MathToken, Span)>,
+    compile: Compile,
+) -> Result<PostExpression, Spanned<PostExpressionError>> {
+    if body.is_empty() {
+        return Err(invalid(format!("Nothing to assign to `{name}`"), assign));
+    }
+    let (tokens, spans) = body.into_iter().unzip();
+    compile(Expression::new(tokens).with_spans(spans))
+}
+
+/// A function definition split into its parts, before the body is compiled.
+struct Header {
+    name: String,
+    params: Vec<String>,
+    assign: Span,
+    body: Vec<(MathToken, Span)>,
+}
+
+/// Split `f(a, b) = body` into the function name, its parameters and the body tokens.
+fn parse_definition(eq: Expression) -> Result<Header, Spanned<PostExpressionError>> {
+    let mut tokens = eq.into_spanned();
+    let Some((MathToken::Function(name), name_span)) = tokens.next() else {
+        unreachable!("Definitions start with a function name")
+    };
+    let bad_params = |span| {
+        invalid(
+            format!("Parameters of `{name}` must be variable names"),
+            span,
+        )
+    };
+    // every path below stops at the `=` at the latest
+    let mut next = || tokens.next().expect("Definitions contain `=`");
+    if next().0 != MathToken::Parens(true) {
+        return Err(bad_params(name_span));
+    }
+    let mut params: Vec<String> = Vec::new();
+    loop {
+        match next() {
+            (MathToken::Parens(false), _) if params.is_empty() => break,
+            (MathToken::Identifier(param), span) => {
+                if params.contains(&param) {
+                    return Err(invalid(
+                        format!("Parameter `{param}` of `{name}` is repeated"),
+                        span,
+                    ));
+                }
+                params.push(param);
+            }
+            (_, span) => return Err(bad_params(span)),
+        }
+        match next() {
+            (MathToken::Comma, _) => {}
+            (MathToken::Parens(false), _) => break,
+            (_, span) => return Err(bad_params(span)),
+        }
+    }
+    let (tok, assign) = next();
+    if tok != MathToken::Assign {
+        return Err(bad_params(assign));
+    }
+    Ok(Header {
+        name,
+        params,
+        assign,
+        body: tokens.collect(),
+    })
+}
+
+impl TryFrom<Expression> for Statement {
+    type Error = Spanned<PostExpressionError>;
+
+    fn try_from(eq: Expression) -> Result<Self, Self::Error> {
+        Self::compile(eq, Syntax::Infix)
+    }
+}
+
+impl Statement {
+    /// Split off the left hand side of an assignment or definition,
+    /// and compile the expression in the given syntax.
+    ///
+    /// # Errors
+    /// When a definition redefines a builtin function or its parameters aren't distinct names,
+    /// or the expression can't be converted to postfix.
+    pub fn compile(eq: Expression, syntax: Syntax) -> Result<Self, Spanned<PostExpressionError>> {
+        let compile: Compile = match syntax {
+            Syntax::Infix => PostExpression::try_from,
+            Syntax::Rpn => PostExpression::from_rpn,
+        };
+        match eq.tokens.as_slice() {
+            [MathToken::Identifier(_), MathToken::Assign, ..] => {
+                let assign = eq.spans.get(1).copied().unwrap_or_default();
+                let mut tokens = eq.into_spanned();
+                let Some((MathToken::Identifier(name), _)) = tokens.next() else {
+                    unreachable!("Matched an identifier above")
+                };
+                let value = compile_body(&name, assign, tokens.skip(1).collect(), compile)?;
+                Ok(Self::Assignment { name, value })
+            }
+            [MathToken::Function(name), ..] if eq.tokens.contains(&MathToken::Assign) => {
+                if builtins::function(name).is_some() {
+                    return Err(invalid(
+                        format!("`{name}` is a builtin function"),
+                        eq.spans.first().copied().unwrap_or_default(),
+                    ));
+                }
+                let Header {
+                    name,
+                    params,
+                    assign,
+                    body,
+                } = parse_definition(eq)?;
+                let body = compile_body(&name, assign, body, compile)?;
+                Ok(Self::Definition {
+                    name,
+                    function: UserFunction { params, body },
+                })
+            }
+            _ => compile(eq).map(Self::Expression),
+        }
+    }
+}
+
+/// Make sure every name used by a function body exists, so mistakes surface at definition time.
+/// The function may call itself, other names are resolved against `env`.
+fn check_definition(
+    env: &Environment,
+    name: &str,
+    function: &UserFunction,
+) -> Result<(), Spanned<EvalError>> {
+    if env.registry().function(name).is_some() {
+        return Err(Spanned::new(
+            EvalError::Registered(name.to_string()),
+            Span::default(),
+        ));
+    }
+    check_names(env, name, function, &function.body, &function.params)
+}
+
+/// Check the names used by `body`, part of the definition of `function`, where the variables
+/// in `bound` are its parameters or those of the series it is in.
+fn check_names(
+    env: &Environment,
+    name: &str,
+    function: &UserFunction,
+    body: &PostExpression,
+    bound: &[String],
+) -> Result<(), Spanned<EvalError>> {
+    for (idx, tok) in body.tokens().iter().enumerate() {
+        let at = |error| Spanned::new(error, body.span(idx));
+        match tok {
+            MathToken::Series(series) => {
+                let bound: Vec<_> = std::iter::once(series.var.clone())
+                    .chain(bound.iter().cloned())
+                    .collect();
+                check_names(env, name, function, &series.body, &bound)?;
+            }
+            MathToken::Identifier(var) => {
+                let known = bound.contains(var)
+                    || env.get(var).is_some()
+                    || builtins::constant(var).is_some();
+                if !known {
+                    return Err(at(EvalError::UndefinedVariable(var.clone())));
+                }
+            }
+            MathToken::Call(callee, argc) => {
+                let expected = if callee == name {
+                    Arity::Exact(function.params.len())
+                } else if let Some(registered) = env.registry().function(callee) {
+                    registered.arity()
+                } else {
+                    let callee = env
+                        .function(callee)
+                        .ok_or_else(|| at(EvalError::UndefinedFunction(callee.clone())))?;
+                    Arity::Exact(callee.params.len())
+                };
+                if !expected.accepts(*argc) {
+                    return Err(at(EvalError::WrongArity {
+                        name: callee.clone(),
+                        expected,
+                        got: *argc,
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Statement {
+    /// Evaluate the statement. Assignments store their result in `env` and also return it,
+    /// definitions store the function and return nothing.
+    ///
+    /// # Errors
+    /// When the expression can't be evaluated, or a definition uses a variable or function that
+    /// isn't defined or calls one with the wrong number of arguments.
+    pub fn execute(&self, env: &mut Environment) -> Result<Option<Value>, Spanned<EvalError>> {
+        match self {
+            Self::Expression(expr) => expr.eval(env).map(Some),
+            Self::Assignment { name, value } => {
+                let result = value.eval(env)?;
+                env.set(name.clone(), result.clone());
+                Ok(Some(result))
+            }
+            Self::Definition { name, function } => {
+                check_definition(env, name, function)?;
+                env.define(name.clone(), function.clone());
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(input: &str) -> Result<Statement, Spanned<PostExpressionError>> {
+        Statement::try_from(input.parse::<Expression>().unwrap())
+    }
+    fn run(env: &mut Environment, input: &str) -> Result<Option<Value>, EvalError> {
+        statement(input).unwrap().execute(env).map_err(|e| e.error)
+    }
+
+    #[test]
+    fn test_rpn_statements() {
+        let rpn =
+            |input: &str| Statement::compile(input.parse::<Expression>().unwrap(), Syntax::Rpn);
+        assert_eq!(rpn("x = 1 2 +").unwrap(), statement("x = 1 + 2").unwrap());
+        assert_eq!(
+            rpn("f(a, b) = a b - 2 ^").unwrap(),
+            statement("f(a, b) = (a - b) ^ 2").unwrap()
+        );
+        assert!(rpn("x = 1 +").is_err());
+        assert!(rpn("x = ").is_err());
+    }
+    #[test]
+    fn test_assignment() {
+        let mut env = Environment::default();
+        let assign = statement("rate = 0.5").unwrap();
+        assert!(matches!(&assign, Statement::Assignment { name, .. } if name == "rate"));
+        assert_eq!(assign.execute(&mut env).unwrap(), Some(Value::Float(0.5)));
+        assert_eq!(env.get("rate"), Some(Value::Float(0.5)));
+        assert_eq!(
+            run(&mut env, "4 * (1 + rate) ^ 2"),
+            Ok(Some(Value::Float(9.0)))
+        );
+    }
+    #[test]
+    fn test_reassign_uses_old_value() {
+        let mut env = Environment::default();
+        env.set("x", Value::Int(2));
+        run(&mut env, "x = x * 3").unwrap();
+        assert_eq!(env.get("x"), Some(Value::Int(6)));
+    }
+    #[test]
+    fn test_invalid_assignment() {
+        assert!(statement("x =").is_err());
+        assert!(statement("1 = 2").is_err());
+        assert!(statement("x = y = 2").is_err());
+    }
+    #[test]
+    fn test_undefined_variable() {
+        let mut env = Environment::default();
+        assert_eq!(
+            run(&mut env, "y + 1"),
+            Err(EvalError::UndefinedVariable("y".into()))
+        );
+    }
+    #[test]
+    fn test_define_and_call() {
+        let mut env = Environment::default();
+        assert_eq!(run(&mut env, "f(x) = x^2 + 2*x + 1"), Ok(None));
+        assert_eq!(run(&mut env, "f(3)"), Ok(Some(Value::Int(16))));
+        assert_eq!(run(&mut env, "hyp(a, b) = sqrt(a^2 + b^2)"), Ok(None));
+        assert_eq!(run(&mut env, "hyp(3, 4)"), Ok(Some(Value::Float(5.0))));
+        assert_eq!(
+            run(&mut env, "f(hyp(3, 4)) - 1"),
+            Ok(Some(Value::Float(35.0)))
+        );
+    }
+    #[test]
+    fn test_definition_uses_globals() {
+        let mut env = Environment::default();
+        run(&mut env, "k = 2").unwrap();
+        run(&mut env, "scale(x) = k * x").unwrap();
+        assert_eq!(run(&mut env, "scale(5)"), Ok(Some(Value::Int(10))));
+        run(&mut env, "k = 3").unwrap();
+        assert_eq!(run(&mut env, "scale(5)"), Ok(Some(Value::Int(15))));
+        // parameters shadow globals
+        assert_eq!(run(&mut env, "g(k) = k"), Ok(None));
+        assert_eq!(run(&mut env, "g(7)"), Ok(Some(Value::Int(7))));
+    }
+    #[test]
+    fn test_definition_time_errors() {
+        let mut env = Environment::default();
+        assert_eq!(
+            run(&mut env, "f(x) = x + y"),
+            Err(EvalError::UndefinedVariable("y".into()))
+        );
+        // `h` is only hours after a number
+        assert_eq!(
+            run(&mut env, "f(x) = x + h"),
+            Err(EvalError::UndefinedVariable("h".into()))
+        );
+        assert_eq!(
+            run(&mut env, "f(x) = g(x)"),
+            Err(EvalError::UndefinedFunction("g".into()))
+        );
+        assert!(matches!(
+            run(&mut env, "f(x) = f(x, x)"),
+            Err(EvalError::WrongArity { .. })
+        ));
+        assert!(env.function("f").is_none());
+    }
+    #[test]
+    fn test_invalid_definitions() {
+        assert!(statement("sqrt(x) = x").is_err());
+        assert!(statement("f(1) = 2").is_err());
+        assert!(statement("f(x, x) = x").is_err());
+        assert!(statement("f(x) =").is_err());
+        assert!(statement("f(x) + 1 = 2").is_err());
+    }
+    #[test]
+    fn test_error_spans() {
+        let span = |input: &str| statement(input).unwrap_err().span;
+        assert_eq!(span("x = "), Span::new(2, 3));
+        assert_eq!(span("f(x, 1) = x"), Span::new(5, 6));
+        assert_eq!(span("f(x, x) = x"), Span::new(5, 6));
+        assert_eq!(span("sqrt(x) = x"), Span::new(0, 4));
+        assert_eq!(span("f(x) = (x"), Span::new(7, 8));
+        let mut env = Environment::default();
+        let err = statement("f(x) = x + y").unwrap().execute(&mut env);
+        assert_eq!(err.unwrap_err().span, Span::new(11, 12));
+    }
+    #[test]
+    fn test_series_in_definition() {
+        let mut env = Environment::default();
+        assert_eq!(run(&mut env, "tri(n) = sum(i, 1, n, i)"), Ok(None));
+        assert_eq!(run(&mut env, "tri(4)"), Ok(Some(Value::Int(10))));
+        assert_eq!(
+            run(&mut env, "f(n) = sum(k, 1, n, k * q)"),
+            Err(EvalError::UndefinedVariable("q".into()))
+        );
+        // the variable of a series is only bound inside it
+        assert_eq!(
+            run(&mut env, "f(n) = sum(k, 1, n, k) + k"),
+            Err(EvalError::UndefinedVariable("k".into()))
+        );
+    }
+    #[test]
+    fn test_call_arity() {
+        let mut env = Environment::default();
+        run(&mut env, "f(a, b) = a - b").unwrap();
+        assert!(matches!(
+            run(&mut env, "f(1)"),
+            Err(EvalError::WrongArity { got: 1, .. })
+        ));
+    }
+    #[test]
+    fn test_recursive_conditional() {
+        let mut env = Environment::default();
+        run(&mut env, "fact(n) = n <= 1 ? 1 : n * fact(n - 1)").unwrap();
+        assert_eq!(run(&mut env, "fact(10)"), Ok(Some(Value::Int(3_628_800))));
+    }
+    #[test]
+    fn test_recursion_limit() {
+        let mut env = Environment::default();
+        run(&mut env, "f(x) = f(x + 1)").unwrap();
+        assert_eq!(
+            run(&mut env, "f(0)"),
+            Err(EvalError::RecursionLimit("f".into()))
+        );
+    }
+}
@@ -0,0 +1,678 @@
+//! Symbolic differentiation and simplification of expression trees.
+use crate::ast::Ast;
+use crate::builtins;
+use crate::postfix::PostExpression;
+use crate::tokens::{Expression, OperatorType};
+use crate::value::Value;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SymbolicError {
+    #[error("Can't differentiate `{0}`")]
+    NotDifferentiable(String),
+}
+
+/// Derivatives of builtin functions of one argument in terms of that argument `u`,
+/// the chain rule multiplies them by the derivative of `u`.
+const DERIVATIVES: [(&str, &str); 16] = [
+    ("sqrt", "1 / (2 * sqrt(u))"),
+    ("cbrt", "1 / (3 * cbrt(u) ^ 2)"),
+    ("exp", "exp(u)"),
+    ("ln", "1 / u"),
+    ("log", "1 / (u * ln(10))"),
+    ("log2", "1 / (u * ln(2))"),
+    ("sin", "cos(u)"),
+    ("cos", "-sin(u)"),
+    ("tan", "1 / cos(u) ^ 2"),
+    ("asin", "1 / sqrt(1 - u ^ 2)"),
+    ("acos", "-1 / sqrt(1 - u ^ 2)"),
+    ("atan", "1 / (1 + u ^ 2)"),
+    ("sinh", "cosh(u)"),
+    ("cosh", "sinh(u)"),
+    ("tanh", "1 / cosh(u) ^ 2"),
+    ("abs", "u / abs(u)"),
+];
+
+const fn number(n: isize) -> Ast {
+    Ast::Number(Value::Int(n))
+}
+
+fn unary(op: OperatorType, operand: Ast) -> Ast {
+    Ast::Unary {
+        op,
+        operand: Box::new(operand),
+    }
+}
+
+fn binary(op: OperatorType, lhs: Ast, rhs: Ast) -> Ast {
+    Ast::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+fn call(name: &str, args: Vec<Ast>) -> Ast {
+    Ast::Call {
+        name: name.to_string(),
+        args,
+    }
+}
+
+/// Whether `ast` is the real number `n`, of any type.
+#[allow(clippy::float_cmp)]
+fn is(ast: &Ast, n: f64) -> bool {
+    matches!(ast, Ast::Number(v) if !v.is_complex() && v.as_float() == n)
+}
+
+/// Keep a folded constant only if it is as exact as its operands, so `sqrt(2)` stays as written
+/// while `sqrt(4)` becomes `2`.
+#[allow(clippy::cast_possible_truncation)]
+fn exact(result: Value, operands: &[&Value]) -> Option<Value> {
+    let inexact = operands
+        .iter()
+        .any(|v| matches!(v, Value::Float(_) | Value::Complex(_)));
+    match result {
+        Value::Int(_) | Value::Rational(_) => Some(result),
+        _ if inexact => Some(result),
+        // 2^53, past which floats can't tell whole numbers apart
+        Value::Float(f) if f.fract() == 0.0 && f.abs() < 9_007_199_254_740_992.0 => {
+            Some(Value::Int(f as isize))
+        }
+        Value::Float(_) | Value::Complex(_) | Value::Quantity(_) | Value::List(_) => None,
+    }
+}
+
+/// Fold an operator on whole numbers and fractions with rationals, so `1/2 - 1` is `-1/2`
+/// rather than a float. Whole results that fit are integers again.
+fn fold_exact(op: &OperatorType, lhs: &Value, rhs: &Value) -> Option<Value> {
+    let (lhs, rhs) = (lhs.to_rational()?, rhs.to_rational()?);
+    match op.apply(Value::Rational(lhs), Value::Rational(rhs)).ok()? {
+        Value::Rational(r) => Some(rational(r)),
+        _ => None,
+    }
+}
+
+/// A rational as a number node's value, an integer if it is whole and fits.
+fn rational(r: BigRational) -> Value {
+    if r.is_integer() {
+        if let Some(i) = r.to_integer().to_isize() {
+            return Value::Int(i);
+        }
+    }
+    Value::Rational(r)
+}
+
+/// The coefficient of `c*y / d` when dividing by `d` reduces the fraction `c/d`, e.g. `9/9`
+/// or `6/4` but not `3/2`.
+fn cancel(c: &Value, d: &Value) -> Option<Value> {
+    let reduced = fold_exact(&OperatorType::Div, c, d)?;
+    let denominator = reduced.to_rational()?.denom().clone();
+    (denominator < d.to_bigint()?.abs()).then_some(reduced)
+}
+
+/// Split a fraction into its numerator and denominator, e.g. `x/2` or the number `1/2`.
+fn quotient(ast: &Ast) -> Option<(Ast, Ast)> {
+    match ast {
+        Ast::Binary {
+            op: OperatorType::Div,
+            lhs,
+            rhs,
+        } => Some((lhs.as_ref().clone(), rhs.as_ref().clone())),
+        Ast::Number(Value::Rational(r)) if !r.is_integer() => Some((
+            Ast::Number(rational(BigRational::from_integer(r.numer().clone()))),
+            Ast::Number(rational(BigRational::from_integer(r.denom().clone()))),
+        )),
+        _ => None,
+    }
+}
+
+/// Split a term into its numeric coefficient and the rest, e.g. `3*x` into `3` and `x`.
+fn coefficient(ast: &Ast) -> (Value, Ast) {
+    match ast {
+        Ast::Binary {
+            op: OperatorType::Mul,
+            lhs,
+            rhs,
+        } => match lhs.as_ref() {
+            Ast::Number(c) => (c.clone(), rhs.as_ref().clone()),
+            _ => (Value::Int(1), ast.clone()),
+        },
+        Ast::Unary {
+            op: OperatorType::Neg,
+            operand,
+        } => {
+            let (c, rest) = coefficient(operand);
+            let negated = OperatorType::Neg.apply_unary(c);
+            negated.map_or_else(|_| (Value::Int(1), ast.clone()), |c| (c, rest))
+        }
+        _ => (Value::Int(1), ast.clone()),
+    }
+}
+
+/// Split a factor into its base and exponent, e.g. `x^2` into `x` and `2`.
+fn power(ast: &Ast) -> (Ast, Ast) {
+    match ast {
+        Ast::Binary {
+            op: OperatorType::Pow,
+            lhs,
+            rhs,
+        } => (lhs.as_ref().clone(), rhs.as_ref().clone()),
+        _ => (ast.clone(), number(1)),
+    }
+}
+
+impl Ast {
+    /// Parse a derivative template, which is always valid.
+    fn template(source: &str) -> Self {
+        let eq: Expression = source.parse().expect("Derivative templates are valid");
+        let postfix = PostExpression::try_from(eq).expect("Derivative templates are valid");
+        Self::try_from(&postfix).expect("Derivative templates are valid")
+    }
+
+    /// Replace every use of the variable `name` with `value`.
+    fn substitute(&self, name: &str, value: &Self) -> Self {
+        match self {
+            Self::Variable(var) if var == name => value.clone(),
+            Self::Number(_) | Self::Variable(_) => self.clone(),
+            Self::Unary { op, operand } => unary(op.clone(), operand.substitute(name, value)),
+            Self::Binary { op, lhs, rhs } => binary(
+                op.clone(),
+                lhs.substitute(name, value),
+                rhs.substitute(name, value),
+            ),
+            Self::Call { name: func, args } => Self::Call {
+                name: func.clone(),
+                args: args.iter().map(|arg| arg.substitute(name, value)).collect(),
+            },
+            Self::List(items) => Self::List(
+                items
+                    .iter()
+                    .map(|item| item.substitute(name, value))
+                    .collect(),
+            ),
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => Self::Conditional {
+                cond: Box::new(cond.substitute(name, value)),
+                then: Box::new(then.substitute(name, value)),
+                otherwise: Box::new(otherwise.substitute(name, value)),
+            },
+        }
+    }
+
+    /// Whether the variable `name` appears anywhere in the expression.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Number(_) => false,
+            Self::Variable(var) => var == name,
+            Self::Unary { operand, .. } => operand.contains(name),
+            Self::Binary { lhs, rhs, .. } => lhs.contains(name) || rhs.contains(name),
+            Self::Call { args, .. } | Self::List(args) => args.iter().any(|arg| arg.contains(name)),
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => cond.contains(name) || then.contains(name) || otherwise.contains(name),
+        }
+    }
+
+    /// Derivative with respect to `var`, simplified. Other variables are constants,
+    /// and a conditional is differentiated branch by branch, a list element by element.
+    ///
+    /// # Errors
+    /// When a function or operator has no known derivative, e.g. `floor(x)`.
+    pub fn derivative(&self, var: &str) -> Result<Self, SymbolicError> {
+        Ok(self.differentiate(var)?.simplify())
+    }
+
+    fn differentiate(&self, var: &str) -> Result<Self, SymbolicError> {
+        let unsupported = || SymbolicError::NotDifferentiable(self.to_compact());
+        if !self.contains(var) {
+            return Ok(number(0));
+        }
+        Ok(match self {
+            Self::Number(_) => number(0),
+            Self::Variable(_) => number(1),
+            Self::Unary {
+                op: op @ (OperatorType::Neg | OperatorType::Pos),
+                operand,
+            } => unary(op.clone(), operand.differentiate(var)?),
+            Self::Binary { op, lhs: u, rhs: v } => {
+                let (u, v) = (u.as_ref().clone(), v.as_ref().clone());
+                let (du, dv) = (u.differentiate(var)?, v.differentiate(var)?);
+                match op {
+                    OperatorType::Add | OperatorType::Sub => binary(op.clone(), du, dv),
+                    OperatorType::Mul => binary(
+                        OperatorType::Add,
+                        binary(OperatorType::Mul, du, v),
+                        binary(OperatorType::Mul, u, dv),
+                    ),
+                    OperatorType::Div => binary(
+                        OperatorType::Div,
+                        binary(
+                            OperatorType::Sub,
+                            binary(OperatorType::Mul, du, v.clone()),
+                            binary(OperatorType::Mul, u, dv),
+                        ),
+                        binary(OperatorType::Pow, v, number(2)),
+                    ),
+                    // n * u^(n - 1) * u'
+                    OperatorType::Pow if !v.contains(var) => binary(
+                        OperatorType::Mul,
+                        binary(
+                            OperatorType::Mul,
+                            v.clone(),
+                            binary(
+                                OperatorType::Pow,
+                                u,
+                                binary(OperatorType::Sub, v, number(1)),
+                            ),
+                        ),
+                        du,
+                    ),
+                    // 0^v is 0 wherever it has a derivative
+                    OperatorType::Pow if is(&u, 0.0) => number(0),
+                    // v' * u^v * ln(u)
+                    OperatorType::Pow if !u.contains(var) => binary(
+                        OperatorType::Mul,
+                        binary(OperatorType::Mul, dv, self.clone()),
+                        call("ln", vec![u]),
+                    ),
+                    // u^v * (v' * ln(u) + v * u' / u)
+                    OperatorType::Pow => binary(
+                        OperatorType::Mul,
+                        self.clone(),
+                        binary(
+                            OperatorType::Add,
+                            binary(OperatorType::Mul, dv, call("ln", vec![u.clone()])),
+                            binary(OperatorType::Div, binary(OperatorType::Mul, v, du), u),
+                        ),
+                    ),
+                    _ => return Err(unsupported()),
+                }
+            }
+            Self::Call { name, args } if args.len() == 1 => {
+                let (_, derivative) = DERIVATIVES
+                    .iter()
+                    .find(|(func, _)| func == name)
+                    .ok_or_else(unsupported)?;
+                let outer = Self::template(derivative).substitute("u", &args[0]);
+                binary(OperatorType::Mul, outer, args[0].differentiate(var)?)
+            }
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => Self::Conditional {
+                cond: cond.clone(),
+                then: Box::new(then.differentiate(var)?),
+                otherwise: Box::new(otherwise.differentiate(var)?),
+            },
+            Self::List(items) => Self::List(
+                items
+                    .iter()
+                    .map(|item| item.differentiate(var))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Self::Unary { .. } | Self::Call { .. } => return Err(unsupported()),
+        })
+    }
+
+    /// Fold constants and apply algebraic identities such as `x*1 = x`, `x+0 = x`, `x-x = 0`
+    /// and `x*x = x^2`. Folding never turns exact numbers into floats.
+    #[must_use]
+    pub fn simplify(&self) -> Self {
+        match self {
+            Self::Number(_) | Self::Variable(_) => self.clone(),
+            Self::Unary { op, operand } => Self::simplify_unary(op, operand.simplify()),
+            Self::Binary { op, lhs, rhs } => {
+                Self::simplify_binary(op, lhs.simplify(), rhs.simplify())
+            }
+            Self::Call { name, args } => {
+                let args: Vec<_> = args.iter().map(Self::simplify).collect();
+                let values: Option<Vec<_>> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Self::Number(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let folded = builtins::function(name)
+                    .zip(values)
+                    .filter(|(builtin, values)| builtin.arity.accepts(values.len()))
+                    .and_then(|(builtin, values)| {
+                        let result = builtin.call(&values).ok()?;
+                        exact(result, &values.iter().collect::<Vec<_>>())
+                    });
+                folded.map_or_else(|| call(name, args), Self::Number)
+            }
+            Self::List(items) => Self::List(items.iter().map(Self::simplify).collect()),
+            Self::Conditional {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let (then, otherwise) = (then.simplify(), otherwise.simplify());
+                match cond.simplify() {
+                    Self::Number(v) if v.is_zero() => otherwise,
+                    Self::Number(_) => then,
+                    _ if then == otherwise => then,
+                    cond => Self::Conditional {
+                        cond: Box::new(cond),
+                        then: Box::new(then),
+                        otherwise: Box::new(otherwise),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Rewrite a sum with its like terms combined and the constant last, or `None` if a
+    /// coefficient overflows.
+    fn collect(sum: &Self) -> Option<Self> {
+        fn terms(ast: &Ast, negate: bool, out: &mut Vec<(Value, Ast)>) -> Option<()> {
+            match ast {
+                Ast::Binary { op, lhs, rhs }
+                    if matches!(op, OperatorType::Add | OperatorType::Sub) =>
+                {
+                    terms(lhs, negate, out)?;
+                    terms(rhs, negate != (*op == OperatorType::Sub), out)
+                }
+                Ast::Number(v) => push(v.clone(), number(1), negate, out),
+                _ => {
+                    let (c, term) = coefficient(ast);
+                    push(c, term, negate, out)
+                }
+            }
+        }
+        fn push(c: Value, term: Ast, negate: bool, out: &mut Vec<(Value, Ast)>) -> Option<()> {
+            let c = if negate {
+                OperatorType::Neg.apply_unary(c).ok()?
+            } else {
+                c
+            };
+            match out.iter_mut().find(|(_, like)| *like == term) {
+                Some((total, _)) => *total = OperatorType::Add.apply(total.clone(), c).ok()?,
+                None => out.push((c, term)),
+            }
+            Some(())
+        }
+
+        let mut collected = Vec::new();
+        terms(sum, false, &mut collected)?;
+        collected.retain(|(c, _)| !c.is_zero());
+        // the constant goes last, as in `3*x^2 + 2`
+        collected.sort_by_key(|(_, term)| is(term, 1.0));
+        let mut result: Option<Self> = None;
+        for (c, term) in collected {
+            result = Some(match result {
+                None => Self::simplify_binary(&OperatorType::Mul, Self::Number(c), term),
+                Some(acc) if c.as_float() < 0.0 => {
+                    let c = OperatorType::Neg.apply_unary(c).ok()?;
+                    let term = Self::simplify_binary(&OperatorType::Mul, Self::Number(c), term);
+                    binary(OperatorType::Sub, acc, term)
+                }
+                Some(acc) => {
+                    let term = Self::simplify_binary(&OperatorType::Mul, Self::Number(c), term);
+                    binary(OperatorType::Add, acc, term)
+                }
+            });
+        }
+        Some(result.unwrap_or_else(|| number(0)))
+    }
+
+    fn simplify_unary(op: &OperatorType, operand: Self) -> Self {
+        match (op, operand) {
+            (OperatorType::Pos, operand) => operand,
+            (op, Self::Number(v)) => op
+                .apply_unary(v.clone())
+                .ok()
+                .and_then(|r| exact(r, &[&v]))
+                .map_or_else(|| unary(op.clone(), Self::Number(v)), Self::Number),
+            (
+                OperatorType::Neg,
+                Self::Unary {
+                    op: OperatorType::Neg,
+                    operand,
+                },
+            ) => *operand,
+            // -(3*x) is -3*x
+            (
+                OperatorType::Neg,
+                Self::Binary {
+                    op: OperatorType::Mul,
+                    lhs,
+                    rhs,
+                },
+            ) if matches!(lhs.as_ref(), Self::Number(_)) => {
+                Self::simplify_binary(&OperatorType::Mul, Self::simplify_unary(op, *lhs), *rhs)
+            }
+            (op, operand) => unary(op.clone(), operand),
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn simplify_binary(op: &OperatorType, lhs: Self, rhs: Self) -> Self {
+        use OperatorType::{Add, Div, Mul, Neg, Pow, Sub};
+        if let (Self::Number(l), Self::Number(r)) = (&lhs, &rhs) {
+            if let Some(result) = fold_exact(op, l, r).or_else(|| {
+                op.apply(l.clone(), r.clone())
+                    .ok()
+                    .and_then(|result| exact(result, &[l, r]))
+            }) {
+                return Self::Number(result);
+            }
+        }
+        let numbers = matches!(lhs, Self::Number(_)) && matches!(rhs, Self::Number(_));
+        match op {
+            Add | Sub if is(&rhs, 0.0) => lhs,
+            Add if is(&lhs, 0.0) => rhs,
+            Sub if is(&lhs, 0.0) => Self::simplify_unary(&Neg, rhs),
+            // like terms, so x + x is 2*x and x - 1 - (x + 1) is -2
+            Add | Sub if !numbers => {
+                let sum = binary(op.clone(), lhs, rhs);
+                Self::collect(&sum).unwrap_or(sum)
+            }
+            Mul if is(&lhs, 0.0) || is(&rhs, 0.0) => number(0),
+            Div if is(&lhs, 0.0) && !is(&rhs, 0.0) => number(0),
+            Mul if is(&lhs, 1.0) => rhs,
+            Mul | Div if is(&rhs, 1.0) => lhs,
+            Mul if is(&lhs, -1.0) => Self::simplify_unary(&Neg, rhs),
+            // coefficients go first, so x*3 is 3*x
+            Mul if !numbers && matches!(rhs, Self::Number(_)) => {
+                Self::simplify_binary(op, rhs, lhs)
+            }
+            Mul if matches!(&lhs, Self::Unary { op: Neg, .. })
+                || matches!(&rhs, Self::Unary { op: Neg, .. }) =>
+            {
+                let strip = |ast: Self| match ast {
+                    Self::Unary { op: Neg, operand } => (true, *operand),
+                    ast => (false, ast),
+                };
+                let ((l_neg, lhs), (r_neg, rhs)) = (strip(lhs), strip(rhs));
+                let product = Self::simplify_binary(op, lhs, rhs);
+                if l_neg == r_neg {
+                    product
+                } else {
+                    Self::simplify_unary(&Neg, product)
+                }
+            }
+            // gather coefficients, so 2*(3*x) is 6*x and x*(2*y) is 2*x*y
+            Mul if matches!(&rhs, Self::Binary { op: Mul, lhs: c, .. } if matches!(c.as_ref(), Self::Number(_))) =>
+            {
+                let (c, rest) = coefficient(&rhs);
+                let lhs = if let Self::Number(_) = lhs {
+                    Self::simplify_binary(op, lhs, Self::Number(c))
+                } else {
+                    Self::simplify_binary(op, Self::Number(c), lhs)
+                };
+                Self::simplify_binary(op, lhs, rest)
+            }
+            // a product with a quotient is a quotient, so x*(1/y) is x/y and 1/2*x is x/2
+            Mul if !numbers && (quotient(&lhs).is_some() || quotient(&rhs).is_some()) => {
+                let ((numerator, denominator), other) = match quotient(&rhs) {
+                    Some(fraction) => (fraction, lhs),
+                    None => (quotient(&lhs).expect("Matched above"), rhs),
+                };
+                let numerator = Self::simplify_binary(op, other, numerator);
+                Self::simplify_binary(&Div, numerator, denominator)
+            }
+            // dividing by a fraction multiplies by its reciprocal, so x/(1/3) is 3*x
+            Div if matches!(&rhs, Self::Number(Value::Rational(r)) if !r.is_integer()) => {
+                let Self::Number(Value::Rational(r)) = rhs else {
+                    unreachable!("Matched above")
+                };
+                Self::simplify_binary(&Mul, Self::Number(rational(r.recip())), lhs)
+            }
+            // like factors, so x*x is x^2 and x^3/x is x^2
+            Mul | Div if !numbers => {
+                let ((base, exponent), (other_base, other_exponent)) = (power(&lhs), power(&rhs));
+                let combine = if *op == Mul { Add } else { Sub };
+                match (&lhs, &rhs) {
+                    _ if base == other_base && !matches!(base, Self::Number(_)) => {
+                        let exponent = Self::simplify_binary(&combine, exponent, other_exponent);
+                        match exponent {
+                            // a quotient stays one, so y/y^2 is 1/y
+                            Self::Number(e) if *op == Div && e.as_float() < 0.0 => {
+                                let e = Self::simplify_unary(&Neg, Self::Number(e));
+                                let power = Self::simplify_binary(&Pow, base, e);
+                                Self::simplify_binary(&Div, number(1), power)
+                            }
+                            exponent => Self::simplify_binary(&Pow, base, exponent),
+                        }
+                    }
+                    // cancel coefficients, so 2/(2*x) is 1/x
+                    (
+                        Self::Number(a),
+                        Self::Binary {
+                            op: Mul,
+                            lhs: b,
+                            rhs: y,
+                        },
+                    ) if *op == Div => {
+                        let reduced = match b.as_ref() {
+                            Self::Number(b) => op
+                                .apply(a.clone(), b.clone())
+                                .ok()
+                                .filter(|c| matches!(c, Value::Int(_))),
+                            _ => None,
+                        };
+                        reduced.map_or_else(
+                            || binary(op.clone(), lhs.clone(), rhs.clone()),
+                            |c| Self::simplify_binary(op, Self::Number(c), y.as_ref().clone()),
+                        )
+                    }
+                    // and the other way around, so 9*x^2/9 is x^2 and 6*x/4 is 3*x/2
+                    (
+                        Self::Binary {
+                            op: Mul,
+                            lhs: c,
+                            rhs: y,
+                        },
+                        Self::Number(d),
+                    ) if *op == Div => {
+                        let reduced = match c.as_ref() {
+                            Self::Number(c) => cancel(c, d),
+                            _ => None,
+                        };
+                        reduced.map_or_else(
+                            || binary(op.clone(), lhs.clone(), rhs.clone()),
+                            |c| Self::simplify_binary(&Mul, Self::Number(c), y.as_ref().clone()),
+                        )
+                    }
+                    _ => binary(op.clone(), lhs, rhs),
+                }
+            }
+            Pow if is(&rhs, 0.0) || is(&lhs, 1.0) => number(1),
+            Pow if is(&rhs, 1.0) => lhs,
+            _ => binary(op.clone(), lhs, rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ast(input: &str) -> Ast {
+        Ast::template(input)
+    }
+
+    fn simplified(input: &str) -> String {
+        ast(input).simplify().to_compact()
+    }
+
+    fn derivative(input: &str, var: &str) -> String {
+        ast(input).derivative(var).unwrap().to_compact()
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(simplified("x * 1 + 0"), "x");
+        assert_eq!(simplified("x - x"), "0");
+        assert_eq!(simplified("2 * 3 + x * 0"), "6");
+        assert_eq!(simplified("x + x"), "2*x");
+        assert_eq!(simplified("3*x - x"), "2*x");
+        assert_eq!(simplified("x * 3 * 2"), "6*x");
+        assert_eq!(simplified("x * x * x"), "x^3");
+        assert_eq!(simplified("x^3 / x"), "x^2");
+        assert_eq!(simplified("x * (1 / y)"), "x/y");
+        assert_eq!(simplified("y + -x"), "y - x");
+        assert_eq!(simplified("x - 1 - (x + 1)"), "-2");
+        assert_eq!(simplified("2 + x - 3*y + 2*y"), "x - y + 2");
+        assert_eq!(simplified("--x ^ 1"), "x");
+        assert_eq!(simplified("1 ? a : b"), "a");
+        assert_eq!(simplified("0 / x"), "0");
+        assert_eq!(simplified("y / y^2"), "1/y");
+    }
+    #[test]
+    fn test_fold_exact() {
+        assert_eq!(simplified("6 / 3 * x"), "2*x");
+        assert_eq!(simplified("1 / 3 * x"), "x/3");
+        assert_eq!(simplified("sqrt(4) + sqrt(2)"), "sqrt(2) + 2");
+        assert_eq!(simplified("0.5 * 3"), "1.5");
+        assert_eq!(simplified("x ^ (1/2 - 1)"), "x^(-1/2)");
+        assert_eq!(simplified("x / (1/3)"), "3*x");
+        assert_eq!(simplified("6 * x / 4"), "3*x/2");
+    }
+    #[test]
+    fn test_derivative() {
+        assert_eq!(derivative("x^3 + 2*x", "x"), "3*x^2 + 2");
+        assert_eq!(derivative("x * y", "x"), "y");
+        assert_eq!(derivative("y ^ 2", "x"), "0");
+        assert_eq!(derivative("sin(2 * x)", "x"), "2*cos(2*x)");
+        assert_eq!(derivative("cos(x)", "x"), "-sin(x)");
+        assert_eq!(derivative("1 / x", "x"), "-1/x^2");
+        assert_eq!(derivative("2 ^ x", "x"), "2^x*ln(2)");
+        assert_eq!(derivative("sqrt(x^2 + 1)", "x"), "x/sqrt(x^2 + 1)");
+        assert_eq!(derivative("x ^ x", "x"), "x^x*(ln(x) + 1)");
+        assert_eq!(derivative("e ^ x", "x"), "e^x*ln(e)");
+        assert_eq!(derivative("y ^ x", "x"), "y^x*ln(y)");
+        assert_eq!(derivative("2 ^ (3 * x)", "x"), "3*2^(3*x)*ln(2)");
+        assert_eq!(derivative("(x + 1) / (x - 1)", "x"), "-2/(x - 1)^2");
+        assert_eq!(derivative("x > 0 ? x^2 : -x", "x"), "x > 0 ? 2*x : -1");
+        assert_eq!(derivative("x ^ (1/2)", "x"), "x^(-1/2)/2");
+        assert_eq!(derivative("x^3 / 3", "x"), "x^2");
+        assert_eq!(derivative("0 ^ x", "x"), "0");
+        assert_eq!(derivative("x / y", "x"), "1/y");
+    }
+    #[test]
+    fn test_lists() {
+        assert_eq!(derivative("[x^2, 3 * x, y]", "x"), "[2*x, 3, 0]");
+        assert_eq!(simplified("[x * 1, 2 + 2]"), "[x, 4]");
+    }
+    #[test]
+    fn test_not_differentiable() {
+        assert_eq!(
+            ast("floor(x) + 1").derivative("x"),
+            Err(SymbolicError::NotDifferentiable("floor(x)".into()))
+        );
+        assert_eq!(ast("floor(y)").derivative("x"), Ok(number(0)));
+    }
+}
@@ -1,14 +1,75 @@
+use crate::builtins::{self, Arity};
+use crate::lexer::Lexer;
+use crate::postfix::{EvalError, PostExpression, MAX_LIST_LEN};
+use crate::registry::{self, CustomOperator, Registry};
+use crate::span::{Span, Spanned};
+use crate::units::Quantity;
+use crate::value::{Arithmetic, Value};
 use log::debug;
-use std::{cmp, str::FromStr};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+use std::{cmp, fmt, iter, str::FromStr, sync::Arc};
 use thiserror::Error;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Largest power `--exact` mode will compute, in bits of numerator and denominator.
+const MAX_EXACT_BITS: u64 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OperatorType {
     Add,
     Sub,
     Div,
     Mul,
+    /// Multiplication of an operand by the unit after it, e.g. `30 min`. It binds tighter than
+    /// `/` so `2 km / 30 min` is a speed, the postfix conversion outputs it as `Mul`.
+    Implicit,
     Pow,
+    /// Unary minus, e.g. `-3`
+    Neg,
+    /// Unary plus, e.g. `+3`
+    Pos,
+    /// Remainder of truncating division, e.g. `-7 % 3` is `-1`
+    Rem,
+    BitAnd,
+    BitOr,
+    /// Bitwise exclusive or, written `xor`
+    Xor,
+    /// Bitwise not, e.g. `~0` is `-1`
+    BitNot,
+    Shl,
+    /// Arithmetic shift right, e.g. `-8 >> 1` is `-4`
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Logical and, `&&`, only evaluates its right side when the left is true
+    And,
+    /// Logical or, `||`, only evaluates its right side when the left is false
+    Or,
+    /// Logical not, `!`, is `1` for zero and `0` otherwise
+    Not,
+    /// The conditional `cond ? a : b`, it takes three operands
+    Cond,
+    /// Unit conversion, e.g. `60 mi/h to km/h`
+    To,
+    /// The list of whole numbers from one to the other, e.g. `1..3` is `[1, 2, 3]`
+    Range,
+    /// An operator added to a `Registry`
+    Custom(Arc<CustomOperator>),
+}
+
+/// Which side operators of equal precedence group from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`
+    Right,
 }
 
 impl PartialOrd for OperatorType {
@@ -19,27 +80,401 @@ impl PartialOrd for OperatorType {
 
 impl Ord for OperatorType {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        let precedence = |op: &Self| match op {
-            // greater value means heigher precedence.
-            Self::Pow => 3,
-            Self::Mul | Self::Div => 2,
-            Self::Add | Self::Sub => 1,
-        };
         debug!("Comparing operators {:?} and {:?}", self, other);
-        precedence(self).cmp(&precedence(other))
+        self.precedence().cmp(&other.precedence())
     }
 }
 
 impl OperatorType {
-    #[allow(clippy::cast_sign_loss)]
-    pub fn apply(&self, lhs: isize, rhs: isize) -> isize {
+    /// Greater value means higher precedence, see `registry` for the builtin order.
+    #[must_use]
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Custom(custom) => custom.precedence(),
+            op => registry::builtin(op).precedence,
+        }
+    }
+
+    #[must_use]
+    pub fn associativity(&self) -> Associativity {
         match self {
+            Self::Custom(custom) => custom.associativity(),
+            op => registry::builtin(op).associativity,
+        }
+    }
+
+    /// Prefix operators that take a single operand.
+    #[must_use]
+    pub fn is_unary(&self) -> bool {
+        match self {
+            Self::Custom(custom) => custom.arity() == 1,
+            op => registry::builtin(op).arity == 1,
+        }
+    }
+
+    /// Operators that may skip evaluating some of their operands.
+    #[must_use]
+    pub const fn short_circuits(&self) -> bool {
+        matches!(self, Self::And | Self::Or | Self::Cond)
+    }
+
+    /// Operators that work on the bits of whole numbers.
+    #[must_use]
+    pub const fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            Self::BitAnd | Self::BitOr | Self::Xor | Self::BitNot | Self::Shl | Self::Shr
+        )
+    }
+
+    /// The prefix form of this operator, if it has one.
+    #[must_use]
+    pub fn to_unary(&self) -> Option<Self> {
+        if self.is_unary() {
+            return Some(self.clone());
+        }
+        match self {
+            Self::Custom(_) => None,
+            op => registry::builtin_operator(registry::builtin(op).symbol, 1),
+        }
+    }
+
+    /// Lists are negated, or otherwise operated on, element by element.
+    ///
+    /// # Errors
+    /// When the result overflows, or `~` is applied to a number that isn't whole.
+    pub fn apply_unary(&self, operand: Value) -> Result<Value, EvalError> {
+        if let Value::List(items) = operand {
+            return items
+                .into_iter()
+                .map(|item| self.apply_unary(item))
+                .collect::<Result<_, _>>()
+                .map(Value::List);
+        }
+        match (self, operand) {
+            (Self::Neg, Value::Int(i)) => {
+                i.checked_neg().map(Value::Int).ok_or(EvalError::Overflow)
+            }
+            (Self::Neg, Value::Float(f)) => Ok(Value::Float(-f)),
+            (Self::Neg, Value::Rational(r)) => Ok(Value::Rational(-r)),
+            (Self::Neg, Value::Complex(c)) => Ok(Value::Complex(-c)),
+            (Self::Neg, Value::Quantity(q)) => Ok(Value::Quantity(Quantity::new(-q.value, q.unit))),
+            (Self::Pos, operand) => Ok(operand),
+            (Self::Not, operand) => Ok(truth(operand.is_zero())),
+            (Self::Custom(custom), operand) => custom.apply(&[operand]),
+            (Self::BitNot, operand) => {
+                let not = !operand
+                    .to_bigint()
+                    .ok_or_else(|| EvalError::NotInteger(format!("{self}{operand}")))?;
+                Self::from_bits(not, &[&operand])
+            }
+            (op, _) => unreachable!("{:?} is not a unary operator", op),
+        }
+    }
+
+    /// Apply the operator, keeping integer results exact when both sides are integers
+    /// and the result is representable as one. Otherwise the operands are promoted to floats.
+    /// Rationals stay exact unless the other side is a float.
+    /// Complex numbers promote the other side, as do fractional powers of negative numbers.
+    /// Bitwise operators need whole numbers, comparisons and logic give `1` for true and `0` for false.
+    /// Quantities with units are floats, see `apply_quantity`.
+    /// Lists are operated on element by element, see `broadcast`.
+    ///
+    /// # Errors
+    /// When the operation has no result, e.g. a division by zero, an integer overflow, units
+    /// that don't match or lists of different lengths.
+    pub fn apply(&self, lhs: Value, rhs: Value) -> Result<Value, EvalError> {
+        match self {
+            Self::And => return Ok(truth(!lhs.is_zero() && !rhs.is_zero())),
+            Self::Or => return Ok(truth(!lhs.is_zero() || !rhs.is_zero())),
+            Self::Range => return range(&lhs, &rhs),
+            _ if lhs.is_list() || rhs.is_list() => return self.broadcast(lhs, rhs),
+            Self::Custom(custom) => return custom.apply(&[lhs, rhs]),
+            _ if self.is_bitwise() => return self.apply_bits(&lhs, &rhs),
+            Self::To => return self.apply_quantity(&lhs, &rhs),
+            _ if lhs.is_quantity() || rhs.is_quantity() => return self.apply_quantity(&lhs, &rhs),
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => {
+                return self.compare(&lhs, &rhs)
+            }
+            _ => {}
+        }
+        match (lhs, rhs) {
+            (Value::Int(l), Value::Int(r)) => self.apply_int(l, r),
+            (lhs @ Value::Complex(_), rhs) | (lhs, rhs @ Value::Complex(_)) => {
+                self.apply_complex(lhs.as_complex(), rhs.as_complex())
+            }
+            (lhs @ Value::Float(_), rhs) | (lhs, rhs @ Value::Float(_)) => {
+                let (lhs, rhs) = (lhs.as_float(), rhs.as_float());
+                if *self == Self::Pow && lhs < 0.0 && rhs.fract() != 0.0 {
+                    return self.apply_complex(lhs.into(), rhs.into());
+                }
+                self.apply_float(lhs, rhs).map(Value::Float)
+            }
+            (lhs, rhs) => {
+                let (Some(lhs), Some(rhs)) = (lhs.to_rational(), rhs.to_rational()) else {
+                    unreachable!("Neither side is a float")
+                };
+                self.apply_rational(lhs, rhs).map(Value::Rational)
+            }
+        }
+    }
+
+    /// Complex numbers can only be compared for equality, `NaN` is unequal to everything.
+    fn compare(&self, lhs: &Value, rhs: &Value) -> Result<Value, EvalError> {
+        let ordering = match (lhs, rhs) {
+            (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+            (Value::Complex(_), _) | (_, Value::Complex(_)) => {
+                if !matches!(self, Self::Eq | Self::Ne) {
+                    return Err(EvalError::Domain(format!("{lhs} {self} {rhs}")));
+                }
+                let equal = lhs.as_complex() == rhs.as_complex();
+                equal.then_some(cmp::Ordering::Equal)
+            }
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                lhs.as_float().partial_cmp(&rhs.as_float())
+            }
+            _ => lhs.to_rational().partial_cmp(&rhs.to_rational()),
+        };
+        let holds = match self {
+            Self::Eq => ordering.is_some_and(cmp::Ordering::is_eq),
+            Self::Ne => !ordering.is_some_and(cmp::Ordering::is_eq),
+            Self::Lt => ordering.is_some_and(cmp::Ordering::is_lt),
+            Self::Le => ordering.is_some_and(cmp::Ordering::is_le),
+            Self::Gt => ordering.is_some_and(cmp::Ordering::is_gt),
+            Self::Ge => ordering.is_some_and(cmp::Ordering::is_ge),
+            _ => unreachable!("{:?} is not a comparison", self),
+        };
+        Ok(truth(holds))
+    }
+
+    /// Apply the operator to lists element by element, `[1, 2] + [10, 20]` is `[11, 22]`.
+    /// A value that isn't a list goes with every element, `[1, 2] * 3` is `[3, 6]`.
+    fn broadcast(&self, lhs: Value, rhs: Value) -> Result<Value, EvalError> {
+        let pairs: Vec<_> = match (lhs, rhs) {
+            (Value::List(l), Value::List(r)) if l.len() != r.len() => {
+                return Err(EvalError::LengthMismatch(l.len(), r.len()))
+            }
+            (Value::List(l), Value::List(r)) => l.into_iter().zip(r).collect(),
+            (Value::List(l), rhs) => l.into_iter().map(|l| (l, rhs.clone())).collect(),
+            (lhs, Value::List(r)) => r.into_iter().map(|r| (lhs.clone(), r)).collect(),
+            _ => unreachable!("One side is a list"),
+        };
+        pairs
+            .into_iter()
+            .map(|(l, r)| self.apply(l, r))
+            .collect::<Result<_, _>>()
+            .map(Value::List)
+    }
+
+    /// Arithmetic on quantities, plain numbers being quantities without a unit.
+    /// Sums, remainders and comparisons need the same dimension on both sides and give the
+    /// result in the unit of the left side, products and quotients combine the units.
+    /// `to` converts the left side to the unit of the right, which must be just a unit, not
+    /// `2 cm`. Complex numbers have no units.
+    #[allow(clippy::float_cmp)]
+    fn apply_quantity(&self, lhs: &Value, rhs: &Value) -> Result<Value, EvalError> {
+        let what = || format!("{lhs} {self} {rhs}");
+        let (Some(l), Some(r)) = (lhs.to_quantity(), rhs.to_quantity()) else {
+            return Err(EvalError::Domain(what()));
+        };
+        let same_dimension = |l: &Quantity, r: &Quantity| {
+            (l.unit.dimension() == r.unit.dimension())
+                .then_some(())
+                .ok_or_else(|| EvalError::IncompatibleUnits(what()))
+        };
+        match self {
+            Self::Add | Self::Sub | Self::Rem => {
+                same_dimension(&l, &r)?;
+                let value = self.apply_float(l.value, r.value_in(&l.unit))?;
+                Ok(Quantity::new(value, l.unit).into())
+            }
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => {
+                same_dimension(&l, &r)?;
+                self.compare(&Value::Float(l.value), &Value::Float(r.value_in(&l.unit)))
+            }
+            Self::Mul | Self::Div => {
+                let value = self.apply_float(l.value, r.value)?;
+                let unit = l
+                    .unit
+                    .times(&r.unit, if *self == Self::Mul { 1 } else { -1 });
+                // e.g. `m/km` or `30 deg`, which are plain numbers
+                if unit.is_dimensionless() {
+                    return Ok(Value::Float(value * unit.scale()));
+                }
+                Ok(Quantity::new(value, unit).into())
+            }
+            Self::Pow => {
+                if !r.unit.is_dimensionless() {
+                    return Err(EvalError::InvalidExponent(what()));
+                }
+                let exponent = r.value * r.unit.scale();
+                let unit = l
+                    .unit
+                    .pow(exponent)
+                    .ok_or_else(|| EvalError::InvalidExponent(what()))?;
+                let value = self.apply_float(l.value, exponent)?;
+                Ok(Quantity::new(value, unit).into())
+            }
+            Self::To => {
+                if !rhs.is_quantity() || r.value != 1.0 {
+                    return Err(EvalError::NotAUnit(rhs.to_string()));
+                }
+                same_dimension(&l, &r)?;
+                Ok(Value::Quantity(Quantity::new(l.value_in(&r.unit), r.unit)))
+            }
+            _ => unreachable!("{:?} is not an arithmetic binary operator", self),
+        }
+    }
+
+    /// Pick the second or third operand of `?:` depending on the first.
+    #[must_use]
+    pub fn apply_cond(cond: &Value, then: Value, otherwise: Value) -> Value {
+        if cond.is_zero() {
+            otherwise
+        } else {
+            then
+        }
+    }
+
+    /// Bitwise operators act on two's complement with as many bits as needed,
+    /// so negative numbers behave as if their sign bit repeats forever.
+    fn apply_bits(&self, lhs: &Value, rhs: &Value) -> Result<Value, EvalError> {
+        let what = || format!("{lhs} {self} {rhs}");
+        let (Some(l), Some(r)) = (lhs.to_bigint(), rhs.to_bigint()) else {
+            return Err(EvalError::NotInteger(what()));
+        };
+        let shift = || {
+            r.to_u64()
+                .filter(|&n| n <= MAX_EXACT_BITS)
+                .ok_or_else(|| EvalError::InvalidShift(what()))
+        };
+        let result = match self {
+            Self::BitAnd => l & r,
+            Self::BitOr => l | r,
+            Self::Xor => l ^ r,
+            Self::Shl => l << shift()?,
+            Self::Shr => l >> shift()?,
+            _ => unreachable!("{:?} is not a bitwise binary operator", self),
+        };
+        Self::from_bits(result, &[lhs, rhs])
+    }
+
+    /// The result of a bitwise operator as a value, it stays exact if any operand was a rational.
+    fn from_bits(result: BigInt, operands: &[&Value]) -> Result<Value, EvalError> {
+        if operands.iter().any(|v| matches!(v, Value::Rational(_))) {
+            return Ok(Value::Rational(BigRational::from_integer(result)));
+        }
+        result.to_isize().map(Value::Int).ok_or(EvalError::Overflow)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn apply_int(&self, lhs: isize, rhs: isize) -> Result<Value, EvalError> {
+        let checked = match self {
+            Self::Add => lhs.checked_add(rhs),
+            Self::Sub => lhs.checked_sub(rhs),
+            Self::Mul => lhs.checked_mul(rhs),
+            Self::Div | Self::Rem if rhs == 0 => return Err(EvalError::DivisionByZero),
+            // `isize::MIN / -1` has no remainder to check and overflows
+            Self::Div if lhs.checked_rem(rhs).is_none_or(|rem| rem == 0) => lhs.checked_div(rhs),
+            Self::Div => return self.apply_float(lhs as f64, rhs as f64).map(Value::Float),
+            // negative exponents can't stay integers
+            Self::Pow if rhs < 0 => {
+                return self.apply_float(lhs as f64, rhs as f64).map(Value::Float)
+            }
+            Self::Pow => {
+                let exp = u32::try_from(rhs)
+                    .map_err(|_| EvalError::InvalidExponent(format!("{lhs} ^ {rhs}")))?;
+                lhs.checked_pow(exp)
+            }
+            Self::Rem => lhs.checked_rem(rhs),
+            _ => unreachable!("{:?} is not an arithmetic binary operator", self),
+        };
+        checked.map(Value::Int).ok_or(EvalError::Overflow)
+    }
+
+    fn apply_rational(&self, lhs: BigRational, rhs: BigRational) -> Result<BigRational, EvalError> {
+        match self {
+            Self::Add => Ok(lhs + rhs),
+            Self::Sub => Ok(lhs - rhs),
+            Self::Mul => Ok(lhs * rhs),
+            Self::Div | Self::Rem if rhs.is_zero() => Err(EvalError::DivisionByZero),
+            Self::Div => Ok(lhs / rhs),
+            // roots of rationals are rarely rational
+            Self::Pow if !rhs.is_integer() => Err(EvalError::Inexact(format!("{lhs} ^ {rhs}"))),
+            Self::Pow => {
+                let exp = rhs
+                    .to_integer()
+                    .to_i32()
+                    .ok_or_else(|| EvalError::InvalidExponent(format!("{lhs} ^ {rhs}")))?;
+                if lhs.is_zero() && exp < 0 {
+                    return Err(EvalError::DivisionByZero);
+                }
+                let bits =
+                    (lhs.numer().bits() + lhs.denom().bits()) * u64::from(exp.unsigned_abs());
+                if bits > MAX_EXACT_BITS {
+                    return Err(EvalError::Overflow);
+                }
+                Ok(lhs.pow(exp))
+            }
+            Self::Rem => Ok(lhs % rhs),
+            _ => unreachable!("{:?} is not an arithmetic binary operator", self),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn apply_complex(&self, lhs: Complex64, rhs: Complex64) -> Result<Value, EvalError> {
+        let result = match self {
             Self::Add => lhs + rhs,
+            Self::Sub => lhs - rhs,
+            Self::Mul => lhs * rhs,
+            Self::Div | Self::Rem if rhs.is_zero() => return Err(EvalError::DivisionByZero),
             Self::Div => lhs / rhs,
+            Self::Pow if rhs.is_zero() => Complex64::one(),
+            Self::Pow if lhs.is_zero() && rhs.re > 0.0 => Complex64::zero(),
+            Self::Pow if lhs.is_zero() => return Err(EvalError::DivisionByZero),
+            // repeated multiplication is exact for small whole powers, `(1 + 2i) ^ 2` is `-3 + 4i`
+            Self::Pow if rhs.im == 0.0 && rhs.re.fract() == 0.0 && rhs.re.abs() <= 64.0 => {
+                lhs.powi(rhs.re as i32)
+            }
+            Self::Pow => {
+                let result = lhs.powc(rhs);
+                if !result.is_finite() && lhs.is_finite() && rhs.is_finite() {
+                    return Err(EvalError::Overflow);
+                }
+                return Ok(Value::transcendental(result));
+            }
+            Self::Rem => {
+                let (lhs, rhs) = (Value::from(lhs), Value::from(rhs));
+                return Err(EvalError::Domain(format!("{lhs} % {rhs}")));
+            }
+            _ => unreachable!("{:?} is not an arithmetic binary operator", self),
+        };
+        if !result.is_finite() && lhs.is_finite() && rhs.is_finite() {
+            return Err(EvalError::Overflow);
+        }
+        Ok(Value::from(result))
+    }
+
+    fn apply_float(&self, lhs: f64, rhs: f64) -> Result<f64, EvalError> {
+        let result = match self {
+            Self::Add => lhs + rhs,
             Self::Sub => lhs - rhs,
             Self::Mul => lhs * rhs,
-            Self::Pow => lhs.pow(u32::try_from(rhs).unwrap()),
+            Self::Div | Self::Rem if rhs == 0.0 => return Err(EvalError::DivisionByZero),
+            Self::Div => lhs / rhs,
+            Self::Pow if lhs == 0.0 && rhs < 0.0 => return Err(EvalError::DivisionByZero),
+            Self::Pow => lhs.powf(rhs),
+            Self::Rem => lhs % rhs,
+            _ => unreachable!("{:?} is not an arithmetic binary operator", self),
+        };
+        if result.is_nan() && !lhs.is_nan() && !rhs.is_nan() {
+            // e.g. `inf - inf`, fractional powers of negative numbers are complex and never get here
+            return Err(EvalError::Domain(format!("{lhs} {self} {rhs}")));
+        }
+        if result.is_infinite() && lhs.is_finite() && rhs.is_finite() {
+            return Err(EvalError::Overflow);
         }
+        Ok(result)
     }
 }
 #[derive(Error, Debug)]
@@ -51,45 +486,234 @@ pub enum MathEquationErr {
     InvalidOperand(String),
 }
 
+/// Only builtin operators are known here, `Registry::operator` also finds registered ones.
 impl FromStr for OperatorType {
     type Err = MathEquationErr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "+" => Ok(Self::Add),
-            "-" => Ok(Self::Sub),
-            "/" => Ok(Self::Div),
-            "*" => Ok(Self::Mul),
-            "^" => Ok(Self::Pow),
-            _ => Err(MathEquationErr::InvalidOperatorType(s.to_string())),
+        registry::builtin_operator(s, 2)
+            .or_else(|| registry::builtin_operator(s, 1))
+            .ok_or_else(|| MathEquationErr::InvalidOperatorType(s.to_string()))
+    }
+}
+
+impl fmt::Display for OperatorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Custom(custom) => write!(f, "{}", custom.symbol()),
+            op => write!(f, "{}", registry::builtin(op).symbol),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MathToken {
     IntOperand(isize),
-    // FloatOperand(f64),
+    FloatOperand(f64),
+    /// Number directly followed by `i`, e.g. `2i`
+    ImaginaryOperand(f64),
+    /// Whole number too large for `IntOperand`
+    BigIntOperand(BigInt),
     Operator(OperatorType),
     /// Opening or closing parentheses
     Parens(bool),
+    /// Opening or closing square brackets of a list, e.g. `[1, 2, 3]`
+    Brackets(bool),
+    /// Postfix only, make a list of the given number of values from the stack
+    List(usize),
+    /// Postfix only, a sum or product over a range, its bounds are on the stack
+    Series(Box<Series>),
+    /// Name of a variable, e.g. `rate`
+    Identifier(String),
+    /// Postfix only, a name read where a unit can be, e.g. the `cm` of `20 cm`
+    Unit(String),
+    /// `=` in an assignment like `rate = 0.07`
+    Assign,
+    /// Name of a function being called, always followed by an opening parenthesis
+    Function(String),
+    /// Separator between function arguments
+    Comma,
+    /// Postfix only, call the named function with the given number of arguments from the stack
+    Call(String, usize),
+    /// `?` of a conditional, before its `:` has been read
+    Question,
+    /// `:` of a conditional
+    Colon,
+    /// Postfix only, jump to the token at the index when the condition holds for the value on
+    /// top of the stack. A placeholder takes the place of the operand that was skipped,
+    /// this is how `&&`, `||` and `?:` avoid evaluating an operand they don't need.
+    Skip(SkipIf, usize),
+}
+
+/// When a `MathToken::Skip` jumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipIf {
+    Always,
+    True,
+    False,
+}
+
+/// `1` for true and `0` for false.
+fn truth(holds: bool) -> Value {
+    Value::Int(isize::from(holds))
+}
+
+/// `from..to`, the whole numbers from `from` up to and including `to`.
+/// It is empty when `to` is smaller, like the bounds of a sum with no terms.
+fn range(from: &Value, to: &Value) -> Result<Value, EvalError> {
+    let what = || format!("{from}..{to}");
+    let (Some(start), Some(end)) = (from.to_bigint(), to.to_bigint()) else {
+        return Err(EvalError::NotInteger(what()));
+    };
+    let (Some(start), Some(end)) = (start.to_isize(), end.to_isize()) else {
+        return Err(EvalError::Overflow);
+    };
+    if end
+        .checked_sub(start)
+        .is_none_or(|len| len >= MAX_LIST_LEN.cast_signed())
+    {
+        return Err(EvalError::TooLong(what()));
+    }
+    Ok(Value::List((start..=end).map(Value::Int).collect()))
+}
+
+/// `sum(i, 1, 100, i^2)` or `product(i, 1, 5, i)`, the body evaluated for every whole number
+/// from the first bound to the second with `var` bound to it, added up or multiplied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    /// Multiply the terms instead of adding them.
+    pub product: bool,
+    pub var: String,
+    pub body: PostExpression,
+}
+
+impl Series {
+    /// The function the series is written as.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        if self.product {
+            "product"
+        } else {
+            "sum"
+        }
+    }
+
+    /// Combine the terms for the values of the variable from `from` to `to`, `term` evaluates
+    /// the body for one of them. Errors of the series itself, like fractional bounds, go
+    /// through `at`. With no terms the result is `0`, or `1` for a product.
+    ///
+    /// # Errors
+    /// When the bounds aren't whole numbers, the series is too long, or a term fails.
+    pub fn fold<E>(
+        &self,
+        (from, to): (Value, Value),
+        arithmetic: Arithmetic,
+        at: impl Fn(EvalError) -> E,
+        mut term: impl FnMut(Value) -> Result<Value, E>,
+    ) -> Result<Value, E> {
+        let op = if self.product {
+            OperatorType::Mul
+        } else {
+            OperatorType::Add
+        };
+        let Value::List(values) = range(&from, &to).map_err(&at)? else {
+            unreachable!("Ranges are lists")
+        };
+        let empty = Value::Int(isize::from(self.product));
+        let mut total = arithmetic.literal(empty).map_err(&at)?;
+        for value in values {
+            let value = arithmetic.literal(value).map_err(&at)?;
+            let result = op.apply(total, term(value)?).map_err(&at)?;
+            total = arithmetic.fit(result, &op).map_err(&at)?;
+        }
+        Ok(total)
+    }
+}
+
+/// Written like a call, with the body in postfix, e.g. `sum(i, i 2 ^)`.
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}, {})", self.name(), self.var, self.body)
+    }
+}
+
+impl From<Value> for MathToken {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Int(i) => Self::IntOperand(i),
+            Value::Float(f) => Self::FloatOperand(f),
+            Value::Complex(c) if c.re == 0.0 => Self::ImaginaryOperand(c.im),
+            // only whole numbers that don't fit an `isize` parse as rationals
+            Value::Rational(r) => Self::BigIntOperand(r.to_integer()),
+            Value::Complex(_) | Value::Quantity(_) | Value::List(_) => {
+                unreachable!("Only numbers as written in the input become tokens")
+            }
+        }
+    }
+}
+
+/// Tokens are written the way they appear in postfix output.
+/// Unary signs are spelled `neg` and `pos` there, since `-` alone would be ambiguous.
+impl fmt::Display for MathToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IntOperand(i) => write!(f, "{i}"),
+            Self::FloatOperand(x) => write!(f, "{}", Value::Float(*x)),
+            Self::ImaginaryOperand(x) => write!(f, "{x}i"),
+            Self::BigIntOperand(n) => write!(f, "{n}"),
+            Self::Operator(OperatorType::Neg) => write!(f, "neg"),
+            Self::Operator(OperatorType::Pos) => write!(f, "pos"),
+            Self::Operator(op) => write!(f, "{op}"),
+            Self::Parens(true) => write!(f, "("),
+            Self::Parens(false) => write!(f, ")"),
+            Self::Brackets(true) => write!(f, "["),
+            Self::Brackets(false) => write!(f, "]"),
+            Self::List(len) => write!(f, "list({len})"),
+            Self::Series(series) => write!(f, "{series}"),
+            Self::Identifier(name) | Self::Unit(name) | Self::Function(name) => {
+                write!(f, "{name}")
+            }
+            Self::Assign => write!(f, "="),
+            Self::Comma => write!(f, ","),
+            Self::Question => write!(f, "?"),
+            Self::Colon => write!(f, ":"),
+            Self::Skip(SkipIf::Always, target) => write!(f, "jump({target})"),
+            Self::Skip(SkipIf::True, target) => write!(f, "jump-if-true({target})"),
+            Self::Skip(SkipIf::False, target) => write!(f, "jump-if-false({target})"),
+            Self::Call(name, argc) => match builtins::function(name) {
+                // the argument count is only needed when the function accepts several
+                Some(builtin) if !matches!(builtin.arity, Arity::Exact(_)) => {
+                    write!(f, "{name}({argc})")
+                }
+                _ => write!(f, "{name}"),
+            },
+        }
+    }
 }
 
 impl FromStr for MathToken {
     type Err = MathEquationErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // if let Ok(f) = s.parse::<f64>() {
-        //     return Ok(Self::FloatOperand(f));
-        // } else
         debug!("Checking token as parentheses");
         if s == "(" {
             return Ok(Self::Parens(true));
         } else if s == ")" {
             return Ok(Self::Parens(false));
+        } else if s == "=" {
+            return Ok(Self::Assign);
+        } else if s == "," {
+            return Ok(Self::Comma);
+        } else if s == "?" {
+            return Ok(Self::Question);
+        } else if s == ":" {
+            return Ok(Self::Colon);
         }
         debug!("Evaluating '{}' as an operand", s);
-        if let Ok(i) = s.parse::<isize>() {
-            return Ok(Self::IntOperand(i));
+        if let Ok(v) = s.parse::<Value>() {
+            return Ok(v.into());
+        }
+        if s.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return Err(MathEquationErr::InvalidOperand(s.to_string()));
         }
         debug!("Evaluating '{}' as an operator", s);
         let op_res = s.parse::<OperatorType>();
@@ -97,51 +721,61 @@ impl FromStr for MathToken {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct Expression {
     /// postfix ordering of a given math equation.
     pub tokens: Vec<MathToken>,
+    /// Where each token was read from, empty for expressions built from bare tokens.
+    pub spans: Vec<Span>,
+}
+
+/// Spans only locate tokens in the input, expressions with the same tokens are equal.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.tokens == other.tokens
+    }
 }
 
 impl FromStr for Expression {
-    type Err = MathEquationErr;
-    /// Shunting Yard Algorithm
-    /// <https://en.wikipedia.org/wiki/Shunting_yard_algorithm#The_algorithm_in_detail>
-    /// Assume that tokens (besides parentheses) are separated by spaces.
+    type Err = Spanned<MathEquationErr>;
+    /// Tokenize an infix expression, whitespace between tokens is optional.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let toks: Result<Vec<_>, _> = s
-            .split_whitespace()
-            .flat_map(|t| {
-                if t.starts_with('(') {
-                    let mut chars = t.chars();
-                    let first = chars
-                        .next()
-                        .expect("Opening parenthesis to exist")
-                        .to_string();
-                    let rest = chars.collect::<String>();
-                    vec![first.parse::<MathToken>(), rest.parse::<MathToken>()]
-                } else if t.ends_with(')') {
-                    let chars = t.chars();
-                    let rest: String = chars.clone().take(t.len() - 1).collect();
-                    let last = chars
-                        .last()
-                        .expect("Closing parenthesis to exist")
-                        .to_string();
-                    vec![rest.parse::<MathToken>(), last.parse::<MathToken>()]
-                } else {
-                    vec![t.parse::<MathToken>()]
-                }
-            })
-            .collect();
-
-        toks.map_or_else(Err, |tokens| Ok(Self { tokens }))
+        Self::parse_with(s, &Registry::default())
     }
 }
 
-#[allow(dead_code)]
 impl Expression {
-    pub fn new(tokens: Vec<MathToken>) -> Self {
-        Self { tokens }
+    #[must_use]
+    pub const fn new(tokens: Vec<MathToken>) -> Self {
+        Self {
+            tokens,
+            spans: Vec::new(),
+        }
+    }
+
+    /// Tokenize an expression that may use the operators added to `registry`.
+    ///
+    /// # Errors
+    /// When an operator or number can't be read.
+    pub fn parse_with(s: &str, registry: &Registry) -> Result<Self, Spanned<MathEquationErr>> {
+        Lexer::new(s, registry)
+            .collect::<Result<Vec<_>, _>>()
+            .map(|tokens| tokens.into_iter().unzip())
+            .map(|(tokens, spans)| Self::new(tokens).with_spans(spans))
+    }
+
+    /// Record where each token was read from, in the same order as the tokens.
+    #[must_use]
+    pub fn with_spans(mut self, spans: Vec<Span>) -> Self {
+        debug_assert_eq!(self.tokens.len(), spans.len());
+        self.spans = spans;
+        self
+    }
+
+    /// Pair every token with its span, tokens without one get an empty span at the start.
+    pub fn into_spanned(self) -> impl Iterator<Item = (MathToken, Span)> {
+        let spans = self.spans.into_iter().chain(iter::repeat(Span::default()));
+        self.tokens.into_iter().zip(spans)
     }
 }
 
@@ -189,6 +823,267 @@ mod tests {
         );
     }
     #[test]
+    fn test_float_mathtoken_from_str() {
+        assert_eq!(
+            "1.5".parse::<MathToken>().unwrap(),
+            MathToken::FloatOperand(1.5)
+        );
+        assert_eq!(
+            ".25".parse::<MathToken>().unwrap(),
+            MathToken::FloatOperand(0.25)
+        );
+        assert!("1.5.2".parse::<MathToken>().is_err());
+        assert!("inf".parse::<MathToken>().is_err());
+    }
+    #[test]
+    fn test_apply_promotion() {
+        assert_eq!(
+            OperatorType::Div.apply(Value::Int(8), Value::Int(2)),
+            Ok(Value::Int(4))
+        );
+        assert_eq!(
+            OperatorType::Div.apply(Value::Int(7), Value::Int(2)),
+            Ok(Value::Float(3.5))
+        );
+        assert_eq!(
+            OperatorType::Add.apply(Value::Float(1.5), Value::Int(2)),
+            Ok(Value::Float(3.5))
+        );
+        assert_eq!(
+            OperatorType::Pow.apply(Value::Int(2), Value::Int(-1)),
+            Ok(Value::Float(0.5))
+        );
+    }
+    #[test]
+    fn test_operator_associativity() {
+        assert_eq!(OperatorType::Sub.associativity(), Associativity::Left);
+        assert_eq!(OperatorType::Pow.associativity(), Associativity::Right);
+        assert!(OperatorType::Pow > OperatorType::Neg);
+        assert!(OperatorType::Neg > OperatorType::Mul);
+    }
+    #[test]
+    fn test_apply_unary() {
+        assert_eq!(
+            OperatorType::Neg.apply_unary(Value::Int(3)).unwrap(),
+            Value::Int(-3)
+        );
+        assert_eq!(
+            OperatorType::Pos.apply_unary(Value::Float(1.5)).unwrap(),
+            Value::Float(1.5)
+        );
+    }
+    #[test]
+    fn test_apply_errors() {
+        assert!(matches!(
+            OperatorType::Div.apply(Value::Int(1), Value::Int(0)),
+            Err(EvalError::DivisionByZero)
+        ));
+        assert!(matches!(
+            OperatorType::Div.apply(Value::Float(1.0), Value::Int(0)),
+            Err(EvalError::DivisionByZero)
+        ));
+        assert!(matches!(
+            OperatorType::Mul.apply(Value::Int(isize::MAX), Value::Int(2)),
+            Err(EvalError::Overflow)
+        ));
+        assert!(matches!(
+            OperatorType::Pow.apply(Value::Int(2), Value::Int(1 << 40)),
+            Err(EvalError::InvalidExponent(_))
+        ));
+        assert!(matches!(
+            OperatorType::Sub.apply(Value::Float(f64::INFINITY), Value::Float(f64::INFINITY)),
+            Err(EvalError::Domain(_))
+        ));
+        assert!(matches!(
+            OperatorType::Pow.apply(Value::Int(0), Value::Int(-1)),
+            Err(EvalError::DivisionByZero)
+        ));
+        assert!(matches!(
+            OperatorType::Neg.apply_unary(Value::Int(isize::MIN)),
+            Err(EvalError::Overflow)
+        ));
+        assert!(matches!(
+            OperatorType::Div.apply(Value::Int(isize::MIN), Value::Int(-1)),
+            Err(EvalError::Overflow)
+        ));
+        assert!(matches!(
+            OperatorType::Rem.apply(Value::Int(isize::MIN), Value::Int(-1)),
+            Err(EvalError::Overflow)
+        ));
+    }
+    #[test]
+    fn test_apply_bitwise() {
+        let apply = |op: OperatorType, lhs, rhs| op.apply(Value::Int(lhs), Value::Int(rhs));
+        assert_eq!(
+            apply(OperatorType::BitAnd, 0b1100, 0b1010),
+            Ok(Value::Int(0b1000))
+        );
+        assert_eq!(
+            apply(OperatorType::BitOr, 0b1100, 0b1010),
+            Ok(Value::Int(0b1110))
+        );
+        assert_eq!(
+            apply(OperatorType::Xor, 0b1100, 0b1010),
+            Ok(Value::Int(0b0110))
+        );
+        assert_eq!(apply(OperatorType::Shl, 3, 4), Ok(Value::Int(48)));
+        assert_eq!(apply(OperatorType::Shr, -8, 1), Ok(Value::Int(-4)));
+        assert_eq!(apply(OperatorType::BitAnd, -1, 0xff), Ok(Value::Int(0xff)));
+        assert_eq!(apply(OperatorType::Shl, 1, 63), Err(EvalError::Overflow));
+        assert_eq!(
+            apply(OperatorType::Shr, 1, -1),
+            Err(EvalError::InvalidShift("1 >> -1".into()))
+        );
+        assert_eq!(
+            OperatorType::BitOr.apply(Value::Float(4.0), Value::Int(1)),
+            Ok(Value::Int(5))
+        );
+        assert_eq!(
+            OperatorType::BitOr.apply(Value::Float(1.5), Value::Int(1)),
+            Err(EvalError::NotInteger("1.5 | 1".into()))
+        );
+        assert_eq!(
+            OperatorType::BitNot.apply_unary(Value::Int(0)),
+            Ok(Value::Int(-1))
+        );
+        let big = Value::Rational(BigRational::from_integer(BigInt::from(u64::MAX)));
+        assert_eq!(
+            OperatorType::Shr.apply(big, Value::Int(60)),
+            Ok(Value::Rational(BigRational::from_integer(15.into())))
+        );
+    }
+    #[test]
+    fn test_apply_comparison() {
+        let third = Value::Rational(BigRational::new(1.into(), 3.into()));
+        let half = Value::Rational(BigRational::new(1.into(), 2.into()));
+        assert_eq!(
+            OperatorType::Lt.apply(third.clone(), half),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            OperatorType::Ge.apply(Value::Int(2), Value::Float(2.5)),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            OperatorType::Eq.apply(Value::Float(f64::NAN), Value::Float(f64::NAN)),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            OperatorType::Ne.apply(Value::Float(f64::NAN), Value::Float(f64::NAN)),
+            Ok(Value::Int(1))
+        );
+        let i = Value::Complex(Complex64::i());
+        assert_eq!(
+            OperatorType::Eq.apply(i.clone(), i.clone()),
+            Ok(Value::Int(1))
+        );
+        assert!(matches!(
+            OperatorType::Gt.apply(i, Value::Int(0)),
+            Err(EvalError::Domain(_))
+        ));
+        assert_eq!(
+            OperatorType::And.apply(third, Value::Float(0.0)),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            OperatorType::Not.apply_unary(Value::Float(0.5)),
+            Ok(Value::Int(0))
+        );
+    }
+    #[test]
+    fn test_apply_rem() {
+        assert_eq!(
+            OperatorType::Rem.apply(Value::Int(-7), Value::Int(3)),
+            Ok(Value::Int(-1))
+        );
+        assert_eq!(
+            OperatorType::Rem.apply(Value::Float(7.5), Value::Int(2)),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            OperatorType::Rem.apply(
+                Value::Rational(BigRational::new(7.into(), 2.into())),
+                Value::Int(1)
+            ),
+            Ok(Value::Rational(BigRational::new(1.into(), 2.into())))
+        );
+        assert_eq!(
+            OperatorType::Rem.apply(Value::Int(1), Value::Int(0)),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+    #[test]
+    fn test_bitwise_precedence() {
+        assert!(OperatorType::Add > OperatorType::Shl);
+        assert!(OperatorType::Shr > OperatorType::BitAnd);
+        assert!(OperatorType::BitAnd > OperatorType::Xor);
+        assert!(OperatorType::Xor > OperatorType::BitOr);
+        assert_eq!(
+            OperatorType::Rem.precedence(),
+            OperatorType::Mul.precedence()
+        );
+        assert!(OperatorType::BitNot.is_unary());
+        assert!(OperatorType::BitOr > OperatorType::Eq);
+        assert!(OperatorType::Eq > OperatorType::And);
+        assert!(OperatorType::And > OperatorType::Or);
+        assert!(OperatorType::Or > OperatorType::Cond);
+        assert_eq!("xor".parse::<OperatorType>().unwrap(), OperatorType::Xor);
+    }
+    #[test]
+    fn test_apply_complex() {
+        let complex = |re, im| Value::Complex(Complex64::new(re, im));
+        assert_eq!(
+            OperatorType::Mul.apply(complex(1.0, 2.0), complex(3.0, -1.0)),
+            Ok(complex(5.0, 5.0))
+        );
+        assert_eq!(
+            OperatorType::Pow.apply(complex(1.0, 2.0), Value::Int(2)),
+            Ok(complex(-3.0, 4.0))
+        );
+        assert_eq!(
+            OperatorType::Pow.apply(Value::Int(-4), Value::Float(0.5)),
+            Ok(complex(0.0, 2.0))
+        );
+        // the imaginary parts cancel out
+        assert_eq!(
+            OperatorType::Mul.apply(complex(1.0, 2.0), complex(1.0, -2.0)),
+            Ok(Value::Float(5.0))
+        );
+        // exact operations keep a part that is small next to the other
+        assert_eq!(
+            OperatorType::Add.apply(Value::Float(1e16), complex(0.0, 1.0)),
+            Ok(complex(1e16, 1.0))
+        );
+        assert_eq!(
+            OperatorType::Sub.apply(complex(1e16, 1.0), Value::Float(1e16)),
+            Ok(complex(0.0, 1.0))
+        );
+        assert_eq!(
+            OperatorType::Pow.apply(
+                Value::Float(std::f64::consts::E),
+                complex(0.0, std::f64::consts::PI)
+            ),
+            Ok(Value::Float(-1.0))
+        );
+        assert_eq!(
+            OperatorType::Div.apply(complex(1.0, 2.0), Value::Int(0)),
+            Err(EvalError::DivisionByZero)
+        );
+        assert_eq!(
+            OperatorType::Neg.apply_unary(complex(1.0, -2.0)),
+            Ok(complex(-1.0, 2.0))
+        );
+    }
+    #[test]
+    fn test_mathtoken_display() {
+        assert_eq!(MathToken::IntOperand(3).to_string(), "3");
+        assert_eq!(MathToken::FloatOperand(0.5).to_string(), "0.5");
+        assert_eq!(MathToken::Operator(OperatorType::Pow).to_string(), "^");
+        assert_eq!(MathToken::Operator(OperatorType::Neg).to_string(), "neg");
+        assert_eq!(MathToken::Call("sqrt".into(), 1).to_string(), "sqrt");
+        assert_eq!(MathToken::Call("max".into(), 3).to_string(), "max(3)");
+    }
+    #[test]
     fn test_fail_mathtoke_from_str() {
         assert!("--".parse::<MathToken>().is_err());
     }
@@ -198,13 +1093,11 @@ mod tests {
         assert!(input.parse::<Expression>().is_ok());
         assert_eq!(
             input.parse::<Expression>().unwrap(),
-            Expression {
-                tokens: vec![
-                    MathToken::IntOperand(3),
-                    MathToken::Operator(OperatorType::Add),
-                    MathToken::IntOperand(4),
-                ]
-            }
+            Expression::new(vec![
+                MathToken::IntOperand(3),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(4),
+            ])
         );
     }
     #[test]
@@ -224,6 +1117,58 @@ mod tests {
         );
     }
     #[test]
+    fn test_expression_float() {
+        let input = "1.5 + 2";
+        assert_eq!(
+            input.parse::<Expression>().unwrap(),
+            Expression::new(vec![
+                MathToken::FloatOperand(1.5),
+                MathToken::Operator(OperatorType::Add),
+                MathToken::IntOperand(2)
+            ])
+        );
+    }
+    #[test]
+    fn test_expression_no_whitespace() {
+        assert_eq!(
+            "2*(3-1)".parse::<Expression>().unwrap(),
+            Expression::new(vec![
+                MathToken::IntOperand(2),
+                MathToken::Operator(OperatorType::Mul),
+                MathToken::Parens(true),
+                MathToken::IntOperand(3),
+                MathToken::Operator(OperatorType::Sub),
+                MathToken::IntOperand(1),
+                MathToken::Parens(false),
+            ])
+        );
+    }
+    #[test]
+    fn test_expression_assignment() {
+        assert_eq!(
+            "rate = 0.07".parse::<Expression>().unwrap(),
+            Expression::new(vec![
+                MathToken::Identifier("rate".into()),
+                MathToken::Assign,
+                MathToken::FloatOperand(0.07),
+            ])
+        );
+    }
+    #[test]
+    fn test_expression_function() {
+        assert_eq!(
+            "log(100, 10)".parse::<Expression>().unwrap(),
+            Expression::new(vec![
+                MathToken::Function("log".into()),
+                MathToken::Parens(true),
+                MathToken::IntOperand(100),
+                MathToken::Comma,
+                MathToken::IntOperand(10),
+                MathToken::Parens(false),
+            ])
+        );
+    }
+    #[test]
     fn test_expression_power() {
         let input = "3 ^ 4";
         assert_eq!(
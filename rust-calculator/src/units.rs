@@ -0,0 +1,364 @@
+//! Physical units, from a table built into the crate: the SI units with their prefixes and
+//! common units outside the SI, e.g. `km`, `mi`, `h` and `kWh` written as `kW h`.
+use crate::value::Value;
+use std::fmt;
+
+/// Exponents of the SI base quantities: length, mass, time, electric current, temperature,
+/// amount of substance and luminous intensity.
+pub type Dimension = [i32; 7];
+
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+const SPEED: Dimension = [1, 0, -1, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+const DIMENSIONLESS: Dimension = [0; 7];
+
+/// A unit of the table, its size in SI base units and whether it takes SI prefixes.
+struct UnitDef {
+    name: &'static str,
+    scale: f64,
+    dimension: Dimension,
+    prefixes: bool,
+}
+
+const fn def(name: &'static str, scale: f64, dimension: Dimension, prefixes: bool) -> UnitDef {
+    UnitDef {
+        name,
+        scale,
+        dimension,
+        prefixes,
+    }
+}
+
+/// The known units, the kilogram is a prefixed gram.
+static UNITS: [UnitDef; 38] = [
+    def("m", 1.0, LENGTH, true),
+    def("g", 1e-3, MASS, true),
+    def("s", 1.0, TIME, true),
+    def("A", 1.0, [0, 0, 0, 1, 0, 0, 0], true),
+    def("K", 1.0, [0, 0, 0, 0, 1, 0, 0], true),
+    def("mol", 1.0, [0, 0, 0, 0, 0, 1, 0], true),
+    def("cd", 1.0, [0, 0, 0, 0, 0, 0, 1], true),
+    def("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0], true),
+    def("N", 1.0, [1, 1, -2, 0, 0, 0, 0], true),
+    def("Pa", 1.0, PRESSURE, true),
+    def("J", 1.0, ENERGY, true),
+    def("W", 1.0, [2, 1, -3, 0, 0, 0, 0], true),
+    def("C", 1.0, [0, 0, 1, 1, 0, 0, 0], true),
+    def("V", 1.0, [2, 1, -3, -1, 0, 0, 0], true),
+    def("ohm", 1.0, [2, 1, -3, -2, 0, 0, 0], true),
+    def("F", 1.0, [-2, -1, 4, 2, 0, 0, 0], true),
+    def("T", 1.0, [0, 1, -2, -1, 0, 0, 0], true),
+    def("L", 1e-3, VOLUME, true),
+    def("eV", 1.602_176_634e-19, ENERGY, true),
+    def("bar", 1e5, PRESSURE, true),
+    def("rad", 1.0, DIMENSIONLESS, false),
+    def("deg", std::f64::consts::PI / 180.0, DIMENSIONLESS, false),
+    def("min", 60.0, TIME, false),
+    def("h", 3600.0, TIME, false),
+    def("day", 86_400.0, TIME, false),
+    def("week", 604_800.0, TIME, false),
+    // the Julian year of astronomy, 365.25 days
+    def("yr", 31_557_600.0, TIME, false),
+    def("in", 0.0254, LENGTH, false),
+    def("ft", 0.3048, LENGTH, false),
+    def("yd", 0.9144, LENGTH, false),
+    def("mi", 1609.344, LENGTH, false),
+    def("ly", 9_460_730_472_580_800.0, LENGTH, false),
+    def("lb", 0.453_592_37, MASS, false),
+    def("oz", 0.028_349_523_125, MASS, false),
+    def("gal", 3.785_411_784e-3, VOLUME, false),
+    def("mph", 0.447_04, SPEED, false),
+    def("atm", 101_325.0, PRESSURE, false),
+    def("cal", 4.184, ENERGY, true),
+];
+
+/// SI prefixes and the power of ten they stand for, `u` is accepted for `µ`.
+static PREFIXES: [(&str, f64); 21] = [
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("µ", 1e-6),
+    ("u", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+/// One named unit of a `Unit` raised to a power, e.g. `s^-2`.
+#[derive(Debug, Clone, PartialEq)]
+struct Factor {
+    name: String,
+    power: i32,
+    scale: f64,
+    dimension: Dimension,
+}
+
+/// A product of named units raised to whole powers, e.g. `km/h` is `km * h^-1`.
+/// The empty product is the unit of plain numbers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Unit {
+    factors: Vec<Factor>,
+}
+
+impl Unit {
+    /// The unit called `name`, either exactly as in the table or as a prefix and a unit that
+    /// takes prefixes, so `min` is minutes rather than milli-inches.
+    #[must_use]
+    pub fn named(name: &str) -> Option<Self> {
+        let exact = UNITS
+            .iter()
+            .find(|def| def.name == name)
+            .map(|def| (def.scale, def));
+        let (scale, def) = exact.or_else(|| {
+            PREFIXES.iter().find_map(|(prefix, factor)| {
+                let unit = name.strip_prefix(prefix)?;
+                let def = UNITS.iter().find(|def| def.prefixes && def.name == unit)?;
+                Some((factor * def.scale, def))
+            })
+        })?;
+        Some(Self {
+            factors: vec![Factor {
+                name: name.to_string(),
+                power: 1,
+                scale,
+                dimension: def.dimension,
+            }],
+        })
+    }
+
+    /// Plain numbers have no unit.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.factors.is_empty()
+    }
+
+    /// Size of the unit in SI base units, e.g. `1000` for `km`.
+    /// Dividing once at the end keeps round sizes like `0.44704` for `mi/h` exact.
+    #[must_use]
+    pub fn scale(&self) -> f64 {
+        let (mut above, mut below) = (1.0, 1.0);
+        for factor in &self.factors {
+            let size = factor.scale.powi(factor.power.abs());
+            if factor.power > 0 {
+                above *= size;
+            } else {
+                below *= size;
+            }
+        }
+        above / below
+    }
+
+    #[must_use]
+    pub fn dimension(&self) -> Dimension {
+        let mut dimension = DIMENSIONLESS;
+        for factor in &self.factors {
+            for (total, exponent) in dimension.iter_mut().zip(factor.dimension) {
+                *total += exponent * factor.power;
+            }
+        }
+        dimension
+    }
+
+    /// Whether the units cancel out, like in `m/km`.
+    #[must_use]
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension() == DIMENSIONLESS
+    }
+
+    /// The unit of a product, or of a quotient with `power` `-1`.
+    /// Powers of the same named unit add up and disappear when they cancel.
+    #[must_use]
+    pub fn times(&self, other: &Self, power: i32) -> Self {
+        let mut factors = self.factors.clone();
+        for factor in &other.factors {
+            let power = factor.power * power;
+            match factors.iter_mut().find(|mine| mine.name == factor.name) {
+                Some(mine) => mine.power += power,
+                None => factors.push(Factor {
+                    power,
+                    ..factor.clone()
+                }),
+            }
+        }
+        factors.retain(|factor| factor.power != 0);
+        Self { factors }
+    }
+
+    /// The unit raised to `power`, unless that leaves a fractional power of a named unit,
+    /// e.g. `m^2` can be raised to `0.5` but `m` can't.
+    #[allow(clippy::cast_possible_truncation)]
+    #[must_use]
+    pub fn pow(&self, power: f64) -> Option<Self> {
+        let factors = self
+            .factors
+            .iter()
+            .map(|factor| {
+                let raised = f64::from(factor.power) * power;
+                (raised.fract() == 0.0 && raised.abs() <= f64::from(i16::MAX)).then(|| Factor {
+                    power: raised as i32,
+                    ..factor.clone()
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            factors: factors.into_iter().filter(|f| f.power != 0).collect(),
+        })
+    }
+}
+
+/// Written the way it can be typed back in, e.g. `m/s^2`, `kg*m^2` or `J/(kg*K)`.
+/// Units with nothing above the line use negative powers, e.g. `s^-1`.
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let product = |factors: &[(&str, i32)]| {
+            factors
+                .iter()
+                .map(|(name, power)| match power {
+                    1 => (*name).to_string(),
+                    _ => format!("{name}^{power}"),
+                })
+                .collect::<Vec<_>>()
+                .join("*")
+        };
+        let (above, below): (Vec<_>, Vec<_>) = self
+            .factors
+            .iter()
+            .map(|factor| (factor.name.as_str(), factor.power))
+            .partition(|(_, power)| *power > 0);
+        let below: Vec<_> = below
+            .into_iter()
+            .map(|(name, power)| (name, -power))
+            .collect();
+        match below.len() {
+            0 => write!(f, "{}", product(&above)),
+            _ if above.is_empty() => {
+                let negative: Vec<_> = below.iter().map(|(name, power)| (*name, -power)).collect();
+                write!(f, "{}", product(&negative))
+            }
+            1 => write!(f, "{}/{}", product(&above), product(&below)),
+            _ => write!(f, "{}/({})", product(&above), product(&below)),
+        }
+    }
+}
+
+/// A number with a unit, e.g. `9.81 m/s^2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    #[must_use]
+    pub const fn new(value: f64, unit: Unit) -> Self {
+        Self { value, unit }
+    }
+
+    /// The number of `unit` this quantity amounts to, `unit` must have the same dimension.
+    /// Units both sides share cancel first, so `mi/h` to `km/h` only scales by `mi/km`.
+    #[must_use]
+    pub fn value_in(&self, unit: &Unit) -> f64 {
+        debug_assert_eq!(self.unit.dimension(), unit.dimension());
+        self.value * self.unit.times(unit, -1).scale()
+    }
+}
+
+/// Plain numbers are quantities without a unit.
+impl From<Quantity> for Value {
+    fn from(quantity: Quantity) -> Self {
+        if quantity.unit.is_empty() {
+            Self::Float(quantity.value)
+        } else {
+            Self::Quantity(quantity)
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+/// One of the unit called `name`, if there is such a unit.
+#[must_use]
+pub fn quantity(name: &str) -> Option<Value> {
+    Unit::named(name).map(|unit| Value::Quantity(Quantity::new(1.0, unit)))
+}
+
+#[must_use]
+pub fn is_unit(name: &str) -> bool {
+    Unit::named(name).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(name: &str) -> Unit {
+        Unit::named(name).unwrap()
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_named() {
+        assert_eq!(unit("km").scale(), 1000.0);
+        assert_eq!(unit("kg").scale(), 1.0);
+        assert_eq!(unit("min").scale(), 60.0);
+        assert_eq!(unit("ms").dimension(), TIME);
+        assert_eq!(unit("µs").scale(), 1e-6);
+        assert_eq!(unit("kW").dimension(), unit("W").dimension());
+        assert!(Unit::named("kmin").is_none());
+        assert!(Unit::named("x").is_none());
+        assert!(Unit::named("kin").is_none());
+    }
+    #[test]
+    fn test_combine() {
+        let speed = unit("m").times(&unit("s"), -1);
+        assert_eq!(speed.dimension(), SPEED);
+        assert_eq!(speed.to_string(), "m/s");
+        let acceleration = speed.times(&unit("s"), -1);
+        assert_eq!(acceleration.to_string(), "m/s^2");
+        assert_eq!(acceleration.times(&unit("s"), 1), speed);
+        assert!(unit("m").times(&unit("km"), -1).is_dimensionless());
+        assert!(unit("m").times(&unit("m"), -1).is_empty());
+        let heat = unit("J").times(&unit("kg"), -1).times(&unit("K"), -1);
+        assert_eq!(heat.to_string(), "J/(kg*K)");
+        assert_eq!(Unit::default().times(&unit("s"), -1).to_string(), "s^-1");
+    }
+    #[test]
+    fn test_pow() {
+        let area = unit("m").pow(2.0).unwrap();
+        assert_eq!(area.to_string(), "m^2");
+        assert_eq!(area.pow(0.5), Some(unit("m")));
+        assert_eq!(unit("m").pow(0.5), None);
+        assert!(unit("m").pow(0.0).unwrap().is_empty());
+    }
+    #[test]
+    fn test_value_in() {
+        let speed = Quantity::new(60.0, unit("mi").times(&unit("h"), -1));
+        let kmh = unit("km").times(&unit("h"), -1);
+        assert!((speed.value_in(&kmh) - 96.560_64).abs() < 1e-9);
+        assert_eq!(
+            Value::from(Quantity::new(2.0, Unit::default())),
+            Value::Float(2.0)
+        );
+    }
+}
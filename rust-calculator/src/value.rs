@@ -0,0 +1,762 @@
+use crate::postfix::EvalError;
+use crate::tokens::{MathEquationErr, OperatorType};
+use crate::units::{Quantity, Unit};
+use clap::ValueEnum;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+use std::{fmt, str::FromStr};
+
+/// Result of evaluating part of an expression.
+///
+/// Integers stay exact for as long as the operations on them allow it,
+/// anything that can't be represented as an integer is promoted to a float.
+/// Rationals only appear in `--exact` mode, where they replace both.
+/// Complex numbers always have a non zero imaginary part, see `From<Complex64>`.
+/// Quantities are floats with a unit, one whose units cancel out is a float again.
+/// Lists hold any values, operators apply to them element by element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(isize),
+    Float(f64),
+    Rational(BigRational),
+    Complex(Complex64),
+    Quantity(Quantity),
+    List(Vec<Self>),
+}
+
+impl Value {
+    /// Widen this value to a float, regardless of its current type.
+    /// Complex numbers, quantities and lists have no float value and give `NaN`.
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn as_float(&self) -> f64 {
+        match self {
+            Self::Int(i) => *i as f64,
+            Self::Float(f) => *f,
+            Self::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Self::Complex(_) | Self::Quantity(_) | Self::List(_) => f64::NAN,
+        }
+    }
+
+    /// This value as a quantity, without a unit unless it is one. Complex numbers have none.
+    #[must_use]
+    pub fn to_quantity(&self) -> Option<Quantity> {
+        match self {
+            Self::Quantity(q) => Some(q.clone()),
+            Self::Complex(_) | Self::List(_) => None,
+            real => Some(Quantity::new(real.as_float(), Unit::default())),
+        }
+    }
+
+    /// Widen this value to a complex number, regardless of its current type.
+    #[must_use]
+    pub fn as_complex(&self) -> Complex64 {
+        match self {
+            Self::Complex(c) => *c,
+            real => Complex64::from(real.as_float()),
+        }
+    }
+
+    #[must_use]
+    pub const fn is_complex(&self) -> bool {
+        matches!(self, Self::Complex(_))
+    }
+
+    #[must_use]
+    pub const fn is_quantity(&self) -> bool {
+        matches!(self, Self::Quantity(_))
+    }
+
+    #[must_use]
+    pub const fn is_list(&self) -> bool {
+        matches!(self, Self::List(_))
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Self::Float(f) => f.is_nan(),
+            Self::Complex(c) => c.is_nan(),
+            Self::Quantity(q) => q.value.is_nan(),
+            Self::List(items) => items.iter().any(Self::is_nan),
+            Self::Int(_) | Self::Rational(_) => false,
+        }
+    }
+
+    /// Zero is false in conditions, anything else is true. Empty lists are false too.
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Int(i) => *i == 0,
+            Self::Float(f) => *f == 0.0,
+            Self::Rational(r) => r.is_zero(),
+            Self::Complex(c) => c.is_zero(),
+            Self::Quantity(q) => q.value == 0.0,
+            Self::List(items) => items.is_empty(),
+        }
+    }
+
+    /// The exact value, if this isn't a float.
+    #[must_use]
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Self::Int(i) => Some(BigRational::from_integer((*i).into())),
+            Self::Float(_) | Self::Complex(_) | Self::Quantity(_) | Self::List(_) => None,
+            Self::Rational(r) => Some(r.clone()),
+        }
+    }
+
+    /// The value as an integer of any size, if it is a whole number.
+    #[must_use]
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Self::Int(i) => Some((*i).into()),
+            Self::Float(f) if f.fract() == 0.0 => BigInt::from_f64(*f),
+            Self::Float(_) | Self::Complex(_) | Self::Quantity(_) | Self::List(_) => None,
+            Self::Rational(r) => r.is_integer().then(|| r.to_integer()),
+        }
+    }
+
+    /// The value as a machine integer, if it is a whole number that fits.
+    #[must_use]
+    pub fn as_integer(&self) -> Option<isize> {
+        match self {
+            Self::Int(i) => Some(*i),
+            Self::Float(_) | Self::Complex(_) | Self::Quantity(_) | Self::List(_) => None,
+            Self::Rational(r) => r.is_integer().then(|| r.to_integer().to_isize())?,
+        }
+    }
+}
+
+/// Complex numbers without an imaginary part are floats.
+impl From<Complex64> for Value {
+    fn from(c: Complex64) -> Self {
+        if c.im == 0.0 {
+            Self::Float(c.re)
+        } else {
+            Self::Complex(c)
+        }
+    }
+}
+
+impl Value {
+    /// The result of a transcendental function, cleaned of rounding noise so `e ^ (i * pi)`
+    /// is `-1` rather than `-1 + 0.00000000000000012246i`. A part is noise when it is within
+    /// a few ulps of zero relative to the other part. Exact operations like `+` keep both
+    /// parts, `1e16 + i` isn't `1e16`.
+    #[must_use]
+    pub fn transcendental(c: Complex64) -> Self {
+        let noise = |part: f64, other: f64| part.abs() <= 4.0 * f64::EPSILON * other.abs();
+        let re = if noise(c.re, c.im) { 0.0 } else { c.re };
+        let im = if noise(c.im, c.re) { 0.0 } else { c.im };
+        Self::from(Complex64::new(re, im))
+    }
+}
+
+/// Parse a decimal like `-12.5` into the exact fraction it spells, `-25/2`.
+fn parse_decimal(s: &str) -> Option<BigRational> {
+    let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+    let numer: BigInt = format!("{whole}{frac}").parse().ok()?;
+    let denom = BigInt::from(10u8).pow(u32::try_from(frac.len()).ok()?);
+    Some(BigRational::new(numer, denom))
+}
+
+/// Parse a whole number, in decimal or after a `0x`, `0b` or `0o` prefix.
+/// Digits after a prefix can be grouped with `_`, e.g. `0b1111_0000`.
+fn parse_integer(s: &str) -> Option<BigInt> {
+    let negative = s.starts_with('-');
+    let unsigned = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x" | "0X") => (16, unsigned[2..].replace('_', "")),
+        Some("0b" | "0B") => (2, unsigned[2..].replace('_', "")),
+        Some("0o" | "0O") => (8, unsigned[2..].replace('_', "")),
+        _ => (10, unsigned.to_string()),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let n = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+    Some(if negative { -n } else { n })
+}
+
+/// Integer types for `--width`, named like Rust's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Width {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+}
+
+impl Width {
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        match self {
+            Self::I8 | Self::U8 => 8,
+            Self::I16 | Self::U16 => 16,
+            Self::I32 | Self::U32 => 32,
+            Self::I64 | Self::U64 => 64,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_signed(self) -> bool {
+        matches!(self, Self::I8 | Self::I16 | Self::I32 | Self::I64)
+    }
+
+    /// Smallest and largest value of the type.
+    fn range(self) -> (BigInt, BigInt) {
+        let bits = self.bits();
+        if self.is_signed() {
+            let half = BigInt::one() << (bits - 1);
+            (-half.clone(), half - 1)
+        } else {
+            (BigInt::zero(), (BigInt::one() << bits) - 1)
+        }
+    }
+
+    /// Bring `n` into range by dropping the bits that don't fit, like two's complement hardware.
+    fn wrap(self, n: &BigInt) -> BigInt {
+        let modulus = BigInt::one() << self.bits();
+        let wrapped = n.mod_floor(&modulus);
+        if self.is_signed() && wrapped.bits() == u64::from(self.bits()) {
+            wrapped - modulus
+        } else {
+            wrapped
+        }
+    }
+}
+
+/// How numbers are represented while evaluating.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Machine integers that are promoted to floats when needed.
+    #[default]
+    Standard,
+    /// Arbitrary precision rationals, anything that can't be exact is an error.
+    Exact,
+    /// Integers of a fixed size, fractional results are truncated towards zero.
+    /// Results that don't fit wrap around, or are an error unless `wrapping` is set.
+    Fixed { width: Width, wrapping: bool },
+}
+
+/// A result of standard arithmetic, elementwise for lists. Whole rationals are integers and
+/// other rationals floats. Only `bitwise` results may be whole numbers beyond `isize`, other
+/// results that large overflow like integer arithmetic does.
+fn standard(value: Value, bitwise: bool) -> Result<Value, EvalError> {
+    match value {
+        Value::Rational(r) if r.is_integer() => match r.to_integer().to_isize() {
+            Some(i) => Ok(Value::Int(i)),
+            None if bitwise => Ok(Value::Rational(r)),
+            None => Err(EvalError::Overflow),
+        },
+        Value::Rational(r) => Ok(Value::Float(Value::Rational(r).as_float())),
+        Value::List(items) => items
+            .into_iter()
+            .map(|item| standard(item, bitwise))
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        value => Ok(value),
+    }
+}
+
+impl Arithmetic {
+    /// Convert a number typed by the user, or given with `--var`, to this arithmetic.
+    /// In exact mode `0.1` means exactly one tenth.
+    /// Whole numbers too large for an `isize` are rationals, they stay exact so bitwise operators
+    /// see every bit, e.g. `0xFFFFFFFFFFFFFFFF & 0xFF` is `255`, see `fit` for other operators.
+    /// Fixed width literals must be whole and are checked like results, but may be one past
+    /// the largest value so that the smallest can be written, e.g. `-128` for `i8`.
+    ///
+    /// # Errors
+    /// When the literal doesn't fit, see `check`.
+    pub fn literal(self, value: Value) -> Result<Value, EvalError> {
+        let what = value.to_string();
+        let converted = match (self, &value) {
+            (Self::Exact | Self::Fixed { .. }, &Value::Int(i)) => {
+                Value::Rational(BigRational::from_integer(i.into()))
+            }
+            // floats print as the shortest decimal that reads back the same, usually what was typed
+            (Self::Exact | Self::Fixed { .. }, Value::Float(f)) => {
+                parse_decimal(&f.to_string()).map_or(value, Value::Rational)
+            }
+            _ => value,
+        };
+        match self {
+            Self::Fixed { .. } if !converted.is_list() && converted.to_bigint().is_none() => {
+                Err(EvalError::NotInteger(what))
+            }
+            Self::Fixed {
+                width,
+                wrapping: false,
+            } if converted.to_bigint() == Some(-width.range().0) => Ok(converted),
+            _ => self.check(converted, || what),
+        }
+    }
+
+    /// Make sure the result of `op` fits this arithmetic, e.g. the `1` of a true comparison.
+    /// `&`, `|`, `xor` and `~` always wrap around, they work on the bits rather than the number,
+    /// while shifts that push bits out of the width are an error like other operators.
+    /// Standard arithmetic approximates fractions and reports whole results beyond `isize`
+    /// as an overflow, unless `op` is bitwise.
+    ///
+    /// # Errors
+    /// When the result doesn't fit, see `check`.
+    pub fn fit(self, value: Value, op: &OperatorType) -> Result<Value, EvalError> {
+        match self {
+            Self::Fixed { width, wrapping } => Self::Fixed {
+                width,
+                wrapping: wrapping
+                    || matches!(
+                        op,
+                        OperatorType::BitAnd
+                            | OperatorType::BitOr
+                            | OperatorType::Xor
+                            | OperatorType::BitNot
+                    ),
+            }
+            .check(value, || op.to_string()),
+            Self::Exact => self.check(value, || op.to_string()),
+            Self::Standard => standard(value, op.is_bitwise()),
+        }
+    }
+
+    /// Check every element of a list, `what` describing the whole list.
+    fn check_items(self, items: Vec<Value>, what: &str) -> Result<Value, EvalError> {
+        items
+            .into_iter()
+            .map(|item| self.check(item, || what.to_string()))
+            .collect::<Result<_, _>>()
+            .map(Value::List)
+    }
+
+    /// Make sure a value computed by a function or constant fits this arithmetic.
+    /// Exact mode accepts whole numbers, e.g. `sqrt(16)`, anything else is reported as `what`.
+    /// Fixed width mode truncates towards zero and checks the range.
+    /// Every element of a list is checked.
+    ///
+    /// # Errors
+    /// When exact mode gets a value that isn't exact, or a fixed width is out of range
+    /// without wrapping or gets a complex number or quantity.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn check(self, value: Value, what: impl FnOnce() -> String) -> Result<Value, EvalError> {
+        // beyond this floats can't tell whether they hold a whole number
+        const MAX_EXACT_FLOAT: f64 = (1u64 << f64::MANTISSA_DIGITS) as f64;
+        match (self, value) {
+            (Self::Exact | Self::Fixed { .. }, Value::List(items)) => {
+                self.check_items(items, &what())
+            }
+            (Self::Exact, Value::Int(i)) => {
+                Ok(Value::Rational(BigRational::from_integer(i.into())))
+            }
+            (Self::Exact, Value::Float(f)) if f.fract() == 0.0 && f.abs() <= MAX_EXACT_FLOAT => {
+                BigRational::from_float(f)
+                    .map(Value::Rational)
+                    .ok_or_else(|| EvalError::Inexact(what()))
+            }
+            // quantities are floats
+            (Self::Exact, Value::Float(_) | Value::Complex(_) | Value::Quantity(_)) => {
+                Err(EvalError::Inexact(what()))
+            }
+            (Self::Fixed { width, wrapping }, value) => {
+                let whole = match value {
+                    Value::Int(i) => BigInt::from(i),
+                    Value::Rational(r) => r.trunc().to_integer(),
+                    Value::Float(f) => BigRational::from_float(f)
+                        .ok_or(EvalError::Overflow)?
+                        .trunc()
+                        .to_integer(),
+                    Value::Complex(_) => return Err(EvalError::Domain(what())),
+                    Value::Quantity(_) => return Err(EvalError::IntegerUnits(what())),
+                    Value::List(_) => unreachable!("Lists are checked element by element"),
+                };
+                let (min, max) = width.range();
+                if whole >= min && whole <= max {
+                    Ok(Value::Rational(BigRational::from_integer(whole)))
+                } else if wrapping {
+                    Ok(Value::Rational(BigRational::from_integer(
+                        width.wrap(&whole),
+                    )))
+                } else if whole < min {
+                    Err(EvalError::Underflow)
+                } else {
+                    Err(EvalError::Overflow)
+                }
+            }
+            (_, value) => Ok(value),
+        }
+    }
+}
+
+/// Bases whole numbers can be printed in, with the prefix of their literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Base {
+    Hex,
+    Bin,
+    Oct,
+}
+
+impl Base {
+    const fn radix(self) -> u32 {
+        match self {
+            Self::Hex => 16,
+            Self::Bin => 2,
+            Self::Oct => 8,
+        }
+    }
+
+    const fn prefix(self) -> &'static str {
+        match self {
+            Self::Hex => "0x",
+            Self::Bin => "0b",
+            Self::Oct => "0o",
+        }
+    }
+
+    /// Write `n` in this base, negative numbers as their two's complement in `bits` if given.
+    fn show(self, n: &BigInt, twos_complement: Option<u32>) -> String {
+        let n = match twos_complement {
+            Some(bits) if n.is_negative() => n.mod_floor(&(BigInt::one() << bits)),
+            _ => n.clone(),
+        };
+        let sign = if n.is_negative() { "-" } else { "" };
+        format!(
+            "{sign}{}{}",
+            self.prefix(),
+            n.magnitude().to_str_radix(self.radix())
+        )
+    }
+}
+
+/// How results are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// Also show exact results as a decimal with this many digits after the point.
+    pub digits: Option<usize>,
+    /// Show complex numbers as `r * e^(θi)` instead of `a + bi`.
+    pub polar: bool,
+    /// Show whole numbers in this base instead of decimal.
+    pub base: Option<Base>,
+    /// With `base`, show negative numbers as their two's complement in this many bits.
+    pub twos_complement: Option<u32>,
+}
+
+impl Format {
+    /// Lists are shown with every element formatted.
+    #[must_use]
+    pub fn show(&self, value: &Value) -> String {
+        if let Value::List(items) = value {
+            let items: Vec<_> = items.iter().map(|item| self.show(item)).collect();
+            return format!("[{}]", items.join(", "));
+        }
+        if let (Some(base), Some(n)) = (self.base, value.to_bigint()) {
+            return base.show(&n, self.twos_complement);
+        }
+        match (value, self.digits) {
+            (Value::Complex(c), _) if self.polar => {
+                let (r, theta) = c.to_polar();
+                format!("{r} * e^({})", imaginary(theta))
+            }
+            (Value::Rational(r), Some(digits)) if !r.is_integer() => {
+                let (expansion, exact) = decimal(r, digits);
+                format!("{value} {} {expansion}", if exact { "=" } else { "≈" })
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+/// Decimal expansion of `r` rounded to `digits` after the point, and whether it is exact.
+/// Exact expansions drop their trailing zeros.
+fn decimal(r: &BigRational, digits: usize) -> (String, bool) {
+    let scale = BigRational::from_integer(
+        BigInt::from(10u8).pow(u32::try_from(digits).unwrap_or(u32::MAX)),
+    );
+    let scaled = r.abs() * scale;
+    let exact = scaled.is_integer();
+    let mut text = scaled.round().to_integer().to_string();
+    if digits > 0 {
+        if text.len() <= digits {
+            text = format!("{}{text}", "0".repeat(digits + 1 - text.len()));
+        }
+        text.insert(text.len() - digits, '.');
+    }
+    if exact && digits > 0 {
+        text.truncate(text.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    if r.is_negative() && !scaled.round().is_zero() {
+        text.insert(0, '-');
+    }
+    (text, exact)
+}
+
+impl From<isize> for Value {
+    fn from(value: isize) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl FromStr for Value {
+    type Err = MathEquationErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(n) = parse_integer(s) {
+            return Ok(n
+                .to_isize()
+                .map_or_else(|| Self::Rational(BigRational::from_integer(n)), Self::Int));
+        }
+        // `f64::from_str` also accepts words like `inf` and `NaN`, only allow numeric looking input.
+        let unsigned = s.strip_prefix(['-', '+']).unwrap_or(s);
+        if unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            if let Ok(f) = s.parse::<f64>() {
+                return Ok(Self::Float(f));
+            }
+        }
+        Err(MathEquationErr::InvalidOperand(s.to_string()))
+    }
+}
+
+/// Write an imaginary number like `2i`, with `i` and `-i` for plus and minus one.
+fn imaginary(im: f64) -> String {
+    match im {
+        1.0 => "i".to_string(),
+        -1.0 => "-i".to_string(),
+        _ => format!("{im}i"),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Float(x) => write!(f, "{x}"),
+            // shows `1/2`, or just `3` for whole numbers
+            Self::Rational(r) => write!(f, "{r}"),
+            Self::Complex(c) if c.re == 0.0 => write!(f, "{}", imaginary(c.im)),
+            Self::Complex(c) if c.im < 0.0 => write!(f, "{} - {}", c.re, imaginary(-c.im)),
+            Self::Complex(c) => write!(f, "{} + {}", c.re, imaginary(c.im)),
+            Self::Quantity(q) => write!(f, "{q}"),
+            Self::List(items) => {
+                let items: Vec<_> = items.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_float() {
+        assert!((Value::Int(3).as_float() - 3.0).abs() < f64::EPSILON);
+        assert!((Value::Float(2.5).as_float() - 2.5).abs() < f64::EPSILON);
+    }
+    #[test]
+    fn test_from_str() {
+        assert_eq!("42".parse::<Value>().unwrap(), Value::Int(42));
+        assert_eq!("-1.5".parse::<Value>().unwrap(), Value::Float(-1.5));
+        assert!("NaN".parse::<Value>().is_err());
+        assert!("x".parse::<Value>().is_err());
+    }
+    #[test]
+    fn test_from_str_radix() {
+        assert_eq!("0xff".parse::<Value>().unwrap(), Value::Int(255));
+        assert_eq!("-0b1000_0000".parse::<Value>().unwrap(), Value::Int(-128));
+        assert_eq!("0o17".parse::<Value>().unwrap(), Value::Int(15));
+        assert_eq!(
+            "0xffff_ffff_ffff_ffff".parse::<Value>().unwrap(),
+            Value::Rational(BigRational::from_integer(u64::MAX.into()))
+        );
+        assert!("0x".parse::<Value>().is_err());
+        assert!("0b12".parse::<Value>().is_err());
+        assert!("1_000".parse::<Value>().is_err());
+    }
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Int(-4).to_string(), "-4");
+        assert_eq!(Value::Float(3.5).to_string(), "3.5");
+        let half = BigRational::new(1.into(), 2.into());
+        assert_eq!(Value::Rational(half).to_string(), "1/2");
+        let three = BigRational::from_integer(3.into());
+        assert_eq!(Value::Rational(three).to_string(), "3");
+    }
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn test_complex() {
+        let value = |re: f64, im: f64| Value::from(Complex64::new(re, im));
+        assert_eq!(value(1.0, 2.0).to_string(), "1 + 2i");
+        assert_eq!(value(0.5, -1.0).to_string(), "0.5 - i");
+        assert_eq!(value(0.0, -3.0).to_string(), "-3i");
+        assert_eq!(value(2.0, 0.0), Value::Float(2.0));
+        assert_eq!(value(1e16, 1.0).to_string(), "10000000000000000 + i");
+        let transcendental = |re: f64, im: f64| Value::transcendental(Complex64::new(re, im));
+        // rounding noise, the real and imaginary parts of e^(i * pi)
+        assert_eq!(transcendental(-1.0, 1.2246e-16), Value::Float(-1.0));
+        assert_eq!(transcendental(6.1232e-17, 1.0).to_string(), "i");
+        assert_eq!(
+            transcendental(0.0, 1e-20).to_string(),
+            "0.00000000000000000001i"
+        );
+    }
+    #[test]
+    fn test_polar_format() {
+        let format = Format {
+            polar: true,
+            ..Format::default()
+        };
+        assert_eq!(
+            format.show(&Value::Complex(Complex64::new(0.0, 2.0))),
+            format!("2 * e^({}i)", std::f64::consts::FRAC_PI_2)
+        );
+        assert_eq!(format.show(&Value::Float(-1.0)), "-1");
+    }
+    #[test]
+    fn test_exact_literal() {
+        let exact = Arithmetic::Exact;
+        assert_eq!(
+            exact.literal(Value::Float(0.1)),
+            Ok(Value::Rational(ratio(1, 10)))
+        );
+        assert_eq!(
+            exact.literal(Value::Float(-12.5)),
+            Ok(Value::Rational(ratio(-25, 2)))
+        );
+        assert_eq!(
+            exact.literal(Value::Int(7)),
+            Ok(Value::Rational(ratio(7, 1)))
+        );
+        assert_eq!(
+            Arithmetic::Standard.literal(Value::Float(0.1)),
+            Ok(Value::Float(0.1))
+        );
+        let big = Value::Rational(BigRational::from_integer(0x8000_0000_0000_0001_u64.into()));
+        assert_eq!(Arithmetic::Standard.literal(big.clone()), Ok(big));
+    }
+    #[test]
+    fn test_exact_check() {
+        let exact = Arithmetic::Exact;
+        assert_eq!(
+            exact.check(Value::Float(4.0), String::new),
+            Ok(Value::Rational(ratio(4, 1)))
+        );
+        assert_eq!(
+            exact.check(Value::Float(1.5), || "f(3)".into()),
+            Err(EvalError::Inexact("f(3)".into()))
+        );
+        assert_eq!(
+            Arithmetic::Standard.check(Value::Float(1.5), String::new),
+            Ok(Value::Float(1.5))
+        );
+        let meter = crate::units::quantity("m").unwrap();
+        assert_eq!(
+            exact.check(meter, || "m".into()),
+            Err(EvalError::Inexact("m".into()))
+        );
+    }
+    #[test]
+    fn test_width_wrap() {
+        assert_eq!(Width::I8.wrap(&128.into()), BigInt::from(-128));
+        assert_eq!(Width::I8.wrap(&(-129).into()), BigInt::from(127));
+        assert_eq!(Width::U16.wrap(&(-1).into()), BigInt::from(0xffff));
+        assert_eq!(Width::U8.wrap(&256.into()), BigInt::zero());
+        assert_eq!(Width::I32.range().0, BigInt::from(i32::MIN));
+        assert_eq!(Width::U64.range().1, BigInt::from(u64::MAX));
+    }
+    #[test]
+    fn test_fixed_check() {
+        let checked = Arithmetic::Fixed {
+            width: Width::I8,
+            wrapping: false,
+        };
+        let wrapping = Arithmetic::Fixed {
+            width: Width::I8,
+            wrapping: true,
+        };
+        let int = |n: i64| Value::Rational(ratio(n, 1));
+        assert_eq!(checked.check(Value::Float(-2.7), String::new), Ok(int(-2)));
+        assert_eq!(
+            checked.check(Value::Rational(ratio(7, 2)), String::new),
+            Ok(int(3))
+        );
+        assert_eq!(
+            checked.check(Value::Int(128), String::new),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(wrapping.check(Value::Int(128), String::new), Ok(int(-128)));
+        assert_eq!(checked.literal(Value::Int(128)), Ok(int(128)));
+        assert_eq!(checked.literal(Value::Int(129)), Err(EvalError::Overflow));
+        assert_eq!(checked.fit(int(-129), &OperatorType::BitNot), Ok(int(127)));
+        assert_eq!(
+            checked.fit(int(-129), &OperatorType::Sub),
+            Err(EvalError::Underflow)
+        );
+        assert_eq!(
+            checked.fit(int(256), &OperatorType::Shl),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(
+            checked.literal(Value::Float(1.5)),
+            Err(EvalError::NotInteger("1.5".into()))
+        );
+    }
+    #[test]
+    fn test_base_format() {
+        let hex = Format {
+            base: Some(Base::Hex),
+            ..Format::default()
+        };
+        assert_eq!(hex.show(&Value::Int(255)), "0xff");
+        assert_eq!(hex.show(&Value::Int(-255)), "-0xff");
+        assert_eq!(hex.show(&Value::Float(2.5)), "2.5");
+        let twos = Format {
+            base: Some(Base::Bin),
+            twos_complement: Some(8),
+            ..Format::default()
+        };
+        assert_eq!(twos.show(&Value::Int(-2)), "0b11111110");
+        assert_eq!(twos.show(&Value::Int(5)), "0b101");
+        let oct = Format {
+            base: Some(Base::Oct),
+            ..Format::default()
+        };
+        assert_eq!(oct.show(&Value::Rational(ratio(8, 1))), "0o10");
+    }
+    #[test]
+    fn test_decimal_expansion() {
+        assert_eq!(decimal(&ratio(1, 3), 5), ("0.33333".into(), false));
+        assert_eq!(decimal(&ratio(2, 3), 3), ("0.667".into(), false));
+        assert_eq!(decimal(&ratio(1, 8), 5), ("0.125".into(), true));
+        assert_eq!(decimal(&ratio(-7, 2), 2), ("-3.5".into(), true));
+        assert_eq!(decimal(&ratio(2, 3), 0), ("1".into(), false));
+        assert_eq!(decimal(&ratio(-1, 1000), 2), ("0.00".into(), false));
+    }
+    #[test]
+    fn test_format() {
+        let format = Format {
+            digits: Some(4),
+            ..Format::default()
+        };
+        assert_eq!(format.show(&Value::Rational(ratio(1, 3))), "1/3 ≈ 0.3333");
+        assert_eq!(format.show(&Value::Rational(ratio(1, 4))), "1/4 = 0.25");
+        assert_eq!(format.show(&Value::Int(2)), "2");
+        assert_eq!(Format::default().show(&Value::Rational(ratio(1, 3))), "1/3");
+    }
+}